@@ -0,0 +1,236 @@
+//! The compression backends, which are selected at compile time using cargo features.
+//!
+//! Every backend produces a standard zlib stream, lz4 block, or lz4 frame, so the choice of backend affects speed and build requirements, but never which games can read the output.
+
+#[cfg(not(any(feature = "lzzzz", feature = "lz4_flex")))]
+compile_error!(
+    "an lz4 backend is required, so enable either the `lzzzz` or the `lz4_flex` feature"
+);
+
+/// An error raised by the pure rust lz4 backend.
+#[cfg(feature = "lz4_flex")]
+#[derive(thiserror::Error, Debug)]
+#[error(transparent)]
+pub struct Lz4Error(Box<dyn core::error::Error + Send + Sync>);
+
+#[cfg(feature = "lz4_flex")]
+impl Lz4Error {
+    #[cfg_attr(feature = "lzzzz", allow(dead_code))]
+    fn new<E>(error: E) -> Self
+    where
+        E: core::error::Error + Send + Sync + 'static,
+    {
+        Self(Box::new(error))
+    }
+}
+
+pub(crate) mod lz4 {
+    #[cfg(not(feature = "lzzzz"))]
+    use super::Lz4Error;
+    #[cfg(feature = "lzzzz")]
+    use lzzzz::lz4f::Error as Lz4Error;
+
+    pub(crate) const MAX_LEVEL: u32 = 12;
+
+    type Result<T> = core::result::Result<T, Lz4Error>;
+
+    /// Appends `input` to `out`, as a raw lz4 block.
+    ///
+    /// The pure rust backend does not support compression levels, and ignores `level`.
+    #[cfg(feature = "lzzzz")]
+    pub(crate) fn compress_block(input: &[u8], out: &mut Vec<u8>, level: u32) -> Result<()> {
+        lzzzz::lz4_hc::compress_to_vec(input, out, clamp(level))?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "lzzzz"))]
+    pub(crate) fn compress_block(input: &[u8], out: &mut Vec<u8>, _level: u32) -> Result<()> {
+        use lz4_flex::block;
+        let start = out.len();
+        out.resize(start + block::get_maximum_output_size(input.len()), 0);
+        let len = block::compress_into(input, &mut out[start..]).map_err(Lz4Error::new)?;
+        out.truncate(start + len);
+        Ok(())
+    }
+
+    /// Appends `input` to `out`, as an lz4 frame.
+    ///
+    /// The pure rust backend does not support compression levels, and ignores `level`.
+    #[cfg(feature = "lzzzz")]
+    pub(crate) fn compress_frame(input: &[u8], out: &mut Vec<u8>, level: u32) -> Result<()> {
+        use lzzzz::lz4f::{self, AutoFlush, PreferencesBuilder};
+        let prefs = PreferencesBuilder::new()
+            .compression_level(clamp(level))
+            .auto_flush(AutoFlush::Enabled)
+            .build();
+        lz4f::compress_to_vec(input, out, &prefs)?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "lzzzz"))]
+    pub(crate) fn compress_frame(input: &[u8], out: &mut Vec<u8>, _level: u32) -> Result<()> {
+        use lz4_flex::frame::FrameEncoder;
+        use std::io::Write as _;
+        let mut e = FrameEncoder::new(out);
+        e.write_all(input).map_err(Lz4Error::new)?;
+        e.finish().map_err(Lz4Error::new)?;
+        Ok(())
+    }
+
+    /// Appends the raw lz4 block in `input` to `out`, which must decompress to at most `decompressed_len` bytes.
+    #[cfg(feature = "lzzzz")]
+    pub(crate) fn decompress_block(
+        input: &[u8],
+        out: &mut Vec<u8>,
+        decompressed_len: usize,
+    ) -> Result<usize> {
+        let start = out.len();
+        out.resize(start + decompressed_len, 0);
+        let len = lzzzz::lz4::decompress(input, &mut out[start..])?;
+        out.truncate(start + len);
+        Ok(len)
+    }
+
+    #[cfg(not(feature = "lzzzz"))]
+    pub(crate) fn decompress_block(
+        input: &[u8],
+        out: &mut Vec<u8>,
+        decompressed_len: usize,
+    ) -> Result<usize> {
+        use lz4_flex::block;
+        let start = out.len();
+        out.resize(start + decompressed_len, 0);
+        let len = block::decompress_into(input, &mut out[start..]).map_err(Lz4Error::new)?;
+        out.truncate(start + len);
+        Ok(len)
+    }
+
+    /// Appends the lz4 frame in `input` to `out`.
+    #[cfg(feature = "lzzzz")]
+    pub(crate) fn decompress_frame(input: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+        lzzzz::lz4f::decompress_to_vec(input, out)
+    }
+
+    #[cfg(not(feature = "lzzzz"))]
+    pub(crate) fn decompress_frame(input: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+        use lz4_flex::frame::FrameDecoder;
+        use std::io::Read as _;
+        FrameDecoder::new(input)
+            .read_to_end(out)
+            .map_err(Lz4Error::new)
+    }
+
+    /// Clamps `level` to `1..=MAX_LEVEL`, since liblz4 treats 0 as its default level, rather than its fastest.
+    #[cfg(feature = "lzzzz")]
+    #[allow(clippy::cast_possible_wrap)]
+    fn clamp(level: u32) -> i32 {
+        level.clamp(1, MAX_LEVEL) as i32
+    }
+}
+
+pub(crate) mod zlib {
+    use flate2::{write::ZlibEncoder, Compress, Compression};
+    use std::io::{self, Write as _};
+
+    pub(crate) const MAX_WINDOW_BITS: u8 = 15;
+
+    /// Appends `input` to `out`, as a zlib stream.
+    ///
+    /// Only the c backends can restrict the size of the window, so the pure rust backend only accepts [`MAX_WINDOW_BITS`]. When enabled, libdeflate is used whenever the window is not restricted.
+    pub(crate) fn compress(
+        input: &[u8],
+        out: &mut Vec<u8>,
+        level: Compression,
+        window_bits: u8,
+    ) -> io::Result<()> {
+        #[cfg(feature = "libdeflate")]
+        if window_bits == MAX_WINDOW_BITS && level.level() > 0 {
+            return compress_libdeflate(input, out, level);
+        }
+
+        #[cfg(feature = "zlib")]
+        let compress = Compress::new_with_window_bits(level, true, window_bits);
+        #[cfg(not(feature = "zlib"))]
+        let compress = {
+            debug_assert!(supports_window_bits(window_bits));
+            Compress::new(level, true)
+        };
+
+        let mut e = ZlibEncoder::new_with_compress(out, compress);
+        e.write_all(input)?;
+        e.finish()?;
+        Ok(())
+    }
+
+    /// Appends the zlib stream in `input` to `out`, which is expected to decompress to `decompressed_len` bytes.
+    ///
+    /// Returns the actual decompressed length, which the caller must compare against `decompressed_len`, so that a mismatch is reported the same way by every backend.
+    #[cfg(feature = "libdeflate")]
+    pub(crate) fn decompress(
+        input: &[u8],
+        out: &mut Vec<u8>,
+        decompressed_len: usize,
+    ) -> io::Result<usize> {
+        use libdeflater::{DecompressionError, Decompressor};
+        let start = out.len();
+        out.resize(start + decompressed_len, 0);
+        match Decompressor::new().zlib_decompress(input, &mut out[start..]) {
+            Ok(len) => {
+                out.truncate(start + len);
+                Ok(len)
+            }
+            // the stream is longer than expected, so fall back to a streaming decoder to find its actual length
+            Err(DecompressionError::InsufficientSpace) => {
+                out.truncate(start);
+                decompress_flate2(input, out)
+            }
+            Err(err) => {
+                out.truncate(start);
+                Err(io::Error::new(io::ErrorKind::InvalidData, err))
+            }
+        }
+    }
+
+    #[cfg(not(feature = "libdeflate"))]
+    pub(crate) fn decompress(
+        input: &[u8],
+        out: &mut Vec<u8>,
+        _decompressed_len: usize,
+    ) -> io::Result<usize> {
+        decompress_flate2(input, out)
+    }
+
+    /// Whether the backend can compress using a window of the given size.
+    #[must_use]
+    pub(crate) fn supports_window_bits(window_bits: u8) -> bool {
+        cfg!(feature = "zlib") || window_bits == MAX_WINDOW_BITS
+    }
+
+    fn decompress_flate2(input: &[u8], out: &mut Vec<u8>) -> io::Result<usize> {
+        use flate2::write::ZlibDecoder;
+        let mut d = ZlibDecoder::new(out);
+        d.write_all(input)?;
+        d.total_out()
+            .try_into()
+            .map_err(|x| io::Error::new(io::ErrorKind::InvalidData, x))
+    }
+
+    #[cfg(feature = "libdeflate")]
+    fn compress_libdeflate(input: &[u8], out: &mut Vec<u8>, level: Compression) -> io::Result<()> {
+        use libdeflater::{CompressionLvl, Compressor};
+        let level = level
+            .level()
+            .try_into()
+            .ok()
+            .and_then(|level| CompressionLvl::new(level).ok())
+            .unwrap_or_default();
+        let mut compressor = Compressor::new(level);
+        let start = out.len();
+        out.resize(start + compressor.zlib_compress_bound(input.len()), 0);
+        let len = compressor
+            .zlib_compress(input, &mut out[start..])
+            .map_err(io::Error::other)?;
+        out.truncate(start + len);
+        Ok(())
+    }
+}
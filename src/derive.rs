@@ -20,6 +20,16 @@ macro_rules! reader {
             }
         }
 
+        impl<'file> crate::Reader<crate::Buffered<'file>> for $this<'static> {
+            type Error = Error;
+            type Item = $result<$this<'static>>;
+
+            fn read(source: crate::Buffered<'file>) -> Result<Self::Item> {
+                let mut source = crate::io::BufferedSource::try_from(source.0)?;
+                Self::do_read(&mut source)
+            }
+        }
+
         impl crate::Reader<&::std::fs::File> for $this<'static> {
             type Error = Error;
             type Item = $result<$this<'static>>;
@@ -44,6 +54,52 @@ macro_rules! reader {
 
 pub(crate) use reader;
 
+macro_rules! reader_with_layout {
+    ($this:ident => $result:ident) => {
+        impl<'bytes> crate::ReaderWithLayout<crate::Borrowed<'bytes>> for $this<'bytes> {
+            type Error = Error;
+            type Item = $result<$this<'bytes>>;
+
+            fn read_with_layout(source: crate::Borrowed<'bytes>) -> Result<Self::Item> {
+                let mut source = crate::io::BorrowedSource::from(source.0);
+                Self::do_read_with_layout(&mut source)
+            }
+        }
+
+        impl<'bytes> crate::ReaderWithLayout<crate::Copied<'bytes>> for $this<'static> {
+            type Error = Error;
+            type Item = $result<$this<'static>>;
+
+            fn read_with_layout(source: crate::Copied<'bytes>) -> Result<Self::Item> {
+                let mut source = crate::io::CopiedSource::from(source.0);
+                Self::do_read_with_layout(&mut source)
+            }
+        }
+
+        impl crate::ReaderWithLayout<&::std::fs::File> for $this<'static> {
+            type Error = Error;
+            type Item = $result<$this<'static>>;
+
+            fn read_with_layout(source: &::std::fs::File) -> Result<Self::Item> {
+                let mut source = crate::io::MappedSource::try_from(source)?;
+                Self::do_read_with_layout(&mut source)
+            }
+        }
+
+        impl crate::ReaderWithLayout<&::std::path::Path> for $this<'static> {
+            type Error = Error;
+            type Item = $result<$this<'static>>;
+
+            fn read_with_layout(source: &::std::path::Path) -> Result<Self::Item> {
+                let fd = ::std::fs::File::open(source)?;
+                Self::read_with_layout(&fd)
+            }
+        }
+    };
+}
+
+pub(crate) use reader_with_layout;
+
 macro_rules! reader_with_options {
     ($this:ident: $options:ident) => {
         impl<'bytes> crate::ReaderWithOptions<crate::Borrowed<'bytes>> for $this<'bytes> {
@@ -185,6 +241,29 @@ macro_rules! compressable_bytes {
             }
         }
 
+        #[cfg(feature = "async")]
+        impl $this<'static> {
+            /// See also [`compress`](Self::compress).
+            ///
+            /// The compression is offloaded to a blocking task, and so must be awaited within a tokio runtime.
+            pub async fn compress_async(self, options: &$options) -> Result<$this<'static>> {
+                let options = *options;
+                ::tokio::task::spawn_blocking(move || self.compress(&options))
+                    .await
+                    .map_err(::std::io::Error::from)?
+            }
+
+            /// See also [`decompress`](Self::decompress).
+            ///
+            /// The decompression is offloaded to a blocking task, and so must be awaited within a tokio runtime.
+            pub async fn decompress_async(self, options: &$options) -> Result<$this<'static>> {
+                let options = *options;
+                ::tokio::task::spawn_blocking(move || self.decompress(&options))
+                    .await
+                    .map_err(::std::io::Error::from)?
+            }
+        }
+
         #[allow(clippy::needless_update)]
         impl<'bytes, const N: usize> crate::CompressableFrom<&'bytes [u8; N]> for $this<'bytes> {
             fn from_compressed(value: &'bytes [u8; N], decompressed_len: usize) -> Self {
@@ -249,10 +328,37 @@ macro_rules! key {
                 &self.hash
             }
 
+            #[must_use]
+            pub fn into_owned(self) -> $this<'static> {
+                $this {
+                    hash: self.hash,
+                    name: self.name.into_owned(),
+                }
+            }
+
             #[must_use]
             pub fn name(&self) -> &::bstr::BStr {
                 ::bstr::BStr::new(self.name.as_bytes())
             }
+
+            /// Decodes the name of the key into a unicode string, using the given encoding.
+            #[cfg(feature = "encoding")]
+            pub fn decode_name(
+                &self,
+                encoding: crate::encoding::Encoding,
+            ) -> crate::encoding::Result<::std::borrow::Cow<'_, str>> {
+                encoding.decode(self.name.as_bytes())
+            }
+
+            /// Makes a key from a unicode name, by encoding it using the given encoding.
+            #[cfg(feature = "encoding")]
+            pub fn encode_name(
+                name: &str,
+                encoding: crate::encoding::Encoding,
+            ) -> crate::encoding::Result<$this<'static>> {
+                let name = encoding.encode(name)?;
+                Ok(name.into_owned().into())
+            }
         }
 
         // false positive
@@ -306,6 +412,44 @@ macro_rules! key {
                 }
             }
         }
+
+        /// Keys are serialized as their hash, and their name as a byte string.
+        #[cfg(feature = "serde")]
+        impl<'bytes> ::serde::Serialize for $this<'bytes> {
+            fn serialize<S>(&self, serializer: S) -> ::core::result::Result<S::Ok, S::Error>
+            where
+                S: ::serde::Serializer,
+            {
+                use ::serde::ser::SerializeStruct as _;
+                let mut state = serializer.serialize_struct(stringify!($this), 2)?;
+                state.serialize_field("hash", &self.hash)?;
+                state.serialize_field("name", self.name())?;
+                state.end()
+            }
+        }
+
+        /// The hash is deserialized as is, and is not recomputed from the name.
+        #[cfg(feature = "serde")]
+        impl<'de> ::serde::Deserialize<'de> for $this<'static> {
+            fn deserialize<D>(deserializer: D) -> ::core::result::Result<Self, D::Error>
+            where
+                D: ::serde::Deserializer<'de>,
+            {
+                #[derive(::serde::Deserialize)]
+                #[serde(rename = "Key")]
+                struct Repr {
+                    hash: $hash,
+                    name: ::bstr::BString,
+                }
+
+                let repr = Repr::deserialize(deserializer)?;
+                let v: Vec<u8> = repr.name.into();
+                Ok(Self {
+                    hash: repr.hash,
+                    name: crate::containers::Bytes::from_owned(v.into()),
+                })
+            }
+        }
     };
 }
 
@@ -493,6 +637,8 @@ macro_rules! hash {
     ($this:ident) => {
         /// See also [`struct@Hash`].
         #[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        #[cfg_attr(feature = "serde", serde(transparent))]
         #[repr(transparent)]
         pub struct $this(Hash);
 
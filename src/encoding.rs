@@ -0,0 +1,334 @@
+//! Conversions between the raw names stored within archives, and unicode strings.
+//!
+//! Names are stored using the system code page of whatever computer happened to write the archive (see the crate level docs). An [`Encoding`] pairs one of those code pages with a [`Mode`], which decides what happens to characters that can not be converted.
+//!
+//! * Lookups: every key can be made from a unicode name using `encode_name`, and anything which accepts a path as bytes (e.g. [`tes4::Archive::get_file`](crate::tes4::Archive::get_file), or [`Vfs::resolve`](crate::vfs::Vfs::resolve)) accepts the result of [`Encoding::encode`].
+//! * Packing: [`Encoding::encode_path`] converts the path of a file on disk, relative to the directory being packed, into a name, and [`Loose::read_with_encoding`](crate::vfs::Loose::read_with_encoding) indexes a whole directory in the same manner.
+//! * Extraction: every key can decode its name using `decode_name`, and [`Encoding::decode_path`] converts a name into a relative path on disk.
+//!
+//! ```rust
+//! use ba2::{
+//!     encoding::{CodePage, Encoding, Mode},
+//!     tes4::{Archive, ArchiveKey, File},
+//! };
+//! use std::path::Path;
+//!
+//! fn example() -> Option<()> {
+//!     let encoding = Encoding::new(CodePage::Windows1251, Mode::Strict);
+//!     let mut archive = Archive::new();
+//!     let name = encoding.encode_path(Path::new("textures/привет.dds")).ok()?;
+//!     archive.insert_file(&name, File::new());
+//!
+//!     let directory = ArchiveKey::encode_name("textures", encoding).ok()?;
+//!     let (directory, files) = archive.get_key_value(&directory)?;
+//!     for (file, _) in files {
+//!         let path = encoding.decode_path(file.name()).ok()?;
+//!         assert_eq!(path, Path::new("привет.dds"));
+//!     }
+//!     assert_eq!(directory.decode_name(encoding).ok()?, "textures");
+//!     Some(())
+//! }
+//! ```
+
+use bstr::BString;
+use encoding_rs::EncoderResult;
+use std::{
+    borrow::Cow,
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("name can not be decoded using {code_page:?}: {name}")]
+    Decode { code_page: CodePage, name: BString },
+
+    #[error("path can not be encoded using {code_page:?}: {path}")]
+    Encode { code_page: CodePage, path: String },
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// The legacy code pages which the games were localized into.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CodePage {
+    /// Thai.
+    Windows874,
+
+    /// Central and Eastern European, i.e. Polish, Czech, and Hungarian.
+    Windows1250,
+
+    /// Cyrillic, i.e. Russian and Ukrainian.
+    Windows1251,
+
+    /// Western European, i.e. English, French, German, Italian, and Spanish.
+    #[default]
+    Windows1252,
+
+    /// Greek.
+    Windows1253,
+
+    /// Turkish.
+    Windows1254,
+
+    /// Hebrew.
+    Windows1255,
+
+    /// Arabic.
+    Windows1256,
+
+    /// Baltic.
+    Windows1257,
+
+    /// Vietnamese.
+    Windows1258,
+
+    /// Japanese (code page 932).
+    ShiftJis,
+
+    /// Simplified Chinese (code page 936).
+    Gbk,
+
+    /// Korean (code page 949).
+    EucKr,
+
+    /// Traditional Chinese (code page 950).
+    Big5,
+}
+
+impl CodePage {
+    fn encoding(self) -> &'static encoding_rs::Encoding {
+        match self {
+            Self::Windows874 => encoding_rs::WINDOWS_874,
+            Self::Windows1250 => encoding_rs::WINDOWS_1250,
+            Self::Windows1251 => encoding_rs::WINDOWS_1251,
+            Self::Windows1252 => encoding_rs::WINDOWS_1252,
+            Self::Windows1253 => encoding_rs::WINDOWS_1253,
+            Self::Windows1254 => encoding_rs::WINDOWS_1254,
+            Self::Windows1255 => encoding_rs::WINDOWS_1255,
+            Self::Windows1256 => encoding_rs::WINDOWS_1256,
+            Self::Windows1257 => encoding_rs::WINDOWS_1257,
+            Self::Windows1258 => encoding_rs::WINDOWS_1258,
+            Self::ShiftJis => encoding_rs::SHIFT_JIS,
+            Self::Gbk => encoding_rs::GBK,
+            Self::EucKr => encoding_rs::EUC_KR,
+            Self::Big5 => encoding_rs::BIG5,
+        }
+    }
+}
+
+/// Decides what happens to characters which can not be converted.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Mode {
+    /// The conversion fails with an error.
+    #[default]
+    Strict,
+
+    /// The character is replaced, with `U+FFFD` when decoding, or with `?` when encoding, in the same manner as Windows.
+    Lossy,
+}
+
+/// A code page, paired with how to handle characters which can not be converted.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Encoding {
+    code_page: CodePage,
+    mode: Mode,
+}
+
+impl Encoding {
+    #[must_use]
+    pub fn new(code_page: CodePage, mode: Mode) -> Self {
+        Self { code_page, mode }
+    }
+
+    #[must_use]
+    pub fn code_page(&self) -> CodePage {
+        self.code_page
+    }
+
+    /// Decodes a name, as read from an archive, into a unicode string.
+    pub fn decode<'bytes>(&self, name: &'bytes [u8]) -> Result<Cow<'bytes, str>> {
+        let encoding = self.code_page.encoding();
+        match self.mode {
+            Mode::Strict => encoding
+                .decode_without_bom_handling_and_without_replacement(name)
+                .ok_or_else(|| Error::Decode {
+                    code_page: self.code_page,
+                    name: name.into(),
+                }),
+            Mode::Lossy => Ok(encoding.decode_without_bom_handling(name).0),
+        }
+    }
+
+    /// Decodes a name, as read from an archive, into a relative path on disk, i.e. for extracting the file it names.
+    ///
+    /// Both `\` and `/` are treated as separators. Empty, `.`, and `..` components are dropped, so the path never escapes the directory it is joined onto.
+    pub fn decode_path(&self, name: &[u8]) -> Result<PathBuf> {
+        let name = self.decode(name)?;
+        Ok(name
+            .split(['\\', '/'])
+            .filter(|x| !matches!(*x, "" | "." | ".."))
+            .collect())
+    }
+
+    /// Encodes a unicode path into a name, as it would be written to an archive.
+    pub fn encode<'string>(&self, path: &'string str) -> Result<Cow<'string, [u8]>> {
+        if path.is_ascii() {
+            return Ok(Cow::Borrowed(path.as_bytes()));
+        }
+
+        let mut encoder = self.code_page.encoding().new_encoder();
+        let mut result = Vec::with_capacity(
+            encoder
+                .max_buffer_length_from_utf8_without_replacement(path.len())
+                .unwrap_or(path.len()),
+        );
+        let mut src = path;
+        loop {
+            let (status, read) =
+                encoder.encode_from_utf8_to_vec_without_replacement(src, &mut result, true);
+            src = &src[read..];
+            match status {
+                EncoderResult::InputEmpty => break,
+                EncoderResult::OutputFull => result.reserve(src.len().max(1) * 4),
+                EncoderResult::Unmappable(_) => match self.mode {
+                    Mode::Strict => {
+                        return Err(Error::Encode {
+                            code_page: self.code_page,
+                            path: path.into(),
+                        })
+                    }
+                    Mode::Lossy => result.push(b'?'),
+                },
+            }
+        }
+
+        Ok(Cow::Owned(result))
+    }
+
+    /// Encodes a relative path on disk into a name, i.e. for packing the file it points to, using `\` as the separator.
+    ///
+    /// Root, prefix, and `.` components are dropped. Paths which are not valid unicode can not be encoded.
+    pub fn encode_path(&self, path: &Path) -> Result<BString> {
+        let mut result = BString::default();
+        for component in path.components() {
+            let component = match component {
+                Component::Normal(x) => x,
+                Component::ParentDir => "..".as_ref(),
+                Component::CurDir | Component::Prefix(_) | Component::RootDir => continue,
+            };
+            let component = component.to_str().ok_or_else(|| Error::Encode {
+                code_page: self.code_page,
+                path: path.to_string_lossy().into_owned(),
+            })?;
+            if !result.is_empty() {
+                result.push(b'\\');
+            }
+            result.extend_from_slice(&self.encode(component)?);
+        }
+        Ok(result)
+    }
+
+    #[must_use]
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        encoding::{CodePage, Encoding, Error, Mode},
+        fo4,
+        prelude::*,
+        tes3, tes4,
+    };
+    use bstr::ByteSlice as _;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn round_trip() -> anyhow::Result<()> {
+        for (code_page, path) in [
+            (CodePage::Windows1250, "textures\\zażółć gęślą jaźń.dds"),
+            (CodePage::Windows1251, "sound\\voice\\привет.fuz"),
+            (CodePage::Windows1252, "meshes\\café.nif"),
+            (CodePage::ShiftJis, "interface\\日本語.swf"),
+        ] {
+            let encoding = Encoding::new(code_page, Mode::Strict);
+            let name = encoding.encode(path)?;
+            assert!(!name.is_ascii());
+            assert_eq!(encoding.decode(&name)?, path);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn paths() -> anyhow::Result<()> {
+        let encoding = Encoding::new(CodePage::Windows1251, Mode::Strict);
+        let name = encoding.encode_path(Path::new("./sound/voice/привет.fuz"))?;
+        assert_eq!(
+            name,
+            b"sound\\voice\\\xEF\xF0\xE8\xE2\xE5\xF2.fuz".as_bstr()
+        );
+        assert_eq!(
+            encoding.decode_path(&name)?,
+            ["sound", "voice", "привет.fuz"].iter().collect::<PathBuf>()
+        );
+        assert_eq!(
+            encoding.decode_path(b"..\\textures//./sky.dds")?,
+            ["textures", "sky.dds"].iter().collect::<PathBuf>()
+        );
+        assert!(matches!(
+            Encoding::default().encode_path(Path::new("привет.fuz")),
+            Err(Error::Encode { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn lookups() -> anyhow::Result<()> {
+        let encoding = Encoding::new(CodePage::Windows1250, Mode::Strict);
+        let name = encoding.encode("textures\\źdźbło.dds")?;
+
+        let archive: fo4::Archive = [(
+            fo4::ArchiveKey::from(&*name),
+            [fo4::Chunk::from_decompressed(b"fo4")]
+                .into_iter()
+                .collect(),
+        )]
+        .into_iter()
+        .collect();
+        let key = fo4::ArchiveKey::encode_name("Textures/źdźbło.dds", encoding)?;
+        assert!(archive.get(&key).is_some());
+
+        let archive: tes3::Archive = [(tes3::ArchiveKey::from(&*name), tes3::File::from(b"tes3"))]
+            .into_iter()
+            .collect();
+        let key = tes3::ArchiveKey::encode_name("Textures/źdźbło.dds", encoding)?;
+        assert!(archive.get(&key).is_some());
+
+        let mut archive = tes4::Archive::new();
+        archive.insert_file(&*name, tes4::File::from_decompressed(b"tes4"));
+        let path = encoding.encode("Textures/źdźbło.dds")?;
+        assert!(archive.get_file(&*path).is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn strict_and_lossy() -> anyhow::Result<()> {
+        let strict = Encoding::new(CodePage::Windows1252, Mode::Strict);
+        let lossy = Encoding::new(CodePage::Windows1252, Mode::Lossy);
+        assert!(matches!(
+            strict.encode("привет.txt"),
+            Err(Error::Encode { .. })
+        ));
+        assert_eq!(&*lossy.encode("привет.txt")?, b"??????.txt");
+
+        let strict = Encoding::new(CodePage::ShiftJis, Mode::Strict);
+        let lossy = Encoding::new(CodePage::ShiftJis, Mode::Lossy);
+        assert!(matches!(strict.decode(b"\x82"), Err(Error::Decode { .. })));
+        assert_eq!(lossy.decode(b"\x82")?, "\u{FFFD}");
+        Ok(())
+    }
+}
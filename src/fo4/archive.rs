@@ -2,14 +2,23 @@ use crate::{
     containers::Bytes,
     derive,
     fo4::{
-        self, Chunk, CompressionFormat, DX10Header, Error, File, FileHash, FileHeader, Format,
-        GNMFHeader, Hash, Result, Version,
+        self, Chunk, ChunkCompressionOptions, CompressionFormat, DX10Header, Diff, DiffEntry,
+        Error, File, FileHash, FileHeader, Format, GNMFHeader, Hash, Result, Version,
     },
-    io::{Endian, Sink, Source},
+    io::{self, BufferedSource, Endian, Sink, Source},
+    layout::{Order, Plan, Region, Storage},
     protocols::WString,
+    CompressionPolicy, MergePolicy, Progress, QueryEntry, WrittenLen,
 };
-use bstr::BString;
-use std::io::Write;
+use bstr::{BStr, BString};
+use core::mem;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{BufWriter, Seek as _, SeekFrom, Write},
+};
+#[cfg(feature = "async")]
+use tokio::io::{AsyncWrite, AsyncWriteExt as _};
 
 mod constants {
     use crate::cc;
@@ -36,8 +45,8 @@ mod constants {
 }
 
 struct Offsets {
+    file_entries: usize,
     file_data: usize,
-    strings: usize,
 }
 
 impl Offsets {
@@ -61,14 +70,9 @@ impl Offsets {
                 + (chunks_count * chunk_size)
         };
 
-        let strings_offset = {
-            let data_size: usize = archive.values().flat_map(File::iter).map(Chunk::len).sum();
-            file_data_offset + data_size
-        };
-
         Self {
+            file_entries: chunks_offset,
             file_data: file_data_offset,
-            strings: strings_offset,
         }
     }
 }
@@ -79,6 +83,10 @@ struct Header {
     file_count: u32,
     string_table_offset: u64,
     compression_format: CompressionFormat,
+    /// The unknown field of v2 and v3 archives.
+    unknown: u64,
+    /// The compression format, as it was read.
+    compression_format_raw: u32,
 }
 
 /// See also [`ArchiveOptions`](Options).
@@ -156,6 +164,7 @@ impl OptionsBuilder {
 ///     .build();
 /// ```
 #[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Options {
     format: Format,
     version: Version,
@@ -199,6 +208,42 @@ impl Key<'_> {
     }
 }
 
+/// A blob of data which follows the file entries.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(super) enum Section {
+    Chunk(FileHash, usize),
+    Strings,
+}
+
+/// Captures the on-disk layout of an archive, as it was read.
+///
+/// A layout is obtained by reading an archive using [`ReaderWithLayout`](crate::ReaderWithLayout), and is used by [`Archive::write_with_layout`] to write the archive back out identically. The default layout is the one used by [`Archive::write`].
+#[derive(Clone, Debug)]
+pub struct Layout {
+    header_unknown: u64,
+    compression_format: Option<u32>,
+    files: Vec<(FileHash, u8)>,
+    data: Region<Section>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            header_unknown: 1,
+            compression_format: None,
+            files: Vec::new(),
+            data: Region::default(),
+        }
+    }
+}
+
+/// The layout of an archive which is in the middle of being read.
+#[derive(Default)]
+struct Capture {
+    layout: Layout,
+    data: Vec<(Section, usize, usize)>,
+}
+
 type ReadResult<T> = (T, Options);
 derive::archive! {
     /// Represents the FO4 revision of the ba2 format.
@@ -206,63 +251,553 @@ derive::archive! {
     Map: (Key: FileHash) => File
 }
 
+type LayoutResult<T> = (T, Options, Layout);
+derive::reader_with_layout!(Archive => LayoutResult);
+
 impl<'bytes> Archive<'bytes> {
+    /// Compresses every decompressed chunk in the archive whose file the given policy allows, using the given options.
+    ///
+    /// Chunks which are already compressed are left as they are. Every chunk retains its own compression state, so a chunk which the policy keeps decompressed is written out as is.
+    pub fn compress_files(
+        &mut self,
+        options: &ChunkCompressionOptions,
+        policy: &CompressionPolicy,
+    ) -> Result<()> {
+        self.compress_files_with_progress(options, policy, &mut Progress::default())
+    }
+
+    /// See also [`compress_files`](Self::compress_files).
+    ///
+    /// Every file is reported to `progress` once all of its chunks have been considered, whether or not any were compressed. If the operation is cancelled, then the chunks which were already compressed remain so.
+    pub fn compress_files_with_progress(
+        &mut self,
+        options: &ChunkCompressionOptions,
+        policy: &CompressionPolicy,
+        progress: &mut Progress,
+    ) -> Result<()> {
+        progress.start(
+            self.len(),
+            self.values().flat_map(File::iter).map(Chunk::len).sum(),
+        );
+        for (key, file) in self.iter_mut() {
+            if progress.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let len = file.iter().map(Chunk::len).sum();
+            if policy.allows(key.name()) {
+                for chunk in file.iter_mut() {
+                    if chunk.is_compressed() {
+                        continue;
+                    }
+
+                    let compressed = chunk.compress(options)?;
+                    if policy.keeps(compressed.len(), chunk.len()) {
+                        *chunk = compressed;
+                    }
+                }
+            }
+            progress.advance(key.name(), len);
+        }
+        Ok(())
+    }
+
+    /// Compares `self` (the old archive) against `other` (the new archive).
+    ///
+    /// Each archive must be paired with the options it was read with, so that its chunks can be decompressed for comparison.
+    pub fn diff(&self, options: &Options, other: &Self, other_options: &Options) -> Result<Diff> {
+        Diff::new((self, options), (other, other_options))
+    }
+
+    /// Finds the key of every file which satisfies the given predicate.
+    ///
+    /// The length of a file is the sum of the lengths of its chunks, and a file is considered compressed if any of its chunks are compressed. See also [`Glob`](crate::Glob).
+    pub fn find<'this, F>(&'this self, mut predicate: F) -> impl Iterator<Item = &'this Key<'bytes>>
+    where
+        F: FnMut(&QueryEntry) -> bool + 'this,
+    {
+        self.iter()
+            .filter(move |(key, file)| predicate(&Self::query_entry(key, file)))
+            .map(|(key, _)| key)
+    }
+
+    /// Makes a new archive from every file which satisfies the given predicate.
+    #[must_use]
+    pub fn select<F>(&self, mut predicate: F) -> Self
+    where
+        F: FnMut(&QueryEntry) -> bool,
+    {
+        self.iter()
+            .filter(|(key, file)| predicate(&Self::query_entry(key, file)))
+            .map(|(key, file)| (key.clone(), file.clone()))
+            .collect()
+    }
+
+    /// Moves a file into the given directory, keeping its file name.
+    ///
+    /// See also [`rename_file`](Self::rename_file).
+    pub fn move_file<P, D>(&mut self, path: &P, directory: &D) -> Result<()>
+    where
+        P: ?Sized + AsRef<[u8]>,
+        D: ?Sized + AsRef<[u8]>,
+    {
+        let path = path.as_ref();
+        let name = match path.iter().rposition(|&x| x == b'\\' || x == b'/') {
+            Some(pos) => &path[pos + 1..],
+            None => path,
+        };
+        let mut to = BString::from(directory.as_ref());
+        if !to.is_empty() {
+            to.push(b'\\');
+        }
+        to.extend_from_slice(name);
+        self.rename_file(path, &to)
+    }
+
+    /// Renames a file, using the full path of the file before and after.
+    ///
+    /// The file is moved as is, so data which is borrowed or memory-mapped from the source archive is never copied. Fails if no file exists at `from`, or if another file already exists at `to`, in which case the archive is left unchanged.
+    pub fn rename_file<P, Q>(&mut self, from: &P, to: &Q) -> Result<()>
+    where
+        P: ?Sized + AsRef<[u8]>,
+        Q: ?Sized + AsRef<[u8]>,
+    {
+        let from = Key::from(from.as_ref());
+        let to = Key::from(to.as_ref());
+        if !self.map.contains_key(from.hash()) {
+            return Err(Error::FileNotFound(from.name().into()));
+        }
+        if to.hash() != from.hash() && self.map.contains_key(to.hash()) {
+            return Err(Error::FileExists(to.name().into()));
+        }
+
+        if let Some(file) = self.map.remove(from.hash()) {
+            self.map.insert(to, file);
+        }
+        Ok(())
+    }
+
+    /// Merges several archives into one, resolving duplicate files using the given policy.
+    ///
+    /// See also [`merge_with`](Self::merge_with).
+    pub fn merge<I>(sources: I, policy: MergePolicy) -> Result<(Self, Options)>
+    where
+        I: IntoIterator<Item = (Self, Options)>,
+    {
+        Self::merge_with(sources, |key, existing, incoming| match policy {
+            MergePolicy::FirstWins => Ok(existing),
+            MergePolicy::LastWins => Ok(incoming),
+            MergePolicy::Error => Err(Error::MergeConflict(
+                DiffEntry::from(key).to_string().into(),
+            )),
+        })
+    }
+
+    /// Merges several archives into one, resolving duplicate files using the given callback.
+    ///
+    /// The callback is given the key of the duplicate file, the file merged so far, and the file from the next archive, and returns the file to keep.
+    ///
+    /// Every archive must share the same [`Format`], otherwise [`Error::FormatMismatch`] is returned. The merged archive uses the version and compression format of the first archive. Compressed chunks from an archive with a different compression format are decompressed, since their compression would not be valid in the merged archive.
+    pub fn merge_with<I, F>(sources: I, mut resolve: F) -> Result<(Self, Options)>
+    where
+        I: IntoIterator<Item = (Self, Options)>,
+        F: FnMut(&Key<'bytes>, File<'bytes>, File<'bytes>) -> Result<File<'bytes>>,
+    {
+        let mut merged = Self::new();
+        let mut merged_options: Option<Options> = None;
+        for (archive, options) in sources {
+            let merged_options = merged_options.get_or_insert(options);
+            if options.format != merged_options.format {
+                return Err(Error::FormatMismatch);
+            }
+            merged_options.strings |= options.strings;
+            let compression_options = (options.compression_format
+                != merged_options.compression_format)
+                .then(|| ChunkCompressionOptions::from(&options));
+
+            for (key, mut file) in archive {
+                if let Some(compression_options) = &compression_options {
+                    for chunk in &mut file {
+                        if chunk.is_compressed() {
+                            *chunk = chunk.decompress(compression_options)?;
+                        }
+                    }
+                }
+                let (key, file) = match merged.remove_entry(key.hash()) {
+                    Some((existing_key, existing)) => {
+                        let key = if existing_key.name().is_empty() {
+                            key
+                        } else {
+                            existing_key
+                        };
+                        let file = resolve(&key, existing, file)?;
+                        (key, file)
+                    }
+                    None => (key, file),
+                };
+                merged.insert(key, file);
+            }
+        }
+
+        Ok((merged, merged_options.unwrap_or_default()))
+    }
+
     pub fn write<Out>(&self, stream: &mut Out, options: &Options) -> Result<()>
     where
         Out: ?Sized + Write,
     {
-        let mut sink = Sink::new(stream);
-        let (header, mut offsets) = self.make_header(*options)?;
-        Self::write_header(&mut sink, &header)?;
+        self.write_with_layout(stream, options, &Layout::default())
+    }
+
+    /// See also [`write`](Self::write).
+    ///
+    /// The index is written first, followed by each chunk in turn, which is written directly from the archive, so nothing but the index and the string table is buffered, and the executor is yielded to between chunks.
+    #[cfg(feature = "async")]
+    pub async fn write_async<Out>(&self, stream: &mut Out, options: &Options) -> Result<()>
+    where
+        Out: ?Sized + AsyncWrite + Unpin,
+    {
+        let options = *options;
+        let layout = Layout::default();
+        let (files, _, data) = self.plan_write(options, &layout);
+        let mut buffer = Vec::new();
+        Self::write_index(&mut Sink::new(&mut buffer), options, &layout, &files, &data)?;
+        stream.write_all(&buffer).await?;
+        for slot in &data.slots {
+            stream.write_all(slot.padding).await?;
+            if let Some(chunk) = self.chunk(&slot.id) {
+                stream.write_all(chunk.as_bytes()).await?;
+            } else {
+                buffer.clear();
+                self.write_section(&mut Sink::new(&mut buffer), &files, slot.id)?;
+                stream.write_all(&buffer).await?;
+            }
+        }
+        stream.write_all(data.trailing).await?;
+        Ok(())
+    }
+
+    /// Writes the archive using the given options and layout.
+    ///
+    /// Files and chunks are written in the same order, and at the same positions, as they were when the layout was captured, along with any header fields that would otherwise be discarded, so writing an unmodified archive using the options it was read with reproduces the original byte-for-byte. Files and chunks which were added since are written after all others, and those which were removed are simply skipped.
+    pub fn write_with_layout<Out>(
+        &self,
+        stream: &mut Out,
+        options: &Options,
+        layout: &Layout,
+    ) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        self.do_write(stream, *options, layout, &mut Progress::default())
+    }
+
+    /// See also [`write`](Self::write).
+    ///
+    /// Every chunk is reported to `progress` once its data has been written, using the name of the file it belongs to, followed by the string table, if there is one, which is reported without a name. If the operation is cancelled, then the archive is left partially written.
+    pub fn write_with_progress<Out>(
+        &self,
+        stream: &mut Out,
+        options: &Options,
+        progress: &mut Progress,
+    ) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        self.do_write(stream, *options, &Layout::default(), progress)
+    }
 
-        for (key, file) in self {
-            Self::write_file(&mut sink, &header, &mut offsets, key.hash(), file)?;
+    /// Computes the length of the archive as it would be written by [`write`](Self::write), using the given options, without writing it.
+    ///
+    /// Names are only counted when the options enable strings. Fails in the same cases as [`write`](Self::write) would, i.e. when an offset, a length, or a count would not fit within the fields of the archive format.
+    pub fn written_len(&self, options: &Options) -> Result<WrittenLen> {
+        self.written_len_with_layout(options, &Layout::default())
+    }
+
+    /// See also [`written_len`](Self::written_len).
+    ///
+    /// Computes the length of the archive as it would be written by [`write_with_layout`](Self::write_with_layout). Any padding or trailing bytes preserved by the layout are counted as data.
+    pub fn written_len_with_layout(
+        &self,
+        options: &Options,
+        layout: &Layout,
+    ) -> Result<WrittenLen> {
+        let options = *options;
+        let (files, lens, data) = self.plan_write(options, layout);
+        let mut stream = std::io::sink();
+        let mut sink = Sink::new(&mut stream);
+        Self::write_index(&mut sink, options, layout, &files, &data)?;
+        if options.strings {
+            self.write_section(&mut sink, &files, Section::Strings)?;
         }
 
-        for file in self.values() {
-            for chunk in file {
-                sink.write_bytes(chunk.as_bytes())?;
+        let offsets = Offsets::new(self, options);
+        let names = lens.get(&Section::Strings).copied().unwrap_or_default();
+        Ok(WrittenLen {
+            header: offsets.file_entries,
+            index: offsets
+                .file_data
+                .checked_sub(offsets.file_entries)
+                .ok_or(Error::IntegralOverflow)?,
+            names,
+            data: data
+                .end
+                .checked_sub(offsets.file_data)
+                .and_then(|x| x.checked_sub(names))
+                .ok_or(Error::IntegralOverflow)?,
+        })
+    }
+
+    /// Appends the blobs which are not current within `storage` to the end of `stream`, and then rewrites the index.
+    pub(super) fn write_in_place(
+        &self,
+        stream: &mut fs::File,
+        options: Options,
+        layout: &Layout,
+        storage: &Storage<Section>,
+    ) -> Result<()> {
+        let files = self.sort_for_write(layout);
+        let offsets = Offsets::new(self, options);
+        let (data, appended) = storage.allocate(offsets.file_data, Self::sections(&files, options));
+
+        // data must be written first, since the index may grow over blobs which are being relocated
+        if let Some(first) = appended.first() {
+            stream.seek(SeekFrom::Start(data.offset(first).try_into()?))?;
+            let mut stream = BufWriter::new(&mut *stream);
+            let mut sink = Sink::new(&mut stream);
+            for id in appended {
+                self.write_section(&mut sink, &files, id)?;
             }
+            stream.flush()?;
         }
 
-        if options.strings {
-            for key in self.keys() {
-                sink.write_protocol::<WString>(key.name(), Endian::Little)?;
+        let mut index = Vec::new();
+        Self::write_index(&mut Sink::new(&mut index), options, layout, &files, &data)?;
+        stream.seek(SeekFrom::Start(0))?;
+        stream.write_all(&index)?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    /// Moves every blob within `storage` down towards the index, and then rewrites the index, returning the new length of the file.
+    pub(super) fn compact_in_place(
+        &self,
+        stream: &mut fs::File,
+        options: Options,
+        layout: &Layout,
+        storage: &Storage<Section>,
+    ) -> Result<usize> {
+        let files = self.sort_for_write(layout);
+        let offsets = Offsets::new(self, options);
+        let (data, moves) = storage.compact(
+            offsets.file_data,
+            Self::sections(&files, options)
+                .into_iter()
+                .map(|(id, _)| id),
+        );
+        for x in moves {
+            io::copy_within(stream, x.from, x.to, x.len)?;
+        }
+
+        let mut index = Vec::new();
+        Self::write_index(&mut Sink::new(&mut index), options, layout, &files, &data)?;
+        stream.seek(SeekFrom::Start(0))?;
+        stream.write_all(&index)?;
+        stream.flush()?;
+        Ok(data.end)
+    }
+
+    fn chunk(&self, section: &Section) -> Option<&Chunk<'bytes>> {
+        match section {
+            Section::Chunk(hash, idx) => self.get(hash).and_then(|x| x.as_slice().get(*idx)),
+            Section::Strings => None,
+        }
+    }
+
+    fn do_write<Out>(
+        &self,
+        stream: &mut Out,
+        options: Options,
+        layout: &Layout,
+        progress: &mut Progress,
+    ) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        let mut sink = Sink::new(stream);
+        let (files, lens, data) = self.plan_write(options, layout);
+        Self::write_index(&mut sink, options, layout, &files, &data)?;
+        progress.start(
+            data.slots.len(),
+            data.slots.iter().map(|x| lens[&x.id]).sum(),
+        );
+        for slot in &data.slots {
+            if progress.is_cancelled() {
+                return Err(Error::Cancelled);
             }
+            sink.write_bytes(slot.padding)?;
+            self.write_section(&mut sink, &files, slot.id)?;
+            let name = match slot.id {
+                Section::Chunk(hash, _) => self
+                    .map
+                    .get_key_value(&hash)
+                    .map(|(key, _)| key.name())
+                    .unwrap_or_default(),
+                Section::Strings => BStr::new(b""),
+            };
+            progress.advance(name, lens[&slot.id]);
         }
+        sink.write_bytes(data.trailing)?;
 
         Ok(())
     }
 
-    fn make_header(&self, options: Options) -> Result<(Header, Offsets)> {
+    /// Sorts the files for writing, and plans where each blob which follows the file entries is written, alongside the length of each blob.
+    #[allow(clippy::type_complexity)]
+    fn plan_write<'this, 'layout>(
+        &'this self,
+        options: Options,
+        layout: &'layout Layout,
+    ) -> (
+        Vec<(&'this Key<'bytes>, &'this File<'bytes>)>,
+        BTreeMap<Section, usize>,
+        Plan<'layout, Section>,
+    ) {
+        let files = self.sort_for_write(layout);
         let offsets = Offsets::new(self, options);
-        Ok((
-            Header {
-                version: options.version,
-                format: options.format,
-                file_count: self.len().try_into()?,
-                string_table_offset: if options.strings {
-                    offsets.strings as u64
-                } else {
-                    0
-                },
-                compression_format: options.compression_format,
+        let sections = Self::sections(&files, options);
+        let lens = sections.iter().copied().collect();
+        let data = layout.data.plan(
+            offsets.file_data,
+            sections.iter().copied(),
+            |lhs, rhs| match (self.chunk(lhs), self.chunk(rhs)) {
+                (Some(lhs), Some(rhs)) => {
+                    lhs.as_bytes() == rhs.as_bytes()
+                        && lhs.decompressed_len() == rhs.decompressed_len()
+                }
+                _ => false,
             },
-            offsets,
-        ))
+        );
+        (files, lens, data)
+    }
+
+    #[must_use]
+    pub(crate) fn query_entry(key: &Key<'bytes>, file: &File<'bytes>) -> QueryEntry {
+        let len = file.iter().map(Chunk::len).sum();
+        let compressed = file.iter().any(Chunk::is_compressed);
+        let decompressed_len = compressed.then(|| {
+            file.iter()
+                .map(|chunk| chunk.decompressed_len().unwrap_or(chunk.len()))
+                .sum()
+        });
+        QueryEntry::new(key.name(), len, decompressed_len)
+    }
+
+    /// Lists every blob which follows the file entries, along with its length.
+    #[must_use]
+    fn sections(
+        files: &[(&Key<'bytes>, &File<'bytes>)],
+        options: Options,
+    ) -> Vec<(Section, usize)> {
+        files
+            .iter()
+            .flat_map(|(key, file)| {
+                let hash = *key.hash();
+                file.iter()
+                    .enumerate()
+                    .map(move |(idx, chunk)| (Section::Chunk(hash, idx), chunk.len()))
+            })
+            .chain(options.strings.then(|| {
+                // wstring -> include length prefix
+                let len = files
+                    .iter()
+                    .map(|(key, _)| mem::size_of::<u16>() + key.name().len())
+                    .sum();
+                (Section::Strings, len)
+            }))
+            .collect()
+    }
+
+    fn sort_for_write<'this>(
+        &'this self,
+        layout: &Layout,
+    ) -> Vec<(&'this Key<'bytes>, &'this File<'bytes>)> {
+        let mut files: Vec<_> = self.iter().collect();
+        Order::new(layout.files.iter().map(|(hash, _)| hash)).arrange(&mut files, |x| x.0.hash());
+        files
+    }
+
+    fn write_index<Out>(
+        sink: &mut Sink<Out>,
+        options: Options,
+        layout: &Layout,
+        files: &[(&Key<'bytes>, &File<'bytes>)],
+        data: &Plan<'_, Section>,
+    ) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        let header = Header {
+            version: options.version,
+            format: options.format,
+            file_count: files.len().try_into()?,
+            string_table_offset: data
+                .offsets
+                .get(&Section::Strings)
+                .map_or(Ok(0), |&x| x.try_into())?,
+            compression_format: options.compression_format,
+            unknown: layout.header_unknown,
+            compression_format_raw: match (options.compression_format, layout.compression_format) {
+                (CompressionFormat::Zip, Some(raw)) if raw != 3 => raw,
+                (CompressionFormat::Zip, _) => 0,
+                (CompressionFormat::LZ4, _) => 3,
+            },
+        };
+        Self::write_header(sink, &header)?;
+
+        let unknowns: BTreeMap<_, _> = layout.files.iter().copied().collect();
+        for (key, file) in files {
+            let hash = key.hash();
+            let unknown = unknowns.get(hash).copied().unwrap_or_default();
+            Self::write_file(sink, &header, data, hash, file, unknown)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_section<Out>(
+        &self,
+        sink: &mut Sink<Out>,
+        files: &[(&Key<'bytes>, &File<'bytes>)],
+        section: Section,
+    ) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        match section {
+            Section::Chunk(..) => {
+                if let Some(chunk) = self.chunk(&section) {
+                    sink.write_bytes(chunk.as_bytes())?;
+                }
+            }
+            Section::Strings => {
+                for (key, _) in files {
+                    sink.write_protocol::<WString>(key.name(), Endian::Little)?;
+                }
+            }
+        }
+        Ok(())
     }
 
     fn write_chunk<Out>(
         sink: &mut Sink<Out>,
         header: &Header,
-        offsets: &mut Offsets,
+        data_offset: usize,
         chunk: &Chunk<'bytes>,
     ) -> Result<()>
     where
         Out: ?Sized + Write,
     {
-        let data_offset: u64 = offsets.file_data.try_into()?;
-        offsets.file_data += chunk.len();
+        let data_offset: u64 = data_offset.try_into()?;
         let (compressed_size, decompressed_size): (u32, u32) =
             if let Some(decompressed_len) = chunk.decompressed_len() {
                 (chunk.len().try_into()?, decompressed_len.try_into()?)
@@ -291,9 +826,10 @@ impl<'bytes> Archive<'bytes> {
     fn write_file<Out>(
         sink: &mut Sink<Out>,
         header: &Header,
-        offsets: &mut Offsets,
+        data: &Plan<'_, Section>,
         hash: &FileHash,
         file: &File<'bytes>,
+        unknown: u8,
     ) -> Result<()>
     where
         Out: ?Sized + Write,
@@ -306,7 +842,7 @@ impl<'bytes> Archive<'bytes> {
             Format::DX10 => constants::FILE_HEADER_SIZE_DX10,
             Format::GNMF => constants::FILE_HEADER_SIZE_GNMF,
         };
-        sink.write(&(0u8, chunk_count, chunk_size), Endian::Little)?;
+        sink.write(&(unknown, chunk_count, chunk_size), Endian::Little)?;
 
         match (header.format, &file.header) {
             (Format::GNRL, FileHeader::GNRL) => (),
@@ -331,8 +867,9 @@ impl<'bytes> Archive<'bytes> {
             }
         }
 
-        for chunk in file {
-            Self::write_chunk(sink, header, offsets, chunk)?;
+        for (idx, chunk) in file.iter().enumerate() {
+            let data_offset = data.offset(&Section::Chunk(*hash, idx));
+            Self::write_chunk(sink, header, data_offset, chunk)?;
         }
 
         Ok(())
@@ -368,32 +905,64 @@ impl<'bytes> Archive<'bytes> {
         )?;
 
         if matches!(header.version, Version::v2 | Version::v3) {
-            sink.write(&1u64, Endian::Little)?;
+            sink.write(&header.unknown, Endian::Little)?;
         }
 
         if header.version == Version::v3 {
-            let format: u32 = match header.compression_format {
-                CompressionFormat::Zip => 0,
-                CompressionFormat::LZ4 => 3,
-            };
-            sink.write(&format, Endian::Little)?;
+            sink.write(&header.compression_format_raw, Endian::Little)?;
         }
 
         Ok(())
     }
 
     fn do_read<In>(source: &mut In) -> Result<ReadResult<Self>>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        Self::read_archive(source, None)
+    }
+
+    fn do_read_with_layout<In>(source: &mut In) -> Result<LayoutResult<Self>>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        let mut capture = Capture::default();
+        let (archive, options) = Self::read_archive(source, Some(&mut capture))?;
+        let file_data = source.stream_position();
+        capture.layout.data = Region::capture(source.as_bytes(), file_data, capture.data);
+        Ok((archive, options, capture.layout))
+    }
+
+    fn read_archive<In>(
+        source: &mut In,
+        mut capture: Option<&mut Capture>,
+    ) -> Result<ReadResult<Self>>
     where
         In: ?Sized + Source<'bytes>,
     {
         let header = Self::read_header(source)?;
         let mut map = Map::default();
-        let mut strings: usize = header.string_table_offset.try_into()?;
+        let string_table_offset: usize = header.string_table_offset.try_into()?;
+        let mut strings = string_table_offset;
         for _ in 0..header.file_count {
-            let (key, value) = Self::read_file(source, &header, &mut strings)?;
+            let (key, value) =
+                Self::read_file(source, &header, &mut strings, capture.as_deref_mut())?;
             map.insert(key, value);
         }
 
+        if let Some(capture) = capture {
+            if string_table_offset != 0 {
+                capture.data.push((
+                    Section::Strings,
+                    string_table_offset,
+                    strings - string_table_offset,
+                ));
+            }
+            capture.layout.header_unknown = header.unknown;
+            capture.layout.compression_format =
+                (header.version == Version::v3).then_some(header.compression_format_raw);
+        }
+
         Ok((
             Self { map },
             Options {
@@ -405,7 +974,8 @@ impl<'bytes> Archive<'bytes> {
         ))
     }
 
-    fn read_chunk<In>(source: &mut In, header: &Header) -> Result<Chunk<'bytes>>
+    /// Reads a chunk, along with where its data lies, as `(offset, len)`.
+    fn read_chunk<In>(source: &mut In, header: &Header) -> Result<(Chunk<'bytes>, (usize, usize))>
     where
         In: ?Sized + Source<'bytes>,
     {
@@ -424,26 +994,28 @@ impl<'bytes> Archive<'bytes> {
             return Err(Error::InvalidChunkSentinel(sentinel));
         }
 
+        let data_offset: usize = data_offset.try_into()?;
+        let len = if compressed_size == 0 {
+            decompressed_size
+        } else {
+            compressed_size
+        } as usize;
         let bytes = source.save_restore_position(|source| -> Result<Bytes<'bytes>> {
-            source.seek_absolute(data_offset.try_into()?)?;
-            let len = if compressed_size == 0 {
-                decompressed_size
-            } else {
-                compressed_size
-            };
-            let bytes = source.read_bytes(len as usize)?;
+            source.seek_absolute(data_offset)?;
+            let bytes = source.read_blob(len)?;
             Ok(bytes)
         })??;
         let decompressed_len = (compressed_size != 0).then_some(decompressed_size as usize);
         let bytes = bytes.into_compressable(decompressed_len);
 
-        Ok(Chunk { bytes, mips })
+        Ok((Chunk { bytes, mips }, (data_offset, len)))
     }
 
     fn read_file<In>(
         source: &mut In,
         header: &Header,
         strings: &mut usize,
+        mut capture: Option<&mut Capture>,
     ) -> Result<(Key<'bytes>, File<'bytes>)>
     where
         In: ?Sized + Source<'bytes>,
@@ -460,7 +1032,7 @@ impl<'bytes> Archive<'bytes> {
         };
 
         let hash = Self::read_hash(source)?;
-        let (_, chunk_count, chunk_size): (u8, u8, u16) = source.read(Endian::Little)?;
+        let (unknown, chunk_count, chunk_size): (u8, u8, u16) = source.read(Endian::Little)?;
         if !matches!(
             (header.format, chunk_size),
             (Format::GNRL, constants::FILE_HEADER_SIZE_GNRL)
@@ -491,17 +1063,23 @@ impl<'bytes> Archive<'bytes> {
             }
         };
 
+        let hash: FileHash = hash.into();
         let mut chunks = Vec::with_capacity(chunk_count.into());
-        for _ in 0..chunk_count {
-            let chunk = Self::read_chunk(source, header)?;
+        for idx in 0..chunk_count.into() {
+            let (chunk, (data_offset, len)) = Self::read_chunk(source, header)?;
+            if let Some(capture) = capture.as_deref_mut() {
+                let section = Section::Chunk(hash, idx);
+                capture.data.push((section, data_offset, len));
+            }
             chunks.push(chunk);
         }
 
+        if let Some(capture) = capture {
+            capture.layout.files.push((hash, unknown));
+        }
+
         Ok((
-            Key {
-                hash: hash.into(),
-                name,
-            },
+            Key { hash, name },
             File {
                 chunks,
                 header: file_header,
@@ -548,17 +1126,19 @@ impl<'bytes> Archive<'bytes> {
             _ => return Err(Error::InvalidVersion(version)),
         };
 
-        if matches!(version, Version::v2 | Version::v3) {
-            source.read::<u64>(Endian::Little)?;
-        }
+        let unknown = if matches!(version, Version::v2 | Version::v3) {
+            source.read(Endian::Little)?
+        } else {
+            1
+        };
 
-        let compression_format = if version == Version::v3 {
-            let format: u32 = source.read(Endian::Little)?;
-            if format == 3 {
-                CompressionFormat::LZ4
-            } else {
-                CompressionFormat::Zip
-            }
+        let compression_format_raw = if version == Version::v3 {
+            source.read(Endian::Little)?
+        } else {
+            0
+        };
+        let compression_format = if compression_format_raw == 3 {
+            CompressionFormat::LZ4
         } else {
             CompressionFormat::Zip
         };
@@ -569,24 +1149,56 @@ impl<'bytes> Archive<'bytes> {
             file_count,
             string_table_offset,
             compression_format,
+            unknown,
+            compression_format_raw,
         })
     }
 }
 
+impl Archive<'static> {
+    /// Reads the archive within `stream`, along with where each of its blobs lies within it.
+    pub(super) fn read_in_place(
+        stream: &fs::File,
+    ) -> Result<(Self, Options, Layout, Storage<Section>)> {
+        let mut source = BufferedSource::try_from(stream)?;
+        Self::read_storage(&mut source)
+    }
+
+    /// See also [`read_in_place`](Self::read_in_place).
+    pub(super) fn read_storage<In>(
+        source: &mut In,
+    ) -> Result<(Self, Options, Layout, Storage<Section>)>
+    where
+        In: ?Sized + Source<'static>,
+    {
+        let mut capture = Capture::default();
+        let (archive, options) = Self::read_archive(source, Some(&mut capture))?;
+        let storage = Storage::new(capture.data, source.stream_position(), source.stream_len());
+        Ok((archive, options, capture.layout, storage))
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::Offsets;
     use crate::{
         cc,
         fo4::{
-            Archive, ArchiveKey, ArchiveOptions, CompressionFormat, Error, File, FileHeader,
-            FileReadOptions, Format, Version,
+            Archive, ArchiveKey, ArchiveLayout, ArchiveOptions, Chunk, ChunkCompressionOptions,
+            CompressionFormat, Error, File, FileHeader, Format, Version,
         },
+        layout::Region,
         prelude::*,
-        Borrowed, CompressionResult,
+        Borrowed, Buffered, CancellationToken, CompressionPolicy, Glob, MergePolicy, Progress,
+        ProgressEvent,
     };
+    #[cfg(feature = "directxtex")]
+    use crate::{fo4::FileReadOptions, CompressionResult};
     use anyhow::Context as _;
     use bstr::ByteSlice as _;
+    #[cfg(feature = "directxtex")]
     use core::mem;
+    #[cfg(feature = "directxtex")]
     use directxtex::DXGI_FORMAT;
     use memmap2::Mmap;
     use std::{
@@ -605,6 +1217,7 @@ mod tests {
         assert_eq!(archive.len(), 0);
     }
 
+    #[cfg(feature = "directxtex")]
     #[test]
     fn chunking_strategy() -> anyhow::Result<()> {
         let file = {
@@ -707,6 +1320,7 @@ mod tests {
         Ok(())
     }
 
+    #[cfg(feature = "directxtex")]
     #[test]
     fn files_with_cubemaps() -> anyhow::Result<()> {
         let file = {
@@ -807,6 +1421,7 @@ mod tests {
         Ok(())
     }
 
+    #[cfg(feature = "directxtex")]
     #[allow(non_camel_case_types, non_snake_case)]
     #[derive(Clone, Copy, Debug, Eq, PartialEq)]
     struct DDS_PIXELFORMAT {
@@ -820,6 +1435,7 @@ mod tests {
         dwABitMask: u32,
     }
 
+    #[cfg(feature = "directxtex")]
     #[allow(non_camel_case_types, non_snake_case)]
     #[derive(Clone, Copy, Debug)]
     struct DDS_HEADER {
@@ -841,8 +1457,10 @@ mod tests {
         dwReserved2: u32,
     }
 
+    #[cfg(feature = "directxtex")]
     impl Eq for DDS_HEADER {}
 
+    #[cfg(feature = "directxtex")]
     impl PartialEq for DDS_HEADER {
         fn eq(&self, other: &Self) -> bool {
             macro_rules! compare {
@@ -866,6 +1484,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "directxtex")]
     #[allow(non_camel_case_types, non_snake_case)]
     #[derive(Clone, Copy, Debug)]
     struct DDS_HEADER_DXT10 {
@@ -877,8 +1496,10 @@ mod tests {
         miscFlags2: u32,
     }
 
+    #[cfg(feature = "directxtex")]
     impl Eq for DDS_HEADER_DXT10 {}
 
+    #[cfg(feature = "directxtex")]
     impl PartialEq for DDS_HEADER_DXT10 {
         fn eq(&self, other: &Self) -> bool {
             macro_rules! compare {
@@ -894,6 +1515,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "directxtex")]
     #[allow(non_snake_case)]
     #[derive(Clone, Copy, Debug, Eq, PartialEq)]
     struct DDS9Header {
@@ -901,6 +1523,7 @@ mod tests {
         header: DDS_HEADER,
     }
 
+    #[cfg(feature = "directxtex")]
     #[allow(non_snake_case)]
     #[derive(Clone, Copy, Debug, Eq, PartialEq)]
     struct DDS10Header {
@@ -908,6 +1531,7 @@ mod tests {
         header10: DDS_HEADER_DXT10,
     }
 
+    #[cfg(feature = "directxtex")]
     #[test]
     fn pack_unpack_texture_archives() -> anyhow::Result<()> {
         let root = Path::new("data/fo4_dds_test");
@@ -983,6 +1607,31 @@ mod tests {
         Ok(())
     }
 
+    #[cfg(not(feature = "directxtex"))]
+    #[test]
+    fn raw_texture_chunks() -> anyhow::Result<()> {
+        let root = Path::new("data/fo4_dds_test");
+        let (archive, options) = Archive::read(root.join("in.ba2").as_path())?;
+        assert_eq!(options.format(), Format::DX10);
+
+        let file = archive
+            .get(&ArchiveKey::from("Fence006_1K_Roughness.dds"))
+            .context("failed to get file from archive")?;
+        assert!(matches!(file.header, FileHeader::DX10(x) if x.mip_count == 11));
+        assert_eq!(file.mip_for_resolution(256, 256)?, 2);
+
+        // the chunks hold the pixel data which follows the dds header, in order
+        let original = fs::read(root.join("Fence006_1K_Roughness.dds"))?;
+        let pixels: Vec<u8> = file.iter().flat_map(Chunk::as_bytes).copied().collect();
+        assert_eq!(&original[original.len() - pixels.len()..], &pixels[..]);
+
+        // but producing a dds file requires directxtex
+        let result = file.write(&mut Vec::new(), &Default::default());
+        assert!(matches!(result, Err(Error::TexturesDisabled)));
+        Ok(())
+    }
+
+    #[cfg(feature = "directxtex")]
     #[test]
     fn dx9() -> anyhow::Result<()> {
         let root = Path::new("data/fo4_dx9_test");
@@ -1012,6 +1661,39 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn read_buffered() -> anyhow::Result<()> {
+        for path in [
+            "data/fo4_compression_test/normal.ba2",
+            "data/fo4_cubemap_test/in.ba2",
+            "data/fo4_dds_test/in.ba2",
+        ] {
+            let (mapped, mapped_options) = Archive::read(Path::new(path))?;
+            let fd = fs::File::open(path)?;
+            let (buffered, buffered_options) = Archive::read(Buffered(&fd))?;
+            assert_eq!(mapped_options.format(), buffered_options.format());
+            assert!(mapped
+                .diff(&mapped_options, &buffered, &buffered_options)?
+                .is_empty());
+            for (key, file) in &mapped {
+                let other = buffered.get(key).context("file was missing")?;
+                assert_eq!(other.header, file.header);
+                for (lhs, rhs) in other.iter().zip(file) {
+                    assert_eq!(lhs.as_bytes(), rhs.as_bytes());
+                }
+            }
+        }
+
+        let fd = fs::File::open("data/fo4_invalid_test/invalid_exhausted.ba2")?;
+        match Archive::read(Buffered(&fd)) {
+            Err(Error::Io(error)) => assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof),
+            Err(err) => return Err(err.into()),
+            Ok(_) => anyhow::bail!("read should have failed"),
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn invalid_exhausted() -> anyhow::Result<()> {
         let path = Path::new("data/fo4_invalid_test/invalid_exhausted.ba2");
@@ -1299,4 +1981,358 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn compress_files_with_policy() -> anyhow::Result<()> {
+        let payload = [b'x'; 256];
+        let files: [(&str, &[u8]); 3] = [
+            ("meshes/bucket.nif", &payload),
+            ("sound/bucket.wav", &payload),
+            ("meshes/tiny.nif", b"1"),
+        ];
+        let mut archive: Archive = files
+            .iter()
+            .map(|&(name, data)| {
+                let chunk = Chunk::from_decompressed(data);
+                (ArchiveKey::from(name), [chunk].into_iter().collect())
+            })
+            .collect();
+
+        let options = ArchiveOptions::default();
+        let policy = CompressionPolicy::builder()
+            .include_extension("nif")
+            .build();
+        archive.compress_files(&ChunkCompressionOptions::from(&options), &policy)?;
+        let compressed = |archive: &Archive, name: &str| -> anyhow::Result<bool> {
+            let file = archive
+                .get(&ArchiveKey::from(name))
+                .context("file was missing")?;
+            Ok(file[0].is_compressed())
+        };
+        assert!(compressed(&archive, "meshes/bucket.nif")?);
+        assert!(!compressed(&archive, "sound/bucket.wav")?);
+        assert!(!compressed(&archive, "meshes/tiny.nif")?);
+
+        let mut stream = Vec::new();
+        archive.write(&mut stream, &options)?;
+        let (copy, _) = Archive::read(Borrowed(&stream))?;
+        for (name, _) in files {
+            assert_eq!(compressed(&copy, name)?, compressed(&archive, name)?);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn merging() -> anyhow::Result<()> {
+        let make = |files: &[(&str, &'static [u8])], options: ArchiveOptions| {
+            let archive: Archive = files
+                .iter()
+                .map(|&(name, data)| {
+                    let chunk = Chunk::from_decompressed(data);
+                    (ArchiveKey::from(name), [chunk].into_iter().collect())
+                })
+                .collect();
+            (archive, options)
+        };
+        let sources = || {
+            [
+                make(
+                    &[("shared.txt", b"first"), ("first.txt", b"1")],
+                    ArchiveOptions::builder().strings(false).build(),
+                ),
+                make(
+                    &[("shared.txt", b"last"), ("last.txt", b"2")],
+                    ArchiveOptions::builder().strings(true).build(),
+                ),
+            ]
+        };
+        let contents = |archive: &Archive, name: &str| -> Vec<u8> {
+            archive
+                .get(&ArchiveKey::from(name))
+                .unwrap()
+                .iter()
+                .flat_map(|x| x.as_bytes().to_vec())
+                .collect()
+        };
+
+        let (merged, options) = Archive::merge(sources(), MergePolicy::FirstWins)?;
+        assert_eq!(merged.len(), 3);
+        assert_eq!(contents(&merged, "shared.txt"), b"first");
+        assert!(options.strings());
+
+        let (merged, _) = Archive::merge(sources(), MergePolicy::LastWins)?;
+        assert_eq!(contents(&merged, "shared.txt"), b"last");
+
+        match Archive::merge(sources(), MergePolicy::Error) {
+            Err(Error::MergeConflict(name)) => assert_eq!(name, "shared.txt"),
+            _ => anyhow::bail!("merge should have failed"),
+        }
+
+        let (merged, _) = Archive::merge_with(sources(), |key, mut existing, incoming| {
+            assert_eq!(key.name(), "shared.txt");
+            for chunk in incoming {
+                existing.push(chunk);
+            }
+            Ok(existing)
+        })?;
+        assert_eq!(contents(&merged, "shared.txt"), b"firstlast");
+
+        let dx10 = make(&[], ArchiveOptions::builder().format(Format::DX10).build());
+        let mut sources = sources().to_vec();
+        sources.push(dx10);
+        assert!(matches!(
+            Archive::merge(sources, MergePolicy::LastWins),
+            Err(Error::FormatMismatch)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_with_layout() -> anyhow::Result<()> {
+        let paths = [
+            "data/common_guess_test/fo4.ba2",
+            "data/fo4_chunk_test/in.ba2",
+            "data/fo4_compression_test/normal.ba2",
+            "data/fo4_compression_test/xbox.ba2",
+            "data/fo4_cubemap_test/in.ba2",
+            "data/fo4_dds_test/in.ba2",
+            "data/fo4_missing_string_table_test/in.ba2",
+            "data/fo4_next_gen_test/dx10_v7.ba2",
+            "data/fo4_next_gen_test/dx10_v8.ba2",
+            "data/fo4_next_gen_test/gnrl_v7.ba2",
+            "data/fo4_next_gen_test/gnrl_v8.ba2",
+        ];
+        for path in paths {
+            let original = fs::read(path)?;
+            let (archive, options, layout) = Archive::read_with_layout(Borrowed(&original))
+                .with_context(|| format!("failed to read archive: {path}"))?;
+            let mut copy = Vec::new();
+            archive.write_with_layout(&mut copy, &options, &layout)?;
+            assert!(copy == original, "archive was not reproduced: {path}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_with_modified_layout() -> anyhow::Result<()> {
+        let path = Path::new("data/fo4_compression_test/normal.ba2");
+        let (mut archive, options, layout) = Archive::read_with_layout(path)?;
+        let removed = archive.keys().next().context("archive was empty")?.clone();
+        archive.remove(removed.hash());
+        let chunk = Chunk::from_decompressed(b"Hello world!\n");
+        archive.insert(
+            ArchiveKey::from(b"new/file.txt"),
+            [chunk].into_iter().collect(),
+        );
+
+        let mut stream = Vec::new();
+        archive.write_with_layout(&mut stream, &options, &layout)?;
+        let (copy, _) = Archive::read(Borrowed(&stream))?;
+        assert_eq!(copy.len(), archive.len());
+        assert!(copy.get(removed.hash()).is_none());
+        for (key, file) in &archive {
+            let other = copy.get(key.hash()).context("file was missing")?;
+            assert_eq!(other.len(), file.len());
+            for (lhs, rhs) in other.iter().zip(file) {
+                assert_eq!(lhs.as_bytes(), rhs.as_bytes());
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn query() -> anyhow::Result<()> {
+        let options = ChunkCompressionOptions::default();
+        let payload = b"payload payload payload payload";
+        let mut archive = Archive::new();
+        for (path, compress) in [
+            ("sound/fx/bucket.wav", false),
+            ("textures/clutter/bucket_n.dds", true),
+        ] {
+            let chunk = Chunk::from_decompressed(&payload[..]);
+            let chunk = if compress {
+                chunk.compress(&options)?
+            } else {
+                chunk.into_owned()
+            };
+            let file: File = [chunk.clone(), chunk].into_iter().collect();
+            archive.insert(ArchiveKey::from(path), file);
+        }
+
+        let found: Vec<_> = archive
+            .find(|x| x.is_compressed() && x.decompressed_len() == Some(payload.len() * 2))
+            .map(|x| x.name().to_owned())
+            .collect();
+        assert_eq!(found, ["textures\\clutter\\bucket_n.dds"]);
+
+        let glob = Glob::new("sound/**");
+        let selected = archive.select(|x| x.matches(&glob) && x.len() == payload.len() * 2);
+        assert_eq!(selected.len(), 1);
+        assert!(selected
+            .get(&ArchiveKey::from(b"SOUND\\FX\\BUCKET.WAV"))
+            .is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn rename_and_move() -> anyhow::Result<()> {
+        let mut archive = Archive::new();
+        for (path, data) in [("textures/sky.dds", b"sky"), ("textures/sun.dds", b"sun")] {
+            let file: File = [Chunk::from_decompressed(&data[..])].into_iter().collect();
+            archive.insert(ArchiveKey::from(path), file);
+        }
+        let options = ArchiveOptions::builder().strings(true).build();
+        let mut bytes = Vec::new();
+        archive.write(&mut bytes, &options)?;
+        let range = bytes.as_ptr_range();
+
+        let (mut archive, _) = Archive::read(Borrowed(&bytes))?;
+        archive.rename_file("textures/sky.dds", "textures/sky/day.dds")?;
+        archive.move_file("textures\\sun.dds", "textures/sky")?;
+        assert_eq!(archive.len(), 2);
+        for (path, data) in [
+            ("textures/sky/day.dds", b"sky"),
+            ("textures/sky/sun.dds", b"sun"),
+        ] {
+            let (key, file) = archive
+                .get_key_value(&ArchiveKey::from(path))
+                .context("file was missing")?;
+            assert_eq!(key.name(), path.replace('/', "\\"));
+            let chunk = file.iter().next().context("chunk was missing")?;
+            assert_eq!(chunk.as_bytes(), data);
+            assert!(range.contains(&chunk.as_bytes().as_ptr()));
+        }
+
+        match archive.rename_file("textures/sky/day.dds", "textures/sky/sun.dds") {
+            Err(Error::FileExists(path)) => assert_eq!(path, "textures\\sky\\sun.dds"),
+            other => anyhow::bail!("rename should have collided: {other:?}"),
+        }
+        match archive.move_file("textures/missing.dds", "textures/sky") {
+            Err(Error::FileNotFound(path)) => assert_eq!(path, "textures\\missing.dds"),
+            other => anyhow::bail!("move should have failed: {other:?}"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn written_len() -> anyhow::Result<()> {
+        for path in [
+            "data/fo4_compression_test/normal.ba2",
+            "data/fo4_cubemap_test/in.ba2",
+            "data/fo4_dds_test/in.ba2",
+        ] {
+            let (archive, options) = Archive::read(Path::new(path))?;
+            for options in [
+                options,
+                ArchiveOptions::builder()
+                    .format(options.format())
+                    .version(Version::v3)
+                    .strings(false)
+                    .build(),
+            ] {
+                let len = archive.written_len(&options)?;
+                let mut bytes = Vec::new();
+                archive.write(&mut bytes, &options)?;
+                assert_eq!(len.total(), bytes.len(), "{path}");
+                assert_eq!(len.names == 0, !options.strings());
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn written_len_with_layout() -> anyhow::Result<()> {
+        let (archive, options) = Archive::read(Path::new("data/fo4_compression_test/normal.ba2"))?;
+
+        // align every blob, as if the archive had been packed for a console
+        let files = archive.sort_for_write(&ArchiveLayout::default());
+        let start = Offsets::new(&archive, options).file_data;
+        let mut items = Vec::new();
+        let mut cursor = start;
+        for (section, len) in Archive::sections(&files, options) {
+            cursor = cursor.next_multiple_of(0x100);
+            items.push((section, cursor, len));
+            cursor += len;
+        }
+        let aligned = vec![0; cursor.next_multiple_of(0x100)];
+        let layout = ArchiveLayout {
+            data: Region::capture(&aligned, start, items),
+            ..ArchiveLayout::default()
+        };
+
+        let len = archive.written_len_with_layout(&options, &layout)?;
+        let mut bytes = Vec::new();
+        archive.write_with_layout(&mut bytes, &options, &layout)?;
+        assert_eq!(len.total(), bytes.len());
+        assert!(len.total() > archive.written_len(&options)?.total());
+
+        let (copy, options, layout) = Archive::read_with_layout(Borrowed(&bytes))?;
+        assert!(layout.data.is_padded());
+        assert_eq!(copy.written_len_with_layout(&options, &layout)?, len);
+        Ok(())
+    }
+
+    #[test]
+    fn progress() -> anyhow::Result<()> {
+        let payload = [b'x'; 256];
+        let files: [(&str, &[u8]); 2] =
+            [("meshes/bucket.nif", &payload), ("meshes/tiny.nif", b"1")];
+        let mut archive: Archive = files
+            .iter()
+            .map(|&(name, data)| {
+                let chunk = Chunk::from_decompressed(data);
+                (ArchiveKey::from(name), [chunk].into_iter().collect())
+            })
+            .collect();
+        let options = ArchiveOptions::builder().strings(true).build();
+
+        let mut events = Vec::new();
+        let mut progress = Progress::builder()
+            .observer(|event: &ProgressEvent| {
+                events.push((event.name().to_owned(), event.bytes(), event.total_bytes()));
+            })
+            .build();
+        archive.compress_files_with_progress(
+            &ChunkCompressionOptions::from(&options),
+            &CompressionPolicy::default(),
+            &mut progress,
+        )?;
+        drop(progress);
+        assert_eq!(
+            events,
+            [
+                ("meshes\\bucket.nif".into(), 256, 257),
+                ("meshes\\tiny.nif".into(), 1, 257),
+            ]
+        );
+
+        let mut events = Vec::new();
+        let mut progress = Progress::builder()
+            .observer(|event: &ProgressEvent| {
+                events.push((event.name().to_owned(), event.bytes(), event.total_bytes()));
+            })
+            .build();
+        let mut bytes = Vec::new();
+        archive.write_with_progress(&mut bytes, &options, &mut progress)?;
+        drop(progress);
+        let mut original = Vec::new();
+        archive.write(&mut original, &options)?;
+        assert_eq!(bytes, original);
+        // one event for each chunk, followed by the string table
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2].0, "");
+        let total: usize = events.iter().map(|x| x.1).sum();
+        assert!(events.iter().all(|x| x.2 == total));
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let mut progress = Progress::builder().cancellation(token).build();
+        let result = archive.write_with_progress(&mut Vec::new(), &options, &mut progress);
+        assert!(matches!(result, Err(Error::Cancelled)));
+        Ok(())
+    }
 }
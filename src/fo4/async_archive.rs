@@ -0,0 +1,232 @@
+use crate::{
+    containers::CompressableBytes,
+    fo4::{archive::Section, Archive, ArchiveKey, ArchiveOptions, File, FileHash, Result},
+    io,
+    layout::Storage,
+};
+use core::borrow::Borrow;
+use tokio::io::{AsyncRead, AsyncSeek};
+
+/// Reads a FO4 archive asynchronously, fetching the data of each file only when it is requested.
+///
+/// Only the index of the archive, and its string table, are read up front. The chunks of a file are read using range reads whenever it is fetched, rather than by memory-mapping the whole archive, which makes this suitable for streams which are slow, or which live on another machine. Chunks are returned as they are stored, so compressed chunks must still be decompressed, i.e. using [`Chunk::decompress_async`](crate::fo4::Chunk::decompress_async).
+///
+/// ```rust
+/// use ba2::fo4::{ArchiveKey, AsyncArchive, ChunkCompressionOptions};
+///
+/// async fn example() -> Option<()> {
+///     let stream = tokio::fs::File::open("path/to/fallout4/Data/Fallout4 - Interface.ba2")
+///         .await
+///         .ok()?;
+///     let mut archive = AsyncArchive::new(stream).await.ok()?;
+///     let options = ChunkCompressionOptions::from(archive.options());
+///     let key = ArchiveKey::from(b"Interface/HUDMenu.swf");
+///     let file = archive.get(&key).await.ok()??;
+///     let mut bytes = Vec::new();
+///     for chunk in file {
+///         let chunk = if chunk.is_compressed() {
+///             chunk.decompress_async(&options).await.ok()?
+///         } else {
+///             chunk
+///         };
+///         bytes.extend_from_slice(chunk.as_bytes());
+///     }
+///     tokio::fs::write("HUDMenu.swf", bytes).await.ok()?;
+///     Some(())
+/// }
+/// ```
+pub struct AsyncArchive<In> {
+    stream: In,
+    /// The index of the archive, whose chunks are all empty, but which still know how they are compressed.
+    index: Archive<'static>,
+    options: ArchiveOptions,
+    storage: Storage<Section>,
+}
+
+impl<In> AsyncArchive<In>
+where
+    In: AsyncRead + AsyncSeek + Unpin,
+{
+    /// Reads the index of the archive within the given stream.
+    pub async fn new(mut stream: In) -> Result<Self> {
+        let (index, options, _, storage) =
+            io::read_deferred(&mut stream, Archive::read_storage).await?;
+        Ok(Self {
+            stream,
+            index,
+            options,
+            storage,
+        })
+    }
+
+    /// Fetches the data of the file with the given hash.
+    pub async fn get<K>(&mut self, key: &K) -> Result<Option<File<'static>>>
+    where
+        K: Borrow<FileHash>,
+    {
+        let hash = *key.borrow();
+        let Some(file) = self.index.get(&hash) else {
+            return Ok(None);
+        };
+
+        let mut chunks = Vec::with_capacity(file.len());
+        for (idx, chunk) in file.iter().enumerate() {
+            let Some((offset, len)) = self.storage.get(&Section::Chunk(hash, idx)) else {
+                return Ok(None);
+            };
+            let bytes = io::read_range(&mut self.stream, offset, len).await?;
+            chunks.push(chunk.copy_with(CompressableBytes::from_owned(
+                bytes,
+                chunk.decompressed_len(),
+            )));
+        }
+
+        Ok(Some(File {
+            chunks,
+            header: file.header.clone(),
+        }))
+    }
+
+    /// Fetches the data of every file, in the order they are stored.
+    pub async fn into_archive(mut self) -> Result<(Archive<'static>, ArchiveOptions)> {
+        let mut keys: Vec<_> = self.index.keys().cloned().collect();
+        keys.sort_by_key(|key| self.storage.get(&Section::Chunk(key.hash, 0)));
+
+        let mut archive = Archive::new();
+        for key in keys {
+            if let Some(file) = self.get(&key).await? {
+                archive.insert(key, file);
+            }
+        }
+
+        Ok((archive, self.options))
+    }
+
+    #[must_use]
+    pub fn into_inner(self) -> In {
+        self.stream
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &ArchiveKey<'static>> {
+        self.index.keys()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    #[must_use]
+    pub fn options(&self) -> &ArchiveOptions {
+        &self.options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        fo4::{
+            Archive, ArchiveKey, ArchiveOptions, AsyncArchive, Chunk, ChunkCompressionOptions, File,
+        },
+        prelude::*,
+    };
+    use anyhow::Context as _;
+    use std::{io::Cursor, path::Path};
+    use tokio::io::{AsyncRead, AsyncSeek};
+
+    async fn compare<In>(
+        expected: &Archive<'_>,
+        mut archive: AsyncArchive<In>,
+    ) -> anyhow::Result<()>
+    where
+        In: AsyncRead + AsyncSeek + Unpin,
+    {
+        assert_eq!(archive.len(), expected.len());
+        let options = ChunkCompressionOptions::from(archive.options());
+        for (key, file) in expected {
+            let fetched = archive.get(key).await?.context("file was missing")?;
+            assert_eq!(fetched.header, file.header);
+            assert_eq!(fetched.len(), file.len());
+            for (lhs, rhs) in fetched.into_iter().zip(file) {
+                assert_eq!(lhs.mips, rhs.mips);
+                assert_eq!(lhs.decompressed_len(), rhs.decompressed_len());
+                assert_eq!(lhs.as_bytes(), rhs.as_bytes());
+                if lhs.is_compressed() {
+                    let decompressed = lhs.decompress_async(&options).await?;
+                    assert_eq!(
+                        decompressed.as_bytes(),
+                        rhs.decompress(&options)?.as_bytes()
+                    );
+                }
+            }
+        }
+
+        let (archive, options) = archive.into_archive().await?;
+        assert!(archive.diff(&options, expected, &options)?.is_empty());
+        for key in expected.keys() {
+            let (other, _) = archive.get_key_value(key).context("file was missing")?;
+            assert_eq!(other.name(), key.name());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_lazily() -> anyhow::Result<()> {
+        for path in [
+            "data/fo4_compression_test/normal.ba2",
+            "data/fo4_cubemap_test/in.ba2",
+            "data/fo4_dds_test/in.ba2",
+            "data/fo4_missing_string_table_test/in.ba2",
+            "data/fo4_next_gen_test/dx10_v8.ba2",
+            "data/fo4_next_gen_test/gnrl_v8.ba2",
+        ] {
+            let path = Path::new(path);
+            let (expected, options) = Archive::read(path)?;
+            let stream = tokio::fs::File::open(path).await?;
+            let archive = AsyncArchive::new(stream).await?;
+            assert_eq!(archive.options().format(), options.format());
+            assert_eq!(archive.options().version(), options.version());
+            compare(&expected, archive).await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_large_archives() -> anyhow::Result<()> {
+        let options = ArchiveOptions::builder().strings(true).build();
+        let compression_options = ChunkCompressionOptions::from(&options);
+        let payload: Vec<u8> = (0..0x8000u32)
+            .flat_map(|i| (i * i % 997).to_le_bytes())
+            .collect();
+
+        let mut expected = Archive::new();
+        for i in 0..8 {
+            let chunk = Chunk::from_decompressed(&payload[i * 0x1000..]);
+            let chunk = if i % 2 == 0 {
+                chunk.compress(&compression_options)?
+            } else {
+                chunk.into_owned()
+            };
+            let file: File = [chunk].into_iter().collect();
+            expected.insert(
+                ArchiveKey::from(format!("large/file{i}.bin").as_str()),
+                file,
+            );
+        }
+
+        let mut bytes = Vec::new();
+        expected.write_async(&mut bytes, &options).await?;
+        assert!(bytes.len() > 0x40000);
+        let mut expected_bytes = Vec::new();
+        expected.write(&mut expected_bytes, &options)?;
+        assert_eq!(bytes, expected_bytes);
+
+        let archive = AsyncArchive::new(Cursor::new(bytes)).await?;
+        compare(&expected, archive).await
+    }
+}
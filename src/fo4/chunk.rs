@@ -1,15 +1,11 @@
 use crate::{
+    codec::{lz4, zlib},
     containers::CompressableBytes,
     derive,
     fo4::{ArchiveOptions, CompressionFormat, CompressionLevel, Error, FileWriteOptions, Result},
 };
 use core::ops::RangeInclusive;
-use flate2::{
-    write::{ZlibDecoder, ZlibEncoder},
-    Compress, Compression,
-};
-use lzzzz::{lz4, lz4_hc};
-use std::io::Write;
+use flate2::Compression;
 
 /// See also [`ChunkCompressionOptions`](CompressionOptions).
 #[derive(Debug, Default)]
@@ -34,6 +30,15 @@ impl CompressionOptionsBuilder {
         self
     }
 
+    /// Overrides the compression level used by the codec, where `None` uses the level chosen by the [`CompressionLevel`] preset. The window size of the preset is kept either way.
+    ///
+    /// Levels range from `0..=9` for zlib, and `1..=12` for lz4. Higher levels trade speed for a better compression ratio, and are clamped to the range supported by the codec, so level 0 is the same as level 1 for lz4.
+    #[must_use]
+    pub fn compression_level_override(mut self, compression_level_override: Option<u32>) -> Self {
+        self.0.compression_level_override = compression_level_override;
+        self
+    }
+
     #[must_use]
     pub fn new() -> Self {
         Self::default()
@@ -91,11 +96,19 @@ impl From<&FileWriteOptions> for CompressionOptionsBuilder {
 /// let _ = ChunkCompressionOptions::builder()
 ///     .compression_format(CompressionFormat::LZ4)
 ///     .build();
+///
+/// // Configure for FO4/FO76, favoring speed over size
+/// let _ = ChunkCompressionOptions::builder()
+///     .compression_format(CompressionFormat::Zip)
+///     .compression_level(CompressionLevel::FO4)
+///     .compression_level_override(Some(1))
+///     .build();
 /// ```
 #[derive(Clone, Copy, Debug, Default)]
 pub struct CompressionOptions {
     pub(crate) compression_format: CompressionFormat,
     pub(crate) compression_level: CompressionLevel,
+    pub(crate) compression_level_override: Option<u32>,
 }
 
 impl CompressionOptions {
@@ -113,6 +126,11 @@ impl CompressionOptions {
     pub fn compression_level(&self) -> CompressionLevel {
         self.compression_level
     }
+
+    #[must_use]
+    pub fn compression_level_override(&self) -> Option<u32> {
+        self.compression_level_override
+    }
 }
 
 impl From<ArchiveOptions> for CompressionOptions {
@@ -159,17 +177,23 @@ impl Chunk<'_> {
         if self.is_compressed() {
             Err(Error::AlreadyCompressed)
         } else {
+            let level = options.compression_level_override;
             match options.compression_format {
-                CompressionFormat::Zip => match options.compression_level {
-                    CompressionLevel::FO4 => {
-                        self.compress_into_zlib(out, Compression::default(), 15)
-                    }
-                    CompressionLevel::FO4Xbox => {
-                        self.compress_into_zlib(out, Compression::best(), 12)
+                CompressionFormat::Zip => {
+                    let (preset, window_bits) = match options.compression_level {
+                        CompressionLevel::FO4 => (Compression::default(), zlib::MAX_WINDOW_BITS),
+                        CompressionLevel::FO4Xbox => (Compression::best(), 12),
+                        CompressionLevel::SF => (Compression::best(), zlib::MAX_WINDOW_BITS),
+                    };
+                    if !zlib::supports_window_bits(window_bits) {
+                        return Err(Error::NotImplemented);
                     }
-                    CompressionLevel::SF => self.compress_into_zlib(out, Compression::best(), 15),
-                },
-                CompressionFormat::LZ4 => self.compress_into_lz4(out),
+                    let level = level.map_or(preset, |level| Compression::new(level.min(9)));
+                    self.compress_into_zlib(out, level, window_bits)
+                }
+                CompressionFormat::LZ4 => {
+                    self.compress_into_lz4(out, level.unwrap_or(lz4::MAX_LEVEL))
+                }
             }
         }
     }
@@ -181,8 +205,8 @@ impl Chunk<'_> {
 
         out.reserve_exact(decompressed_len);
         let out_len = match options.compression_format {
-            CompressionFormat::Zip => self.decompress_into_zlib(out),
-            CompressionFormat::LZ4 => self.decompress_into_lz4(out),
+            CompressionFormat::Zip => self.decompress_into_zlib(out, decompressed_len),
+            CompressionFormat::LZ4 => self.decompress_into_lz4(out, decompressed_len),
         }?;
 
         if out_len == decompressed_len {
@@ -202,8 +226,8 @@ impl Chunk<'_> {
         }
     }
 
-    fn compress_into_lz4(&self, out: &mut Vec<u8>) -> Result<()> {
-        lz4_hc::compress_to_vec(self.as_bytes(), out, lz4_hc::CLEVEL_MAX)?;
+    fn compress_into_lz4(&self, out: &mut Vec<u8>, level: u32) -> Result<()> {
+        lz4::compress_block(self.as_bytes(), out, level)?;
         Ok(())
     }
 
@@ -213,30 +237,27 @@ impl Chunk<'_> {
         level: Compression,
         window_bits: u8,
     ) -> Result<()> {
-        let mut e = ZlibEncoder::new_with_compress(
-            out,
-            Compress::new_with_window_bits(level, true, window_bits),
-        );
-        e.write_all(self.as_bytes())?;
-        e.finish()?;
+        zlib::compress(self.as_bytes(), out, level, window_bits)?;
         Ok(())
     }
 
-    fn decompress_into_lz4(&self, out: &mut [u8]) -> Result<usize> {
-        let len = lz4::decompress(self.as_bytes(), out)?;
+    fn decompress_into_lz4(&self, out: &mut Vec<u8>, decompressed_len: usize) -> Result<usize> {
+        let len = lz4::decompress_block(self.as_bytes(), out, decompressed_len)?;
         Ok(len)
     }
 
-    fn decompress_into_zlib(&self, out: &mut Vec<u8>) -> Result<usize> {
-        let mut d = ZlibDecoder::new(out);
-        d.write_all(self.as_bytes())?;
-        Ok(d.total_out().try_into()?)
+    fn decompress_into_zlib(&self, out: &mut Vec<u8>, decompressed_len: usize) -> Result<usize> {
+        let len = zlib::decompress(self.as_bytes(), out, decompressed_len)?;
+        Ok(len)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Chunk;
+    use crate::{
+        fo4::{Chunk, ChunkCompressionOptions, CompressionFormat, CompressionLevel, Error},
+        prelude::*,
+    };
 
     #[test]
     fn default_state() {
@@ -247,4 +268,60 @@ mod tests {
         assert_eq!(c.len(), 0);
         assert_eq!(c.mips, None);
     }
+
+    #[test]
+    fn compression_levels() -> anyhow::Result<()> {
+        let payload: Vec<u8> = (0..4096u32)
+            .flat_map(|i| (i * i % 997).to_le_bytes())
+            .collect();
+        let decompressed = Chunk::from_decompressed(&payload[..]);
+        for format in [CompressionFormat::Zip, CompressionFormat::LZ4] {
+            let compress = |level| {
+                let options = ChunkCompressionOptions::builder()
+                    .compression_format(format)
+                    .compression_level_override(level)
+                    .build();
+                decompressed
+                    .compress(&options)
+                    .map(|chunk| (chunk, options))
+            };
+
+            let (fast, _) = compress(Some(0))?;
+            let (best, options) = compress(Some(u32::MAX))?;
+            let (preset, _) = compress(None)?;
+            // the pure rust lz4 backend does not support compression levels
+            if format == CompressionFormat::Zip || cfg!(feature = "lzzzz") {
+                assert!(best.len() < fast.len());
+                assert!(preset.len() < fast.len());
+            }
+            if format == CompressionFormat::LZ4 {
+                let (lowest, _) = compress(Some(1))?;
+                assert_eq!(fast.as_bytes(), lowest.as_bytes());
+            }
+            assert_eq!(best.decompress(&options)?.as_bytes(), &payload[..]);
+        }
+
+        let options = ChunkCompressionOptions::builder()
+            .compression_level_override(Some(1))
+            .build();
+        let compressed = decompressed.compress(&options)?;
+        assert_eq!(compressed.decompress(&options)?.as_bytes(), &payload[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn window_bits() -> anyhow::Result<()> {
+        let chunk = Chunk::from_decompressed(&b"hello world hello world"[..]);
+        let options = ChunkCompressionOptions::builder()
+            .compression_level(CompressionLevel::FO4Xbox)
+            .build();
+        let result = chunk.compress(&options);
+        if cfg!(feature = "zlib") {
+            // a 4KiB window is advertised in the zlib header
+            assert_eq!(result?.as_bytes()[0], 0x48);
+        } else {
+            assert!(matches!(result, Err(Error::NotImplemented)));
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,345 @@
+use crate::fo4::{
+    Archive, ArchiveKey, ArchiveOptions, ChunkCompressionOptions, CompressionFormat, File,
+    FileHash, FileHeader, Format, Result, Version,
+};
+use bstr::BString;
+use core::fmt::{self, Debug, Display, Formatter};
+use std::borrow::Cow;
+
+/// A file reported by a [`Diff`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Entry {
+    pub hash: FileHash,
+    /// The name of the file, which may be empty.
+    pub name: BString,
+}
+
+impl From<&ArchiveKey<'_>> for Entry {
+    fn from(value: &ArchiveKey<'_>) -> Self {
+        Self {
+            hash: *value.hash(),
+            name: value.name().into(),
+        }
+    }
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.name.is_empty() {
+            write!(
+                f,
+                "{:08x}\\{:08x}.{:08x}",
+                self.hash.directory, self.hash.file, self.hash.extension
+            )
+        } else {
+            write!(f, "{}", self.name)
+        }
+    }
+}
+
+/// A value which differs between archives.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Change<T> {
+    pub old: T,
+    pub new: T,
+}
+
+impl<T> Change<T>
+where
+    T: PartialEq,
+{
+    fn new(old: T, new: T) -> Option<Self> {
+        (old != new).then_some(Self { old, new })
+    }
+}
+
+/// A file whose header differs between archives.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HeaderChange {
+    pub entry: Entry,
+    pub header: Change<FileHeader>,
+}
+
+/// A file whose name differs between archives, while its hash remains the same.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rename {
+    pub hash: FileHash,
+    pub old: BString,
+    pub new: BString,
+}
+
+/// The differences between two FO4 archives.
+///
+/// File contents are compared after decompression, and irrespective of how they are chunked, so recompressing or rechunking a file does not count as a change to its contents. Changes to a file's [`FileHeader`] (i.e. the dimensions or format of a texture) are reported separately by [`headers`](Self::headers).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diff {
+    /// Files which exist only in the new archive.
+    pub added: Vec<Entry>,
+    /// Files which exist only in the old archive.
+    pub removed: Vec<Entry>,
+    /// Files which exist in both archives, but whose contents differ.
+    pub changed: Vec<Entry>,
+    /// Files which exist in both archives, but whose names differ.
+    pub renamed: Vec<Rename>,
+    /// Files which exist in both archives, but whose headers differ.
+    pub headers: Vec<HeaderChange>,
+    pub format: Option<Change<Format>>,
+    pub version: Option<Change<Version>>,
+    pub compression_format: Option<Change<CompressionFormat>>,
+}
+
+impl Diff {
+    /// Returns `true` if both archives were found to be identical.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.renamed.is_empty()
+            && self.headers.is_empty()
+            && self.format.is_none()
+            && self.version.is_none()
+            && self.compression_format.is_none()
+    }
+
+    pub(crate) fn new(
+        old: (&Archive, &ArchiveOptions),
+        new: (&Archive, &ArchiveOptions),
+    ) -> Result<Self> {
+        let (old, old_options) = old;
+        let (new, new_options) = new;
+        let old_compression: ChunkCompressionOptions = old_options.into();
+        let new_compression: ChunkCompressionOptions = new_options.into();
+
+        let mut result = Self {
+            format: Change::new(old_options.format(), new_options.format()),
+            version: Change::new(old_options.version(), new_options.version()),
+            compression_format: Change::new(
+                old_options.compression_format(),
+                new_options.compression_format(),
+            ),
+            ..Default::default()
+        };
+
+        for (new_key, new_file) in new {
+            let Some((old_key, old_file)) = old.get_key_value(new_key.hash()) else {
+                result.added.push(new_key.into());
+                continue;
+            };
+
+            if !old_key.name().is_empty()
+                && !new_key.name().is_empty()
+                && old_key.name() != new_key.name()
+            {
+                result.renamed.push(Rename {
+                    hash: *new_key.hash(),
+                    old: old_key.name().into(),
+                    new: new_key.name().into(),
+                });
+            }
+
+            if let Some(header) = Change::new(old_file.header.clone(), new_file.header.clone()) {
+                result.headers.push(HeaderChange {
+                    entry: new_key.into(),
+                    header,
+                });
+            }
+
+            if decompressed(old_file, &old_compression)?
+                != decompressed(new_file, &new_compression)?
+            {
+                result.changed.push(new_key.into());
+            }
+        }
+
+        for old_key in old.keys() {
+            if new.get(old_key.hash()).is_none() {
+                result.removed.push(old_key.into());
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+impl Display for Diff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fn option<T: Debug>(
+            f: &mut Formatter<'_>,
+            name: &str,
+            change: Option<&Change<T>>,
+        ) -> fmt::Result {
+            match change {
+                Some(change) => writeln!(f, "{name}: {:?} -> {:?}", change.old, change.new),
+                None => Ok(()),
+            }
+        }
+
+        option(f, "format", self.format.as_ref())?;
+        option(f, "version", self.version.as_ref())?;
+        option(f, "compression format", self.compression_format.as_ref())?;
+        for entry in &self.added {
+            writeln!(f, "+ {entry}")?;
+        }
+        for entry in &self.removed {
+            writeln!(f, "- {entry}")?;
+        }
+        for entry in &self.changed {
+            writeln!(f, "~ {entry}")?;
+        }
+        for rename in &self.renamed {
+            writeln!(f, "> {} -> {}", rename.old, rename.new)?;
+        }
+        for change in &self.headers {
+            writeln!(
+                f,
+                "* {}: {:?} -> {:?}",
+                change.entry, change.header.old, change.header.new
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn decompressed<'file>(
+    file: &'file File<'_>,
+    options: &ChunkCompressionOptions,
+) -> Result<Cow<'file, [u8]>> {
+    match file.as_slice() {
+        [chunk] if !chunk.is_compressed() => Ok(Cow::Borrowed(chunk.as_bytes())),
+        chunks => {
+            let mut bytes = Vec::new();
+            for chunk in chunks {
+                if chunk.is_compressed() {
+                    bytes.extend_from_slice(chunk.decompress(options)?.as_bytes());
+                } else {
+                    bytes.extend_from_slice(chunk.as_bytes());
+                }
+            }
+            Ok(Cow::Owned(bytes))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        containers::Bytes,
+        fo4::{
+            Archive, ArchiveKey, ArchiveOptions, Chunk, ChunkCompressionOptions, DX10Header, File,
+            FileHeader, Format,
+        },
+        prelude::*,
+    };
+
+    fn make(files: &[(&str, &'static [u8])]) -> Archive<'static> {
+        files
+            .iter()
+            .map(|&(name, data)| {
+                let chunk = Chunk::from_decompressed(data);
+                (ArchiveKey::from(name), [chunk].into_iter().collect())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_all_changes() -> anyhow::Result<()> {
+        let old = make(&[
+            ("same.txt", b"same"),
+            ("changed.txt", b"old"),
+            ("removed.txt", b"gone"),
+            ("renamed.txt", b"name"),
+        ]);
+        let mut new = make(&[
+            ("same.txt", b"same"),
+            ("changed.txt", b"new"),
+            ("added.txt", b"here"),
+        ]);
+
+        // names are normalized when keys are constructed, so emulate a name read from disk
+        let mut key = ArchiveKey::from(b"renamed.txt");
+        key.name = Bytes::from_owned(b"Renamed.txt".to_vec().into());
+        new.insert(
+            key,
+            [Chunk::from_decompressed(b"name")].into_iter().collect(),
+        );
+
+        let old_options = ArchiveOptions::default();
+        let new_options = ArchiveOptions::builder().format(Format::DX10).build();
+
+        let diff = old.diff(&old_options, &new, &new_options)?;
+        assert!(!diff.is_empty());
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].name, "added.txt");
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].name, "removed.txt");
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].name, "changed.txt");
+        assert_eq!(diff.renamed.len(), 1);
+        assert_eq!(diff.renamed[0].old, "renamed.txt");
+        assert_eq!(diff.renamed[0].new, "Renamed.txt");
+        assert!(diff.headers.is_empty());
+        assert!(diff.version.is_none());
+        assert!(diff.compression_format.is_none());
+        let format = diff.format.unwrap();
+        assert_eq!(format.old, Format::GNRL);
+        assert_eq!(format.new, Format::DX10);
+
+        let text = diff.to_string();
+        assert!(text.contains("format: GNRL -> DX10\n"));
+        assert!(text.contains("+ added.txt\n"));
+        assert!(text.contains("- removed.txt\n"));
+        assert!(text.contains("~ changed.txt\n"));
+        assert!(text.contains("> renamed.txt -> Renamed.txt\n"));
+
+        assert!(old.diff(&old_options, &old, &old_options)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn headers_and_chunking() -> anyhow::Result<()> {
+        const DATA: &[u8] = &[0xAB; 512];
+        let options = ArchiveOptions::builder().format(Format::DX10).build();
+        let compression: ChunkCompressionOptions = (&options).into();
+        let header = DX10Header {
+            height: 16,
+            width: 16,
+            mip_count: 1,
+            ..Default::default()
+        };
+
+        let mut old_file: File = [Chunk::from_decompressed(DATA)].into_iter().collect();
+        old_file.header = FileHeader::DX10(header);
+        let old: Archive = [(ArchiveKey::from(b"a.dds"), old_file)]
+            .into_iter()
+            .collect();
+
+        // recompress and rechunk the same data, but change the header
+        let mut new_file: File = [
+            Chunk::from_decompressed(&DATA[..256]).compress(&compression)?,
+            Chunk::from_decompressed(&DATA[256..]),
+        ]
+        .into_iter()
+        .collect();
+        new_file.header = FileHeader::DX10(DX10Header {
+            height: 32,
+            ..header
+        });
+        let new: Archive = [(ArchiveKey::from(b"a.dds"), new_file)]
+            .into_iter()
+            .collect();
+
+        let diff = old.diff(&options, &new, &options)?;
+        assert!(diff.changed.is_empty());
+        assert_eq!(diff.headers.len(), 1);
+        assert_eq!(diff.headers[0].entry.name, "a.dds");
+        assert!(matches!(diff.headers[0].header.new, FileHeader::DX10(x) if x.height == 32));
+        Ok(())
+    }
+}
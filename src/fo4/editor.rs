@@ -0,0 +1,269 @@
+use crate::{
+    fo4::{
+        archive::Section, Archive, ArchiveKey, ArchiveLayout, ArchiveOptions, File, FileHash,
+        Result,
+    },
+    layout::Storage,
+};
+use core::borrow::Borrow;
+use std::fs;
+
+/// Edits an FO4 archive file in place, without rewriting the whole file.
+///
+/// Changes are made to the archive in memory, and are written out by [`commit`](Self::commit), which appends the chunks of every new or replaced file to the end of the file, and then rewrites only the header and the file entries. If any names changed, then the string table is relocated to the end of the file as well. Chunks which the file entries would grow over are relocated to the end of the file before the file entries are rewritten, so nothing is lost if they grow.
+///
+/// The space held by chunks which were replaced or removed is not reclaimed until the archive is [`compact`](Self::compact)ed, which can be done whenever [`dead_space`](Self::dead_space) grows too large.
+///
+/// The archive is read into owned buffers, in the same manner as reading through [`Buffered`](crate::Buffered), and is read again after every commit. Nothing is memory-mapped, so chunks obtained from the editor remain valid regardless of how the file changes, but the data of every file is held in memory while editing.
+///
+/// ```rust
+/// use ba2::{
+///     fo4::{ArchiveKey, Chunk, Editor},
+///     prelude::*,
+/// };
+/// use std::fs;
+///
+/// fn example() -> Option<()> {
+///     let stream = fs::File::options()
+///         .read(true)
+///         .write(true)
+///         .open("path/to/fallout4/Data/Fallout4 - Interface.ba2")
+///         .ok()?;
+///     let mut editor = Editor::new(stream).ok()?;
+///     let chunk = Chunk::from_decompressed(b"Hello world!\n");
+///     editor.insert(ArchiveKey::from(b"hello.txt"), [chunk].into_iter().collect());
+///     editor.commit().ok()?;
+///     Some(())
+/// }
+/// ```
+pub struct Editor<'bytes> {
+    stream: fs::File,
+    archive: Archive<'bytes>,
+    options: ArchiveOptions,
+    layout: ArchiveLayout,
+    storage: Storage<Section>,
+}
+
+impl<'bytes> Editor<'bytes> {
+    /// Opens the archive within the given file, which must be both readable and writable.
+    pub fn new(stream: fs::File) -> Result<Self> {
+        let (archive, options, layout, storage) = Archive::read_in_place(&stream)?;
+        Ok(Self {
+            stream,
+            archive,
+            options,
+            layout,
+            storage,
+        })
+    }
+
+    #[must_use]
+    pub fn archive(&self) -> &Archive<'bytes> {
+        &self.archive
+    }
+
+    /// The number of bytes within the file which no longer hold any data, as of the last commit.
+    #[must_use]
+    pub fn dead_space(&self) -> usize {
+        self.storage.dead_space()
+    }
+
+    /// Inserts the given file, replacing any file with the same hash.
+    pub fn insert<K>(&mut self, key: K, file: File<'bytes>) -> Option<File<'bytes>>
+    where
+        K: Into<ArchiveKey<'bytes>>,
+    {
+        let key = key.into();
+        let renamed = self
+            .archive
+            .get_key_value(key.hash())
+            .is_none_or(|(existing, _)| existing.name() != key.name());
+        self.invalidate(key.hash(), renamed);
+        let result = self.archive.remove(key.hash());
+        self.archive.insert(key, file);
+        result
+    }
+
+    #[must_use]
+    pub fn into_inner(self) -> fs::File {
+        self.stream
+    }
+
+    #[must_use]
+    pub fn options(&self) -> &ArchiveOptions {
+        &self.options
+    }
+
+    pub fn remove<K>(&mut self, key: &K) -> Option<File<'bytes>>
+    where
+        K: Borrow<FileHash>,
+    {
+        let hash = key.borrow();
+        self.invalidate(hash, true);
+        self.archive.remove(hash)
+    }
+
+    /// Writes every change made since the last commit to the file.
+    pub fn commit(&mut self) -> Result<()> {
+        self.archive
+            .write_in_place(&mut self.stream, self.options, &self.layout, &self.storage)?;
+        self.reload()
+    }
+
+    /// Commits any changes, and then reclaims all dead space within the file, by moving chunks towards the start of the file and truncating it.
+    pub fn compact(&mut self) -> Result<()> {
+        self.commit()?;
+        let len = self.archive.compact_in_place(
+            &mut self.stream,
+            self.options,
+            &self.layout,
+            &self.storage,
+        )?;
+        self.stream.set_len(len.try_into()?)?;
+        self.reload()
+    }
+
+    /// Forgets the chunks of the given file, and the string table if `names` have changed.
+    fn invalidate(&mut self, hash: &FileHash, names: bool) {
+        self.storage.retain(|section| match section {
+            Section::Chunk(chunk, _) => chunk != hash,
+            Section::Strings => !names,
+        });
+    }
+
+    fn reload(&mut self) -> Result<()> {
+        let (archive, options, layout, storage) = Archive::read_in_place(&self.stream)?;
+        self.archive = archive;
+        self.options = options;
+        self.layout = layout;
+        self.storage = storage;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        fo4::{Archive, ArchiveKey, Chunk, Editor},
+        prelude::*,
+        Copied,
+    };
+    use anyhow::Context as _;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    fn open(name: &str) -> anyhow::Result<(PathBuf, fs::File)> {
+        let path = std::env::temp_dir().join(format!("ba2_fo4_editor_{name}.ba2"));
+        fs::copy("data/fo4_compression_test/normal.ba2", &path)?;
+        let stream = fs::File::options().read(true).write(true).open(&path)?;
+        Ok((path, stream))
+    }
+
+    #[test]
+    fn append_and_compact() -> anyhow::Result<()> {
+        let (original, _) = Archive::read(Path::new("data/fo4_compression_test/normal.ba2"))?;
+        let (path, stream) = open("append_and_compact")?;
+        let len = stream.metadata()?.len();
+        let mut editor = Editor::new(stream)?;
+        assert_eq!(editor.dead_space(), 0);
+
+        let removed = original.keys().next().context("archive was empty")?.clone();
+        editor.remove(removed.hash());
+        for i in 0..64 {
+            let chunk = Chunk::from_decompressed(b"Hello world!\n");
+            let key = ArchiveKey::from(format!("new/file{i}.txt").as_str());
+            editor.insert(key, [chunk].into_iter().collect());
+        }
+        editor.commit()?;
+        assert!(editor.dead_space() > 0);
+        let stream = editor.into_inner();
+        assert!(stream.metadata()?.len() > len);
+
+        // the file entries grew over the data which followed them, so it must have been relocated
+        let bytes = fs::read(&path)?;
+        let (copy, options) = Archive::read(Copied(&bytes))?;
+        assert_eq!(copy.len(), original.len() + 63);
+        assert!(copy.get(removed.hash()).is_none());
+        for (key, file) in &original {
+            if key.hash() == removed.hash() {
+                continue;
+            }
+            let other = copy.get(key.hash()).context("file was missing")?;
+            for (lhs, rhs) in other.iter().zip(file) {
+                assert_eq!(lhs.as_bytes(), rhs.as_bytes());
+            }
+        }
+        let (key, file) = copy
+            .get_key_value(&ArchiveKey::from(b"new/file7.txt"))
+            .context("file was missing")?;
+        assert_eq!(key.name(), "new\\file7.txt");
+        assert_eq!(file[0].as_bytes(), b"Hello world!\n");
+        assert!(options.strings());
+
+        let mut editor = Editor::new(stream)?;
+        editor.compact()?;
+        assert_eq!(editor.dead_space(), 0);
+        let stream = editor.into_inner();
+        let (compacted, _) = Archive::read(&stream)?;
+        assert!(compacted.diff(&options, &copy, &options)?.is_empty());
+
+        let mut rewritten = Vec::new();
+        compacted.write(&mut rewritten, &options)?;
+        assert_eq!(stream.metadata()?.len(), rewritten.len() as u64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn chunks_outlive_commits() -> anyhow::Result<()> {
+        let (_, stream) = open("chunks_outlive_commits")?;
+        let mut editor = Editor::new(stream)?;
+        let (removed, kept, file) = {
+            let mut files = editor.archive().iter();
+            let (removed, _) = files.next().context("archive was empty")?;
+            let (kept, file) = files.next().context("archive was too small")?;
+            (removed.clone(), kept.clone(), file.clone())
+        };
+        let expected = file[0].as_bytes().to_vec();
+
+        // the kept chunk is moved, and the file is truncated
+        editor.remove(removed.hash());
+        editor.compact()?;
+        assert_eq!(file[0].as_bytes(), expected);
+        let moved = editor
+            .archive()
+            .get(kept.hash())
+            .context("file was missing")?;
+        assert_eq!(moved[0].as_bytes(), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn replace_in_place() -> anyhow::Result<()> {
+        let (_, stream) = open("replace_in_place")?;
+        let len = stream.metadata()?.len();
+        let mut editor = Editor::new(stream)?;
+        let key = editor
+            .archive()
+            .keys()
+            .next()
+            .context("archive was empty")?
+            .clone()
+            .into_owned();
+        let chunk = Chunk::from_decompressed(b"replaced");
+        editor.insert(key.clone(), [chunk].into_iter().collect());
+        editor.commit()?;
+
+        // only the replaced chunk is appended, since names are unchanged
+        let stream = editor.into_inner();
+        assert_eq!(stream.metadata()?.len(), len + 8);
+        let (archive, _) = Archive::read(&stream)?;
+        let file = archive.get(key.hash()).context("file was missing")?;
+        assert_eq!(file[0].as_bytes(), b"replaced");
+
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
+#[cfg(feature = "directxtex")]
+use crate::containers::CompressableBytes;
 use crate::{
-    containers::CompressableBytes,
     derive,
     fo4::{
         ArchiveOptions, Chunk, ChunkCompressionOptions, CompressionFormat, CompressionLevel, Error,
@@ -10,11 +11,12 @@ use crate::{
 };
 use core::{
     fmt::{self, Debug, Display, Formatter},
-    num::NonZeroUsize,
-    ops::{Index, IndexMut, Range, RangeBounds},
-    ptr::NonNull,
-    result, slice,
+    ops::{Index, IndexMut, RangeBounds, RangeInclusive},
+    result,
 };
+#[cfg(feature = "directxtex")]
+use core::{ops::Range, ptr::NonNull, slice};
+#[cfg(feature = "directxtex")]
 use directxtex::{
     ScratchImage, TexMetadata, CP_FLAGS, DDS_FLAGS, DXGI_FORMAT, FORMAT_TYPE, TEX_DIMENSION,
     TEX_MISC_FLAG,
@@ -48,6 +50,83 @@ impl Display for CapacityError<'_> {
 
 impl error::Error for CapacityError<'_> {}
 
+/// The mips held by each chunk of a texture, captured from an existing file.
+///
+/// A layout can only be captured from a file whose chunks hold contiguous runs of mips, starting from the first mip, since the chunks of a texture are concatenated when it is written. See also [`ChunkingStrategy::Layout`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ChunkLayout {
+    /// The exclusive end of the mips held by each chunk.
+    ends: [u16; 4],
+    len: usize,
+}
+
+impl ChunkLayout {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterates over the mips held by each chunk.
+    pub fn iter(&self) -> impl Iterator<Item = RangeInclusive<u16>> + '_ {
+        let starts = [0].into_iter().chain(self.ends.iter().copied());
+        starts
+            .zip(&self.ends[..self.len])
+            .map(|(start, &end)| start..=end - 1)
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl TryFrom<&File<'_>> for ChunkLayout {
+    type Error = Error;
+
+    fn try_from(value: &File<'_>) -> Result<Self> {
+        let mut result = Self::default();
+        let mut start = 0;
+        for mips in value.iter().filter_map(|chunk| chunk.mips.as_ref()) {
+            if result.len == result.ends.len() {
+                break;
+            }
+            if *mips.start() != start || mips.end() < mips.start() {
+                return Err(Error::InvalidChunkLayout);
+            }
+            start = mips.end().checked_add(1).ok_or(Error::IntegralOverflow)?;
+            result.ends[result.len] = start;
+            result.len += 1;
+        }
+        Ok(result)
+    }
+}
+
+/// Specifies how the mips of a DX10 texture are split into [chunks](Chunk) when it is read.
+///
+/// A file can hold at most 4 chunks, so any mips which do not fit are always grouped into the last chunk.
+///
+/// Cubemaps ignore the strategy, and are always stored in a single chunk, since the faces are stored one after another, each with its own mips, so no run of mips is contiguous across all six faces.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChunkingStrategy {
+    /// Mips are grouped into at most the given number of chunks, by comparing their size against the pitch of a [`mip_chunk_width`](ReadOptionsBuilder::mip_chunk_width) by [`mip_chunk_height`](ReadOptionsBuilder::mip_chunk_height) image, in the same manner as _Archive2.exe_.
+    MaxChunks(usize),
+
+    /// Every mip is placed in a chunk of its own.
+    PerMip,
+
+    /// Mips are grouped into chunks until a chunk would reach the given number of bytes.
+    SizeThreshold(usize),
+
+    /// Mips are split into chunks in the same manner as a reference texture, i.e. one read from an existing archive.
+    Layout(ChunkLayout),
+}
+
+impl Default for ChunkingStrategy {
+    fn default() -> Self {
+        Self::MaxChunks(4)
+    }
+}
+
 /// See also [`FileReadOptions`](ReadOptions).
 #[derive(Debug, Default)]
 #[repr(transparent)]
@@ -59,6 +138,13 @@ impl ReadOptionsBuilder {
         self.0
     }
 
+    /// How the mips of a texture are split into chunks. Cubemaps are always stored in a single chunk, regardless of the strategy.
+    #[must_use]
+    pub fn chunking_strategy(mut self, chunking_strategy: ChunkingStrategy) -> Self {
+        self.0.chunking_strategy = chunking_strategy;
+        self
+    }
+
     #[must_use]
     pub fn compression_format(mut self, compression_format: CompressionFormat) -> Self {
         self.0.compression_options.compression_format = compression_format;
@@ -177,6 +263,7 @@ impl From<&ArchiveOptions> for ReadOptionsBuilder {
 #[derive(Clone, Copy, Debug)]
 pub struct ReadOptions {
     format: Format,
+    chunking_strategy: ChunkingStrategy,
     mip_chunk_width: usize,
     mip_chunk_height: usize,
     compression_options: ChunkCompressionOptions,
@@ -189,6 +276,11 @@ impl ReadOptions {
         ReadOptionsBuilder::new()
     }
 
+    #[must_use]
+    pub fn chunking_strategy(&self) -> ChunkingStrategy {
+        self.chunking_strategy
+    }
+
     #[must_use]
     pub fn compression_format(&self) -> CompressionFormat {
         self.compression_options.compression_format
@@ -224,6 +316,7 @@ impl Default for ReadOptions {
     fn default() -> Self {
         Self {
             format: Format::default(),
+            chunking_strategy: ChunkingStrategy::default(),
             mip_chunk_width: 512,
             mip_chunk_height: 512,
             compression_options: ChunkCompressionOptions::default(),
@@ -336,6 +429,7 @@ impl From<&ArchiveOptions> for WriteOptions {
 
 /// File header for DX10 archives.
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DX10 {
     pub height: u16,
     pub width: u16,
@@ -345,8 +439,31 @@ pub struct DX10 {
     pub tile_mode: u8,
 }
 
+#[cfg(feature = "directxtex")]
+impl From<&DX10> for TexMetadata {
+    fn from(value: &DX10) -> Self {
+        let is_cubemap = (value.flags & 1) != 0;
+        Self {
+            width: value.width.into(),
+            height: value.height.into(),
+            depth: 1,
+            array_size: if is_cubemap { 6 } else { 1 },
+            mip_levels: value.mip_count.into(),
+            misc_flags: if is_cubemap {
+                TEX_MISC_FLAG::TEX_MISC_TEXTURECUBE.into()
+            } else {
+                0
+            },
+            misc_flags2: 0,
+            format: u32::from(value.format).into(),
+            dimension: TEX_DIMENSION::TEX_DIMENSION_TEXTURE2D,
+        }
+    }
+}
+
 /// File header for GNMF archives.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GNMF {
     /// See [here](https://github.com/tge-was-taken/GFD-Studio/blob/dad6c2183a6ec0716c3943b71991733bfbd4649d/GFDLibrary/Textures/GNF/GNFTexture.cs#L529-L536) for more info.
     pub metadata: [u32; 8],
@@ -568,6 +685,7 @@ impl GNMF {
         1 << 24,
     }
 
+    #[cfg(feature = "directxtex")]
     fn block_size(&self) -> Result<usize> {
         // https://learn.microsoft.com/en-us/windows/win32/direct3d11/texture-block-compression-in-direct3d-11
         match self.surface_format() {
@@ -601,6 +719,7 @@ impl Default for GNMF {
     }
 }
 
+#[cfg(feature = "directxtex")]
 impl TryFrom<&TexMetadata> for GNMF {
     type Error = Error;
 
@@ -668,6 +787,7 @@ impl TryFrom<&TexMetadata> for GNMF {
     }
 }
 
+#[cfg(feature = "directxtex")]
 impl TryFrom<&GNMF> for TexMetadata {
     type Error = Error;
 
@@ -896,6 +1016,7 @@ impl TileMode {
 /// The header variant must match the archive [`Format`] when writing.
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Header {
     #[default]
     GNRL,
@@ -915,6 +1036,7 @@ impl From<GNMF> for Header {
     }
 }
 
+#[cfg(feature = "directxtex")]
 mod swizzle {
     // https://github.com/tge-was-taken/GFD-Studio/blob/dad6c2183a6ec0716c3943b71991733bfbd4649d/GFDLibrary/Textures/Swizzle/SwizzleUtilities.cs#L9
     fn morton(t: usize, sx: usize, sy: usize) -> usize {
@@ -1049,6 +1171,15 @@ impl<'bytes> File<'bytes> {
         self.chunks.clear();
     }
 
+    /// Produces the header of the dds file which [`write`](Self::write) would emit for a DX10 or GNMF texture.
+    #[cfg(feature = "directxtex")]
+    pub fn dds_header(&self) -> Result<Box<[u8]>> {
+        let header = self
+            .tex_metadata()?
+            .encode_dds_header(DDS_FLAGS::DDS_FLAGS_NONE)?;
+        Ok(header)
+    }
+
     /// # Panics
     ///
     /// Panics if [`start_bound`](RangeBounds::start_bound) exceeds [`end_bound`](RangeBounds::end_bound), or if [`end_bound`](RangeBounds::end_bound) exceeds [`len`](Self::len).
@@ -1089,6 +1220,27 @@ impl<'bytes> File<'bytes> {
         self.chunks.len()
     }
 
+    /// Finds the smallest mip of a DX10 texture whose dimensions are at least `width` by `height`, which makes it the cheapest mip to extract when producing an image of that size. If no mip is large enough, then the first mip is chosen.
+    #[cfg_attr(feature = "directxtex", doc = "")]
+    #[cfg_attr(
+        feature = "directxtex",
+        doc = "See also [`write_mips`](Self::write_mips)."
+    )]
+    pub fn mip_for_resolution(&self, width: usize, height: usize) -> Result<usize> {
+        let Header::DX10(dx10) = &self.header else {
+            return Err(Error::FormatMismatch);
+        };
+
+        let mip = (0..usize::from(dx10.mip_count))
+            .rev()
+            .find(|&mip| {
+                mip_extent(dx10.width.into(), mip) >= width
+                    && mip_extent(dx10.height.into(), mip) >= height
+            })
+            .unwrap_or_default();
+        Ok(mip)
+    }
+
     #[must_use]
     pub fn new() -> Self {
         Self::default()
@@ -1124,6 +1276,34 @@ impl<'bytes> File<'bytes> {
         self.chunks.retain_mut(f);
     }
 
+    /// Describes the texture of a DX10 or GNMF file, i.e. its dimensions, mip count, and whether it is a cubemap.
+    ///
+    /// The name of the format can be obtained by formatting [`TexMetadata::format`] using [`Debug`], e.g. `DXGI_FORMAT_BC1_UNORM`.
+    #[cfg(feature = "directxtex")]
+    pub fn tex_metadata(&self) -> Result<TexMetadata> {
+        match &self.header {
+            Header::GNRL => Err(Error::FormatMismatch),
+            Header::DX10(x) => Ok(x.into()),
+            Header::GNMF(x) => x.try_into(),
+        }
+    }
+
+    /// The number of bytes of pixel data within a DX10 or GNMF texture, across every mip and array item, as it would be written after the [`dds_header`](Self::dds_header).
+    #[cfg(feature = "directxtex")]
+    pub fn texture_len(&self) -> Result<usize> {
+        let metadata = self.tex_metadata()?;
+        let mut len = 0;
+        for mip in 0..metadata.mip_levels {
+            let pitch = metadata.format.compute_pitch(
+                mip_extent(metadata.width, mip),
+                mip_extent(metadata.height, mip),
+                CP_FLAGS::CP_FLAGS_NONE,
+            )?;
+            len += pitch.slice * metadata.array_size;
+        }
+        Ok(len)
+    }
+
     /// # Panics
     ///
     /// Panics if `index` exceeds [`len`](Self::len), or [`is_empty`](Self::is_empty).
@@ -1182,11 +1362,97 @@ impl<'bytes> File<'bytes> {
     {
         match &self.header {
             Header::GNRL => self.write_gnrl(stream, *options),
+            #[cfg(feature = "directxtex")]
             Header::DX10(x) => self.write_dx10(stream, *options, *x),
+            #[cfg(not(feature = "directxtex"))]
+            Header::DX10(_) => Err(Error::TexturesDisabled),
             Header::GNMF(_) => Err(Error::NotImplemented), //self.write_gnmf(stream, *options, x)?,
         }
     }
 
+    /// Writes a DX10 texture as a dds file which contains only the mips from `first_mip` onwards, with its header adjusted to match.
+    ///
+    /// Chunks which only hold mips before `first_mip` are skipped without being decompressed, which makes this much cheaper than writing the whole texture when only a low resolution preview is needed.
+    #[cfg(feature = "directxtex")]
+    pub fn write_mips<Out>(
+        &self,
+        stream: &mut Out,
+        options: &WriteOptions,
+        first_mip: usize,
+    ) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        let Header::DX10(dx10) = &self.header else {
+            return Err(Error::FormatMismatch);
+        };
+        let metadata = TexMetadata::from(dx10);
+        if first_mip >= metadata.mip_levels {
+            return Err(Error::MipOutOfRange {
+                mip: first_mip,
+                mip_count: metadata.mip_levels,
+            });
+        }
+
+        let header = TexMetadata {
+            width: mip_extent(metadata.width, first_mip),
+            height: mip_extent(metadata.height, first_mip),
+            mip_levels: metadata.mip_levels - first_mip,
+            ..metadata
+        }
+        .encode_dds_header(DDS_FLAGS::DDS_FLAGS_NONE)?;
+        stream.write_all(&header)?;
+
+        // every array item stores its own mip chain, so there is one range to keep per item
+        let mut ranges: Vec<Range<usize>> = Vec::with_capacity(metadata.array_size);
+        let mut offset = 0;
+        for _ in 0..metadata.array_size {
+            for mip in 0..metadata.mip_levels {
+                if mip == first_mip {
+                    ranges.push(offset..offset);
+                }
+                let pitch = metadata.format.compute_pitch(
+                    mip_extent(metadata.width, mip),
+                    mip_extent(metadata.height, mip),
+                    CP_FLAGS::CP_FLAGS_NONE,
+                )?;
+                offset += pitch.slice;
+            }
+            if let Some(range) = ranges.last_mut() {
+                range.end = offset;
+            }
+        }
+
+        let mut bytes_buffer = Vec::new();
+        let options: ChunkCompressionOptions = options.into();
+        let mut start = 0;
+        for chunk in self {
+            let chunk_start = start;
+            let chunk_end = start + chunk.decompressed_len().unwrap_or(chunk.len());
+            start = chunk_end;
+            let overlaps =
+                |range: &&Range<usize>| range.start < chunk_end && chunk_start < range.end;
+            if !ranges.iter().any(|range| overlaps(&range)) {
+                continue;
+            }
+
+            let bytes = if chunk.is_compressed() {
+                bytes_buffer.clear();
+                chunk.decompress_into(&mut bytes_buffer, &options)?;
+                &bytes_buffer
+            } else {
+                chunk.as_bytes()
+            };
+            for range in ranges.iter().filter(overlaps) {
+                let from = range.start.max(chunk_start) - chunk_start;
+                let to = range.end.min(chunk_end) - chunk_start;
+                stream.write_all(bytes.get(from..to).ok_or(Error::FormatMismatch)?)?;
+            }
+        }
+
+        Ok(())
+    }
+
     fn do_reserve(&mut self) {
         match self.len() {
             0 | 3 => self.chunks.reserve_exact(1),
@@ -1202,7 +1468,10 @@ impl<'bytes> File<'bytes> {
     {
         let mut this = match options.format {
             Format::GNRL => Self::read_gnrl(stream),
+            #[cfg(feature = "directxtex")]
             Format::DX10 => Self::read_dx10(stream, options),
+            #[cfg(not(feature = "directxtex"))]
+            Format::DX10 => Err(Error::TexturesDisabled),
             Format::GNMF => Err(Error::NotImplemented), // Self::read_gnmf(stream, options),
         }?;
 
@@ -1215,6 +1484,7 @@ impl<'bytes> File<'bytes> {
         Ok(this)
     }
 
+    #[cfg(feature = "directxtex")]
     fn make_chunks(scratch: &ScratchImage, options: &ReadOptions) -> Result<Vec<Chunk<'bytes>>> {
         let metadata = scratch.metadata();
         let images = scratch.images();
@@ -1238,61 +1508,39 @@ impl<'bytes> File<'bytes> {
             })
         };
 
-        let chunks = if let Some(images_len) = NonZeroUsize::new(images.len()) {
-            if metadata.is_cubemap() {
-                // don't chunk cubemaps
-                let chunk = chunk_from_mips(0..images_len.get())?;
-                [chunk].into_iter().collect()
-            } else {
-                let pitch = metadata.format.compute_pitch(
-                    options.mip_chunk_width,
-                    options.mip_chunk_height,
-                    CP_FLAGS::CP_FLAGS_NONE,
-                )?;
-
-                let mut v = Vec::with_capacity(4);
-                let mut size = 0;
-                let mut start = 0;
-                let mut stop = 0;
-                loop {
-                    let image = &images[stop];
-                    if size == 0 || size + image.slice_pitch < pitch.slice {
-                        size += image.slice_pitch;
-                    } else {
-                        let chunk = chunk_from_mips(start..stop)?;
-                        v.push(chunk);
-                        start = stop;
-                        size = image.slice_pitch;
-                    }
-
-                    if v.len() == 3 {
-                        break;
-                    }
-
-                    stop += 1;
-                    if stop == images_len.get() {
-                        break;
-                    }
-                }
-
-                if stop < images_len.get() {
-                    let chunk = chunk_from_mips(stop..images_len.get())?;
-                    v.push(chunk);
-                } else {
-                    let chunk = chunk_from_mips(start..stop)?;
-                    v.push(chunk);
+        let chunks = if images.is_empty() {
+            Vec::new()
+        } else if metadata.is_cubemap() {
+            // don't chunk cubemaps
+            let chunk = chunk_from_mips(0..images.len())?;
+            [chunk].into_iter().collect()
+        } else {
+            let sizes: Vec<_> = images.iter().map(|x| x.slice_pitch).collect();
+            let ranges = match options.chunking_strategy {
+                ChunkingStrategy::MaxChunks(max_chunks) => {
+                    let pitch = metadata.format.compute_pitch(
+                        options.mip_chunk_width,
+                        options.mip_chunk_height,
+                        CP_FLAGS::CP_FLAGS_NONE,
+                    )?;
+                    group_mips(&sizes, pitch.slice, max_chunks)
                 }
+                ChunkingStrategy::PerMip => group_mips(&sizes, 0, 4),
+                ChunkingStrategy::SizeThreshold(threshold) => group_mips(&sizes, threshold, 4),
+                ChunkingStrategy::Layout(layout) => split_mips(sizes.len(), &layout),
+            };
 
-                debug_assert!(v.len() <= 4);
-                v
-            }
-        } else {
-            Vec::new()
+            debug_assert!(ranges.len() <= 4);
+            ranges
+                .into_iter()
+                .map(chunk_from_mips)
+                .collect::<Result<_>>()?
         };
 
         Ok(chunks)
     }
 
+    #[cfg(feature = "directxtex")]
     fn read_dx10<In>(stream: &In, options: &ReadOptions) -> Result<Self>
     where
         In: ?Sized + Source<'bytes>,
@@ -1300,6 +1548,14 @@ impl<'bytes> File<'bytes> {
         let scratch =
             ScratchImage::load_dds(stream.as_bytes(), DDS_FLAGS::DDS_FLAGS_NONE, None, None)?;
         let meta = scratch.metadata();
+        if meta.dimension == TEX_DIMENSION::TEX_DIMENSION_TEXTURE3D || meta.depth > 1 {
+            return Err(Error::VolumeTexture(meta.depth));
+        }
+        let array_size = if meta.is_cubemap() { 6 } else { 1 };
+        if meta.array_size != array_size {
+            return Err(Error::TextureArray(meta.array_size));
+        }
+
         let header: Header = DX10 {
             height: meta.height.try_into()?,
             width: meta.width.try_into()?,
@@ -1314,6 +1570,7 @@ impl<'bytes> File<'bytes> {
         Ok(Self { chunks, header })
     }
 
+    #[cfg(feature = "directxtex")]
     #[allow(unused)]
     fn read_gnmf<In>(stream: &mut In, options: &ReadOptions) -> Result<Self>
     where
@@ -1374,32 +1631,17 @@ impl<'bytes> File<'bytes> {
         Ok([chunk].into_iter().collect())
     }
 
+    #[cfg(feature = "directxtex")]
     fn write_dx10<Out>(&self, stream: &mut Out, options: WriteOptions, dx10: DX10) -> Result<()>
     where
         Out: ?Sized + Write,
     {
-        let is_cubemap = (dx10.flags & 1) != 0;
-        let meta = TexMetadata {
-            width: dx10.width.into(),
-            height: dx10.height.into(),
-            depth: 1,
-            array_size: if is_cubemap { 6 } else { 1 },
-            mip_levels: dx10.mip_count.into(),
-            misc_flags: if is_cubemap {
-                TEX_MISC_FLAG::TEX_MISC_TEXTURECUBE.into()
-            } else {
-                0
-            },
-            misc_flags2: 0,
-            format: u32::from(dx10.format).into(),
-            dimension: TEX_DIMENSION::TEX_DIMENSION_TEXTURE2D,
-        };
-
-        let header = meta.encode_dds_header(DDS_FLAGS::DDS_FLAGS_NONE)?;
+        let header = TexMetadata::from(&dx10).encode_dds_header(DDS_FLAGS::DDS_FLAGS_NONE)?;
         stream.write_all(&header)?;
         self.write_gnrl(stream, options)
     }
 
+    #[cfg(feature = "directxtex")]
     #[allow(unused)]
     fn write_gnmf<Out>(&self, stream: &mut Out, options: WriteOptions, gnmf: &GNMF) -> Result<()>
     where
@@ -1471,6 +1713,50 @@ impl<'bytes> File<'bytes> {
     }
 }
 
+/// Groups consecutive mips of the given sizes, starting a new group whenever the current one would reach `threshold` bytes, until there are `max_chunks` groups.
+#[cfg(feature = "directxtex")]
+fn group_mips(sizes: &[usize], threshold: usize, max_chunks: usize) -> Vec<Range<usize>> {
+    let max_chunks = max_chunks.clamp(1, 4);
+    let mut ranges = Vec::with_capacity(max_chunks);
+    let mut start = 0;
+    let mut size = 0;
+    for (mip, &len) in sizes.iter().enumerate() {
+        if size != 0 && size + len >= threshold && ranges.len() + 1 < max_chunks {
+            ranges.push(start..mip);
+            start = mip;
+            size = 0;
+        }
+        size += len;
+    }
+    if start < sizes.len() {
+        ranges.push(start..sizes.len());
+    }
+    ranges
+}
+
+/// Splits `mip_count` mips in the same manner as the given layout, extending the last group to cover any mips beyond it.
+#[cfg(feature = "directxtex")]
+fn split_mips(mip_count: usize, layout: &ChunkLayout) -> Vec<Range<usize>> {
+    let mut ranges: Vec<_> = layout
+        .iter()
+        .map(|mips| usize::from(*mips.start())..usize::from(*mips.end()) + 1)
+        .take_while(|mips| mips.start < mip_count)
+        .collect();
+    match ranges.last_mut() {
+        Some(last) => last.end = mip_count,
+        None => ranges.push(0..mip_count),
+    }
+    ranges
+}
+
+/// The width or height of the given mip, for a texture with the given base width or height.
+fn mip_extent(extent: usize, mip: usize) -> usize {
+    extent
+        .checked_shr(mip.try_into().unwrap_or(u32::MAX))
+        .unwrap_or(0)
+        .max(1)
+}
+
 impl<'bytes> Index<usize> for File<'bytes> {
     type Output = Chunk<'bytes>;
 
@@ -1528,7 +1814,42 @@ impl<'bytes, 'this> IntoIterator for &'this mut File<'bytes> {
 
 #[cfg(test)]
 mod tests {
-    use crate::fo4::File;
+    #[cfg(feature = "directxtex")]
+    use crate::fo4::ChunkingStrategy;
+    use crate::{
+        fo4::{Chunk, ChunkLayout, Error, File, FileReadOptions, FileWriteOptions, Format},
+        prelude::*,
+        CompressionResult,
+    };
+    use core::ops::RangeInclusive;
+    #[cfg(feature = "directxtex")]
+    use core::slice;
+    #[cfg(feature = "directxtex")]
+    use directxtex::{Image, ScratchImage, DDS_FLAGS};
+    use std::path::Path;
+
+    #[cfg(feature = "directxtex")]
+    fn read_dx10(path: &str) -> anyhow::Result<File<'static>> {
+        read_dx10_with(path, ChunkingStrategy::default())
+    }
+
+    #[cfg(feature = "directxtex")]
+    fn read_dx10_with(
+        path: &str,
+        chunking_strategy: ChunkingStrategy,
+    ) -> anyhow::Result<File<'static>> {
+        let options = FileReadOptions::builder()
+            .format(Format::DX10)
+            .chunking_strategy(chunking_strategy)
+            .compression_result(CompressionResult::Compressed)
+            .build();
+        Ok(File::read(Path::new(path), &options)?)
+    }
+
+    #[cfg(feature = "directxtex")]
+    fn pixels(image: &Image) -> &[u8] {
+        unsafe { slice::from_raw_parts(image.pixels, image.slice_pitch) }
+    }
 
     #[test]
     fn default_state() {
@@ -1537,4 +1858,210 @@ mod tests {
         assert!(f.as_slice().is_empty());
         assert!(!f.is_full());
     }
+
+    #[cfg(feature = "directxtex")]
+    #[test]
+    fn unsupported_textures() {
+        let read = |name: &str| read_dx10(&format!("data/fo4_unsupported_texture_test/{name}"));
+        let error = |result: anyhow::Result<File>| result.unwrap_err().downcast::<Error>().unwrap();
+        assert!(matches!(error(read("array.dds")), Error::TextureArray(2)));
+        assert!(matches!(error(read("volume.dds")), Error::VolumeTexture(4)));
+    }
+
+    #[cfg(feature = "directxtex")]
+    #[test]
+    fn tex_metadata() -> anyhow::Result<()> {
+        let options = FileWriteOptions::default();
+        for (path, width, height, cubemap) in [
+            ("data/fo4_chunk_test/test.dds", 1024, 1024, false),
+            ("data/fo4_cubemap_test/blacksky_e.dds", 512, 512, true),
+        ] {
+            let file = read_dx10(path)?;
+            let metadata = file.tex_metadata()?;
+            assert_eq!(metadata.width, width);
+            assert_eq!(metadata.height, height);
+            assert_eq!(metadata.is_cubemap(), cubemap);
+
+            let mut dds = Vec::new();
+            file.write(&mut dds, &options)?;
+            let header = file.dds_header()?;
+            assert!(dds.starts_with(&header));
+            assert_eq!(header.len() + file.texture_len()?, dds.len());
+        }
+
+        let file = read_dx10("data/fo4_chunk_test/test.dds")?;
+        let metadata = file.tex_metadata()?;
+        assert_eq!(metadata.mip_levels, 11);
+        assert_eq!(format!("{:?}", metadata.format), "DXGI_FORMAT_BC1_UNORM");
+
+        assert!(matches!(
+            File::new().tex_metadata(),
+            Err(Error::FormatMismatch)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn chunk_layouts() -> anyhow::Result<()> {
+        let file = |mips: &[RangeInclusive<u16>]| -> File<'static> {
+            mips.iter()
+                .map(|mips| {
+                    let mut chunk = Chunk::from_decompressed(&b""[..]);
+                    chunk.mips = Some(mips.clone());
+                    chunk
+                })
+                .collect()
+        };
+
+        let layout = ChunkLayout::try_from(&file(&[0..=0, 1..=3, 4..=10]))?;
+        assert_eq!(layout.len(), 3);
+        assert_eq!(layout.iter().collect::<Vec<_>>(), [0..=0, 1..=3, 4..=10]);
+        assert!(ChunkLayout::try_from(&File::new())?.is_empty());
+
+        for mips in [
+            &[0..=0, 2..=3][..],
+            &[1..=2],
+            &[0..=3, 3..=4],
+            &[0..=2, RangeInclusive::new(3, 1)],
+        ] {
+            assert!(matches!(
+                ChunkLayout::try_from(&file(mips)),
+                Err(Error::InvalidChunkLayout)
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "directxtex")]
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn chunking_strategies() -> anyhow::Result<()> {
+        let mips = |file: &File| -> Vec<_> {
+            file.iter()
+                .map(|chunk| chunk.mips.clone().unwrap())
+                .collect()
+        };
+        let path = "data/fo4_chunk_test/test.dds";
+
+        let file = read_dx10_with(path, ChunkingStrategy::PerMip)?;
+        assert_eq!(mips(&file), [0..=0, 1..=1, 2..=2, 3..=10]);
+        let layout = ChunkLayout::try_from(&file)?;
+        assert_eq!(layout.iter().collect::<Vec<_>>(), mips(&file));
+
+        let file = read_dx10_with(path, ChunkingStrategy::MaxChunks(2))?;
+        assert_eq!(mips(&file), [0..=0, 1..=10]);
+
+        let file = read_dx10_with(path, ChunkingStrategy::SizeThreshold(usize::MAX))?;
+        assert_eq!(mips(&file), [0..=10]);
+
+        let file = read_dx10_with(path, ChunkingStrategy::Layout(layout))?;
+        assert_eq!(mips(&file), [0..=0, 1..=1, 2..=2, 3..=10]);
+
+        // a layout with more mips than the texture is truncated, and one with fewer is extended
+        assert_eq!(super::split_mips(2, &layout), [0..1, 1..2]);
+        assert_eq!(super::split_mips(11, &ChunkLayout::default()), [0..11]);
+
+        let file = read_dx10_with(
+            "data/fo4_cubemap_test/blacksky_e.dds",
+            ChunkingStrategy::PerMip,
+        )?;
+        assert_eq!(mips(&file), [0..=9]);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "directxtex")]
+    #[test]
+    fn mip_for_resolution() -> anyhow::Result<()> {
+        // 1024x1024, with 11 mips
+        let file = read_dx10("data/fo4_chunk_test/test.dds")?;
+        assert_eq!(file.mip_for_resolution(1024, 512)?, 0);
+        assert_eq!(file.mip_for_resolution(256, 1)?, 2);
+        assert_eq!(file.mip_for_resolution(200, 100)?, 2);
+        assert_eq!(file.mip_for_resolution(1, 1)?, 10);
+        assert_eq!(file.mip_for_resolution(4096, 4096)?, 0);
+        assert!(matches!(
+            File::new().mip_for_resolution(1, 1),
+            Err(Error::FormatMismatch)
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "directxtex")]
+    #[test]
+    fn write_mips() -> anyhow::Result<()> {
+        let options = FileWriteOptions::default();
+        for path in [
+            "data/fo4_chunk_test/test.dds",
+            "data/fo4_cubemap_test/blacksky_e.dds",
+        ] {
+            let file = read_dx10(path)?;
+            let mut full = Vec::new();
+            file.write(&mut full, &options)?;
+            let full = ScratchImage::load_dds(&full, DDS_FLAGS::DDS_FLAGS_NONE, None, None)?;
+            let metadata = full.metadata();
+
+            for first_mip in [0, 1, 2, metadata.mip_levels - 1] {
+                let mut partial = Vec::new();
+                file.write_mips(&mut partial, &options, first_mip)?;
+                let partial =
+                    ScratchImage::load_dds(&partial, DDS_FLAGS::DDS_FLAGS_NONE, None, None)?;
+                let partial_metadata = partial.metadata();
+                assert_eq!(partial_metadata.width, (metadata.width >> first_mip).max(1));
+                assert_eq!(
+                    partial_metadata.height,
+                    (metadata.height >> first_mip).max(1)
+                );
+                assert_eq!(partial_metadata.array_size, metadata.array_size);
+                assert_eq!(partial_metadata.mip_levels, metadata.mip_levels - first_mip);
+
+                for item in 0..metadata.array_size {
+                    for mip in 0..partial_metadata.mip_levels {
+                        let lhs = partial.image(mip, item, 0).unwrap();
+                        let rhs = full.image(mip + first_mip, item, 0).unwrap();
+                        assert_eq!(pixels(lhs), pixels(rhs), "{path}: {item}/{mip}");
+                    }
+                }
+            }
+
+            assert!(matches!(
+                file.write_mips(&mut Vec::new(), &options, metadata.mip_levels),
+                Err(Error::MipOutOfRange { .. })
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "directxtex"))]
+    #[test]
+    fn textures_disabled() -> anyhow::Result<()> {
+        use crate::fo4::DX10Header;
+        use std::fs;
+
+        let path = Path::new("data/fo4_chunk_test/test.dds");
+        let options = FileReadOptions::builder().format(Format::DX10).build();
+        assert!(matches!(
+            File::read(path, &options),
+            Err(Error::TexturesDisabled)
+        ));
+
+        // general files do not need directxtex
+        let options = FileReadOptions::builder()
+            .format(Format::GNRL)
+            .compression_result(CompressionResult::Compressed)
+            .build();
+        let mut file = File::read(path, &options)?;
+        let mut bytes = Vec::new();
+        file.write(&mut bytes, &FileWriteOptions::default())?;
+        assert_eq!(bytes, fs::read(path)?);
+
+        file.header = DX10Header::default().into();
+        assert!(matches!(
+            file.write(&mut Vec::new(), &FileWriteOptions::default()),
+            Err(Error::TexturesDisabled)
+        ));
+        Ok(())
+    }
 }
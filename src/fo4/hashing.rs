@@ -4,6 +4,7 @@ use bstr::{BStr, BString, ByteSlice as _};
 // archives aren't sorted in any particular order, so we can just default these
 /// The underlying hash object used to uniquely identify objects within the archive.
 #[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 pub struct Hash {
     /// The file's stem crc.
@@ -0,0 +1,109 @@
+use crate::fo4::{Archive, ArchiveKey, ArchiveOptions, FileHeader};
+use core::ops::RangeInclusive;
+use serde::{Deserialize, Serialize};
+
+/// A chunk within a [`ManifestFile`](File).
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Chunk {
+    pub mips: Option<RangeInclusive<u16>>,
+    /// Whether or not the chunk is stored compressed.
+    pub compressed: bool,
+}
+
+/// A file within a [`Manifest`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct File {
+    pub key: ArchiveKey<'static>,
+    pub header: FileHeader,
+    pub chunks: Vec<Chunk>,
+}
+
+/// Describes everything about a FO4 archive, except for the contents of its files.
+///
+/// A manifest can be stored alongside an archive's extracted files, so that the archive can be rebuilt identically later. Keys are stored with both their hash and their name (as a byte string), so that neither is lost, even if the archive was written without a string table.
+///
+/// ```rust
+/// use ba2::{
+///     fo4::{Archive, ArchiveKey, ArchiveOptions, Chunk, File, Manifest},
+///     prelude::*,
+/// };
+///
+/// fn example() -> Option<()> {
+///     let chunk = Chunk::from_decompressed(b"Hello world!\n");
+///     let file: File = [chunk].into_iter().collect();
+///     let archive: Archive = [(ArchiveKey::from(b"hello.txt"), file)]
+///         .into_iter()
+///         .collect();
+///     let manifest = Manifest::new(&archive, &ArchiveOptions::default());
+///     let json = serde_json::to_string(&manifest).ok()?;
+///     let manifest: Manifest = serde_json::from_str(&json).ok()?;
+///     assert_eq!(manifest.files[0].key.name(), "hello.txt");
+///     Some(())
+/// }
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Manifest {
+    pub options: ArchiveOptions,
+    pub files: Vec<File>,
+}
+
+impl Manifest {
+    /// Describes the given archive, which was read using the given options.
+    #[must_use]
+    pub fn new(archive: &Archive<'_>, options: &ArchiveOptions) -> Self {
+        let files = archive
+            .iter()
+            .map(|(key, file)| File {
+                key: key.clone().into_owned(),
+                header: file.header.clone(),
+                chunks: file
+                    .iter()
+                    .map(|chunk| Chunk {
+                        mips: chunk.mips.clone(),
+                        compressed: chunk.is_compressed(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        Self {
+            options: *options,
+            files,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        fo4::{Archive, FileHeader, Manifest},
+        prelude::*,
+    };
+    use std::path::Path;
+
+    #[test]
+    fn round_trip() -> anyhow::Result<()> {
+        let path = Path::new("data/fo4_chunk_test/in.ba2");
+        let (archive, options) = Archive::read(path)?;
+        let manifest = Manifest::new(&archive, &options);
+        let json = serde_json::to_string(&manifest)?;
+        let copy: Manifest = serde_json::from_str(&json)?;
+
+        assert_eq!(copy.options.format(), options.format());
+        assert_eq!(copy.options.version(), options.version());
+        assert_eq!(copy.options.strings(), options.strings());
+        assert_eq!(copy.files.len(), archive.len());
+        for (file, (key, original)) in copy.files.iter().zip(&archive) {
+            assert_eq!(file.key.hash(), key.hash());
+            assert_eq!(file.key.name(), key.name());
+            assert_eq!(file.header, original.header);
+            assert!(matches!(file.header, FileHeader::DX10(_)));
+            assert_eq!(file.chunks.len(), original.len());
+            for (chunk, original) in file.chunks.iter().zip(original) {
+                assert_eq!(chunk.mips, original.mips);
+                assert_eq!(chunk.compressed, original.is_compressed());
+            }
+        }
+
+        Ok(())
+    }
+}
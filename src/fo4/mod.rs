@@ -48,30 +48,58 @@
 //! ```
 
 mod archive;
+#[cfg(feature = "async")]
+mod async_archive;
 mod chunk;
+mod diff;
+mod editor;
 mod file;
 mod hashing;
+#[cfg(feature = "serde")]
+mod manifest;
+mod patch;
 
 pub use self::{
     archive::{
-        Archive, Key as ArchiveKey, Options as ArchiveOptions,
+        Archive, Key as ArchiveKey, Layout as ArchiveLayout, Options as ArchiveOptions,
         OptionsBuilder as ArchiveOptionsBuilder,
     },
     chunk::{
         Chunk, CompressionOptions as ChunkCompressionOptions,
         CompressionOptionsBuilder as ChunkCompressionOptionsBuilder,
     },
+    diff::{
+        Change as DiffChange, Diff, Entry as DiffEntry, HeaderChange as DiffHeaderChange,
+        Rename as DiffRename,
+    },
+    editor::Editor,
     file::{
-        CapacityError as FileCapacityError, File, Header as FileHeader,
-        ReadOptions as FileReadOptions, ReadOptionsBuilder as FileReadOptionsBuilder,
-        WriteOptions as FileWriteOptions, WriteOptionsBuilder as FileWriteOptionsBuilder,
-        DX10 as DX10Header, GNMF as GNMFHeader,
+        CapacityError as FileCapacityError, ChunkLayout, ChunkingStrategy, File,
+        Header as FileHeader, ReadOptions as FileReadOptions,
+        ReadOptionsBuilder as FileReadOptionsBuilder, WriteOptions as FileWriteOptions,
+        WriteOptionsBuilder as FileWriteOptionsBuilder, DX10 as DX10Header, GNMF as GNMFHeader,
     },
     hashing::{hash_file, hash_file_in_place, FileHash, Hash},
+    patch::{
+        Addition as PatchAddition, Base as PatchBase, Patch, Replacement as PatchReplacement,
+        Target as PatchTarget,
+    },
 };
 
+#[cfg(feature = "async")]
+pub use self::async_archive::AsyncArchive;
+
+#[cfg(feature = "serde")]
+pub use self::manifest::{Chunk as ManifestChunk, File as ManifestFile, Manifest};
+
+#[cfg(feature = "lz4_flex")]
+use crate::Lz4Error;
+use bstr::BString;
 use core::num::TryFromIntError;
+#[cfg(feature = "directxtex")]
 use directxtex::HResultError;
+#[cfg(feature = "lzzzz")]
+use lzzzz::lz4f;
 use std::io;
 
 #[non_exhaustive]
@@ -83,12 +111,22 @@ pub enum Error {
     #[error("can not decompress the given file because it is already decompressed")]
     AlreadyDecompressed,
 
+    #[error("the operation was cancelled")]
+    Cancelled,
+
     #[error("buffer failed to decompress to the expected size... expected {expected} bytes, but got {actual} bytes")]
     DecompressionSizeMismatch { expected: usize, actual: usize },
 
+    #[cfg(feature = "directxtex")]
     #[error("error while working with a dds file")]
     DX10(#[from] HResultError),
 
+    #[error("a file already exists at the given path: {0}")]
+    FileExists(BString),
+
+    #[error("no file exists at the given path: {0}")]
+    FileNotFound(BString),
+
     #[error("attempted to write in a format that does not match a file/chunk")]
     FormatMismatch,
 
@@ -98,12 +136,20 @@ pub enum Error {
     #[error("an operation on an integer would have truncated and corrupted data")]
     IntegralTruncation,
 
+    #[error(
+        "the chunks of the file do not hold contiguous runs of mips, starting from the first mip"
+    )]
+    InvalidChunkLayout,
+
     #[error("invalid sentinel read from chunk: {0}")]
     InvalidChunkSentinel(u32),
 
     #[error("invalid chunk size read from file header: {0}")]
     InvalidChunkSize(u16),
 
+    #[error("invalid compression format read from patch header: {0}")]
+    InvalidCompressionFormat(u8),
+
     #[error("invalid format read from archive header: {0}")]
     InvalidFormat(u32),
 
@@ -116,11 +162,37 @@ pub enum Error {
     #[error(transparent)]
     Io(#[from] io::Error),
 
+    #[cfg(feature = "lzzzz")]
     #[error(transparent)]
-    LZ4(#[from] lzzzz::Error),
+    LZ4(#[from] lz4f::Error),
+
+    #[cfg(feature = "lz4_flex")]
+    #[error(transparent)]
+    Lz4Flex(#[from] Lz4Error),
+
+    #[error("file is present in more than one archive: {0}")]
+    MergeConflict(BString),
+
+    #[error("mip {mip} is out of range for a texture with {mip_count} mips")]
+    MipOutOfRange { mip: usize, mip_count: usize },
 
     #[error("support for this feature is not yet implemented")]
     NotImplemented,
+
+    #[error("patch was made against a different archive than the given one")]
+    PatchBaseMismatch,
+
+    #[error("patch does not apply to the given archive: {0}")]
+    PatchMismatch(BString),
+
+    #[error("texture arrays can not be stored in an archive, but the texture has {0} array items")]
+    TextureArray(usize),
+
+    #[error("converting textures to or from dds files requires the `directxtex` feature")]
+    TexturesDisabled,
+
+    #[error("volume textures can not be stored in an archive, but the texture has a depth of {0}")]
+    VolumeTexture(usize),
 }
 
 impl From<TryFromIntError> for Error {
@@ -133,6 +205,7 @@ pub type Result<T> = core::result::Result<T, Error>;
 
 /// A list of all compression methods supported by the ba2 format.
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CompressionFormat {
     /// The default compression format, compatible with all games that utilize the ba2 format.
     #[default]
@@ -171,12 +244,15 @@ impl CompressionLevel {
 
 /// Represents the file format for an archive.
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Format {
     /// A GNRL archive can contain any kind of file.
     #[default]
     GNRL,
 
     /// A DX10 archive can only contain .dds files (Microsoft DirectX).
+    ///
+    /// Only 2D textures and cubemaps are supported, since the file header has no room for a depth or an array size. Volume textures and texture arrays are rejected when read.
     DX10,
 
     /// A GNMF archive can only contain .gnf files (Sony GNM).
@@ -186,6 +262,7 @@ pub enum Format {
 /// Indicates the version of an archive.
 #[allow(non_camel_case_types)]
 #[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Version {
     /// Initial format introduced in Fallout 4.
     #[default]
@@ -0,0 +1,737 @@
+use crate::{
+    containers::Bytes,
+    derive,
+    fo4::{
+        Archive, ArchiveKey, ArchiveOptions, Chunk, CompressionFormat, DX10Header, Diff, DiffEntry,
+        Error, File, FileHeader, Format, GNMFHeader, Hash, Result, Version,
+    },
+    io::{Endian, Sink, Source},
+    protocols::WString,
+};
+use bstr::BStr;
+use flate2::Crc;
+use std::{collections::BTreeMap, io::Write};
+
+mod constants {
+    use crate::cc;
+
+    pub(crate) const MAGIC: u32 = cc::make_four(b"BA2P");
+    pub(crate) const VERSION: u32 = 1;
+
+    pub(crate) const GNRL: u8 = 0;
+    pub(crate) const DX10: u8 = 1;
+    pub(crate) const GNMF: u8 = 2;
+
+    pub(crate) const ZIP: u8 = 0;
+    pub(crate) const LZ4: u8 = 1;
+}
+
+/// A fingerprint of the archive which a [`Patch`] was made against.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Base {
+    /// The options the base archive was read with.
+    pub options: ArchiveOptions,
+    /// The crc32 of the hash and checksum of every file within the base archive, in hash order.
+    pub checksum: u32,
+}
+
+impl Base {
+    #[must_use]
+    pub fn new(archive: &Archive<'_>, options: &ArchiveOptions) -> Self {
+        let mut crc = Crc::new();
+        for (key, file) in archive {
+            let hash = key.hash();
+            crc.update(&hash.file.to_le_bytes());
+            crc.update(&hash.extension.to_le_bytes());
+            crc.update(&hash.directory.to_le_bytes());
+            crc.update(&checksum(file).to_le_bytes());
+        }
+        Self {
+            options: *options,
+            checksum: crc.sum(),
+        }
+    }
+
+    /// Returns `true` if the given archive, read using the given options, is the one this fingerprint was taken of.
+    #[must_use]
+    pub fn matches(&self, archive: &Archive<'_>, options: &ArchiveOptions) -> bool {
+        let other = Self::new(archive, options);
+        self.checksum == other.checksum
+            && self.options.format() == other.options.format()
+            && self.options.version() == other.options.version()
+            && self.options.compression_format() == other.options.compression_format()
+            && self.options.strings() == other.options.strings()
+    }
+}
+
+/// A file which must exist within the base archive for a [`Patch`] to apply.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Target {
+    pub entry: DiffEntry,
+    /// The crc32 of the file's chunks, exactly as they are stored within the base archive.
+    pub checksum: u32,
+}
+
+impl Target {
+    fn new(key: &ArchiveKey<'_>, file: &File<'_>) -> Self {
+        Self {
+            entry: key.into(),
+            checksum: checksum(file),
+        }
+    }
+}
+
+/// A file which replaces a [`Target`] within the base archive.
+#[derive(Clone, Debug)]
+pub struct Replacement<'bytes> {
+    pub target: Target,
+    pub key: ArchiveKey<'bytes>,
+    /// The new data for the file, or `None` if only its name has changed.
+    pub file: Option<File<'bytes>>,
+}
+
+/// A file which does not exist within the base archive.
+#[derive(Clone, Debug)]
+pub struct Addition<'bytes> {
+    pub key: ArchiveKey<'bytes>,
+    pub file: File<'bytes>,
+}
+
+/// A set of changes which transforms one revision of an FO4 archive into another.
+///
+/// Only the data for files which were added or replaced is stored, exactly as it was stored in the new archive (i.e. compressed chunks remain compressed). Every file which is removed or replaced is identified by a checksum of its chunks in the base archive, and the base archive as a whole is identified by a [`Base`] fingerprint of its options and of every file within it. [`apply`](Self::apply) verifies all of them before making any changes, so that a patch can not be applied to the wrong base.
+///
+/// ```rust
+/// use ba2::{
+///     prelude::*,
+///     fo4::{Archive, ArchiveKey, ArchiveOptions, Chunk, File, Patch},
+///     Borrowed,
+/// };
+///
+/// fn example() -> Option<()> {
+///     let options = ArchiveOptions::default();
+///     let file = |data: &'static [u8]| -> File {
+///         [Chunk::from_decompressed(data)].into_iter().collect()
+///     };
+///     let mut old: Archive = [(ArchiveKey::from(b"hello.txt"), file(b"Hello world!\n"))]
+///         .into_iter()
+///         .collect();
+///     let new: Archive = [(ArchiveKey::from(b"hello.txt"), file(b"Goodbye world!\n"))]
+///         .into_iter()
+///         .collect();
+///
+///     let patch = Patch::new(&old, &options, &new, &options).ok()?;
+///     let mut stream = Vec::new();
+///     patch.write(&mut stream).ok()?;
+///
+///     let patch = Patch::read(Borrowed(&stream)).ok()?;
+///     let mut options = options;
+///     patch.apply(&mut old, &mut options).ok()?;
+///     assert!(old.diff(&options, &new, &options).ok()?.is_empty());
+///     Some(())
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Patch<'bytes> {
+    pub base: Base,
+    /// The options of the new archive.
+    pub options: ArchiveOptions,
+    pub removed: Vec<Target>,
+    pub replaced: Vec<Replacement<'bytes>>,
+    pub added: Vec<Addition<'bytes>>,
+}
+
+impl crate::Sealed for Patch<'_> {}
+
+type ReadResult<T> = T;
+derive::reader!(Patch => ReadResult);
+
+impl<'bytes> Patch<'bytes> {
+    /// Makes a patch which transforms `old` into `new`, where each archive is paired with the options it was read with.
+    ///
+    /// Files are compared using [`Archive::diff`]. If the archives differ in compression format, then any file which has a compressed chunk in either archive is also replaced, since its compressed data would not be valid in the other.
+    pub fn new(
+        old: &Archive<'_>,
+        old_options: &ArchiveOptions,
+        new: &Archive<'bytes>,
+        new_options: &ArchiveOptions,
+    ) -> Result<Self> {
+        let diff = Diff::new((old, old_options), (new, new_options))?;
+
+        // maps each replaced file to whether or not its data must be replaced as well
+        let mut replaced = BTreeMap::new();
+        for entry in diff
+            .changed
+            .iter()
+            .chain(diff.headers.iter().map(|x| &x.entry))
+        {
+            replaced.insert(entry.hash, true);
+        }
+        for rename in &diff.renamed {
+            replaced.entry(rename.hash).or_insert(false);
+        }
+        if diff.compression_format.is_some() {
+            let compressed = |file: &File<'_>| file.iter().any(Chunk::is_compressed);
+            for (key, file) in new {
+                if let Some(old_file) = old.get(key.hash()) {
+                    if compressed(file) || compressed(old_file) {
+                        replaced.insert(*key.hash(), true);
+                    }
+                }
+            }
+        }
+
+        let removed = diff
+            .removed
+            .iter()
+            .filter_map(|entry| old.get_key_value(&entry.hash))
+            .map(|(key, file)| Target::new(key, file))
+            .collect();
+        let replaced = replaced
+            .into_iter()
+            .filter_map(|(hash, data)| {
+                let (old_key, old_file) = old.get_key_value(&hash)?;
+                let (key, file) = new.get_key_value(&hash)?;
+                Some(Replacement {
+                    target: Target::new(old_key, old_file),
+                    key: key.clone(),
+                    file: data.then(|| file.clone()),
+                })
+            })
+            .collect();
+        let added = diff
+            .added
+            .iter()
+            .filter_map(|entry| new.get_key_value(&entry.hash))
+            .map(|(key, file)| Addition {
+                key: key.clone(),
+                file: file.clone(),
+            })
+            .collect();
+
+        Ok(Self {
+            base: Base::new(old, old_options),
+            options: *new_options,
+            removed,
+            replaced,
+            added,
+        })
+    }
+
+    /// Applies the patch to the given archive, which was read using the given options.
+    ///
+    /// Every removed or replaced file must exist within the archive with a matching checksum, and every added file must not exist within the archive, otherwise [`Error::PatchMismatch`] is returned. Then the archive and its options must match the [`Base`] of the patch, otherwise [`Error::PatchBaseMismatch`] is returned. In either case, the archive is left untouched.
+    pub fn apply(self, archive: &mut Archive<'bytes>, options: &mut ArchiveOptions) -> Result<()> {
+        let targets = self
+            .removed
+            .iter()
+            .chain(self.replaced.iter().map(|x| &x.target));
+        for target in targets {
+            match archive.get(&target.entry.hash) {
+                Some(file) if checksum(file) == target.checksum => (),
+                _ => return Err(Error::PatchMismatch(target.entry.to_string().into())),
+            }
+        }
+        for addition in &self.added {
+            if archive.get(addition.key.hash()).is_some() {
+                let entry = DiffEntry::from(&addition.key);
+                return Err(Error::PatchMismatch(entry.to_string().into()));
+            }
+        }
+        if !self.base.matches(archive, options) {
+            return Err(Error::PatchBaseMismatch);
+        }
+
+        for target in &self.removed {
+            archive.remove(&target.entry.hash);
+        }
+        for replacement in self.replaced {
+            if let Some((key, file)) = archive.remove_entry(&replacement.target.entry.hash) {
+                let key = if replacement.key.name().is_empty() {
+                    key
+                } else {
+                    replacement.key
+                };
+                archive.insert(key, replacement.file.unwrap_or(file));
+            }
+        }
+        for addition in self.added {
+            archive.insert(addition.key, addition.file);
+        }
+
+        *options = self.options;
+        Ok(())
+    }
+
+    /// Returns `true` if the patch makes no changes to the files of an archive.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.replaced.is_empty() && self.added.is_empty()
+    }
+
+    pub fn write<Out>(&self, stream: &mut Out) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        let mut sink = Sink::new(stream);
+        sink.write(&(constants::MAGIC, constants::VERSION), Endian::Little)?;
+        Self::write_options(&mut sink, self.base.options)?;
+        sink.write(&self.base.checksum, Endian::Little)?;
+        Self::write_options(&mut sink, self.options)?;
+
+        let len: u32 = self.removed.len().try_into()?;
+        sink.write(&len, Endian::Little)?;
+        for target in &self.removed {
+            Self::write_target(&mut sink, target)?;
+        }
+
+        let len: u32 = self.replaced.len().try_into()?;
+        sink.write(&len, Endian::Little)?;
+        for replacement in &self.replaced {
+            Self::write_target(&mut sink, &replacement.target)?;
+            Self::write_key(&mut sink, replacement.key.hash(), replacement.key.name())?;
+            match &replacement.file {
+                Some(file) => {
+                    sink.write(&1u8, Endian::Little)?;
+                    Self::write_file(&mut sink, file)?;
+                }
+                None => sink.write(&0u8, Endian::Little)?,
+            }
+        }
+
+        let len: u32 = self.added.len().try_into()?;
+        sink.write(&len, Endian::Little)?;
+        for addition in &self.added {
+            Self::write_key(&mut sink, addition.key.hash(), addition.key.name())?;
+            Self::write_file(&mut sink, &addition.file)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_chunk<Out>(sink: &mut Sink<Out>, chunk: &Chunk<'bytes>) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        match &chunk.mips {
+            Some(mips) => sink.write(&(1u8, *mips.start(), *mips.end()), Endian::Little)?,
+            None => sink.write(&(0u8, 0u16, 0u16), Endian::Little)?,
+        }
+        match chunk.decompressed_len() {
+            Some(len) => {
+                let len: u32 = len.try_into()?;
+                sink.write(&(1u8, len), Endian::Little)?;
+            }
+            None => sink.write(&(0u8, 0u32), Endian::Little)?,
+        }
+        let len: u32 = chunk.len().try_into()?;
+        sink.write(&len, Endian::Little)?;
+        sink.write_bytes(chunk.as_bytes())?;
+        Ok(())
+    }
+
+    fn write_file<Out>(sink: &mut Sink<Out>, file: &File<'bytes>) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        match &file.header {
+            FileHeader::GNRL => sink.write(&constants::GNRL, Endian::Little)?,
+            FileHeader::DX10(x) => {
+                sink.write(
+                    &(
+                        constants::DX10,
+                        x.height,
+                        x.width,
+                        x.mip_count,
+                        x.format,
+                        x.flags,
+                        x.tile_mode,
+                    ),
+                    Endian::Little,
+                )?;
+            }
+            FileHeader::GNMF(x) => {
+                sink.write(&constants::GNMF, Endian::Little)?;
+                sink.write(&x.metadata, Endian::Little)?;
+            }
+        }
+
+        let len: u8 = file.len().try_into()?;
+        sink.write(&len, Endian::Little)?;
+        for chunk in file {
+            Self::write_chunk(sink, chunk)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_key<Out>(sink: &mut Sink<Out>, hash: &Hash, name: &BStr) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        sink.write(&(hash.file, hash.extension, hash.directory), Endian::Little)?;
+        sink.write_protocol::<WString>(name, Endian::Little)?;
+        Ok(())
+    }
+
+    fn write_options<Out>(sink: &mut Sink<Out>, options: ArchiveOptions) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        let format = match options.format() {
+            Format::GNRL => constants::GNRL,
+            Format::DX10 => constants::DX10,
+            Format::GNMF => constants::GNMF,
+        };
+        let compression_format: u8 = match options.compression_format() {
+            CompressionFormat::Zip => constants::ZIP,
+            CompressionFormat::LZ4 => constants::LZ4,
+        };
+        sink.write(
+            &(
+                format,
+                options.version() as u32,
+                compression_format,
+                u8::from(options.strings()),
+            ),
+            Endian::Little,
+        )?;
+        Ok(())
+    }
+
+    fn write_target<Out>(sink: &mut Sink<Out>, target: &Target) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        Self::write_key(sink, &target.entry.hash, target.entry.name.as_ref())?;
+        sink.write(&target.checksum, Endian::Little)?;
+        Ok(())
+    }
+
+    fn do_read<In>(source: &mut In) -> Result<ReadResult<Self>>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        let (magic, version): (u32, u32) = source.read(Endian::Little)?;
+        if magic != constants::MAGIC {
+            return Err(Error::InvalidMagic(magic));
+        }
+        if version != constants::VERSION {
+            return Err(Error::InvalidVersion(version));
+        }
+
+        let base = Base {
+            options: Self::read_options(source)?,
+            checksum: source.read(Endian::Little)?,
+        };
+        let options = Self::read_options(source)?;
+
+        let len: u32 = source.read(Endian::Little)?;
+        let mut removed = Vec::new();
+        for _ in 0..len {
+            removed.push(Self::read_target(source)?);
+        }
+
+        let len: u32 = source.read(Endian::Little)?;
+        let mut replaced = Vec::new();
+        for _ in 0..len {
+            let target = Self::read_target(source)?;
+            let key = Self::read_key(source)?;
+            let has_file: u8 = source.read(Endian::Little)?;
+            let file = if has_file == 0 {
+                None
+            } else {
+                Some(Self::read_file(source)?)
+            };
+            replaced.push(Replacement { target, key, file });
+        }
+
+        let len: u32 = source.read(Endian::Little)?;
+        let mut added = Vec::new();
+        for _ in 0..len {
+            let key = Self::read_key(source)?;
+            let file = Self::read_file(source)?;
+            added.push(Addition { key, file });
+        }
+
+        Ok(Self {
+            base,
+            options,
+            removed,
+            replaced,
+            added,
+        })
+    }
+
+    fn read_options<In>(source: &mut In) -> Result<ArchiveOptions>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        let (format, version, compression_format, strings): (u8, u32, u8, u8) =
+            source.read(Endian::Little)?;
+        let format = match format {
+            constants::GNRL => Format::GNRL,
+            constants::DX10 => Format::DX10,
+            constants::GNMF => Format::GNMF,
+            _ => return Err(Error::InvalidFormat(format.into())),
+        };
+        let version = match version {
+            1 => Version::v1,
+            2 => Version::v2,
+            3 => Version::v3,
+            7 => Version::v7,
+            8 => Version::v8,
+            _ => return Err(Error::InvalidVersion(version)),
+        };
+        let compression_format = match compression_format {
+            constants::ZIP => CompressionFormat::Zip,
+            constants::LZ4 => CompressionFormat::LZ4,
+            _ => return Err(Error::InvalidCompressionFormat(compression_format)),
+        };
+        Ok(ArchiveOptions::builder()
+            .format(format)
+            .version(version)
+            .compression_format(compression_format)
+            .strings(strings != 0)
+            .build())
+    }
+
+    fn read_chunk<In>(source: &mut In) -> Result<Chunk<'bytes>>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        let (has_mips, mip_first, mip_last): (u8, u16, u16) = source.read(Endian::Little)?;
+        let (compressed, decompressed_len, len): (u8, u32, u32) = source.read(Endian::Little)?;
+        let bytes = source.read_bytes(len as usize)?;
+        let decompressed_len = (compressed != 0).then_some(decompressed_len as usize);
+        Ok(Chunk {
+            bytes: bytes.into_compressable(decompressed_len),
+            mips: (has_mips != 0).then_some(mip_first..=mip_last),
+        })
+    }
+
+    fn read_file<In>(source: &mut In) -> Result<File<'bytes>>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        let format: u8 = source.read(Endian::Little)?;
+        let header = match format {
+            constants::GNRL => FileHeader::GNRL,
+            constants::DX10 => {
+                let (height, width, mip_count, format, flags, tile_mode) =
+                    source.read(Endian::Little)?;
+                DX10Header {
+                    height,
+                    width,
+                    mip_count,
+                    format,
+                    flags,
+                    tile_mode,
+                }
+                .into()
+            }
+            constants::GNMF => {
+                let metadata = source.read(Endian::Little)?;
+                GNMFHeader { metadata }.into()
+            }
+            _ => return Err(Error::InvalidFormat(format.into())),
+        };
+
+        let len: u8 = source.read(Endian::Little)?;
+        let mut chunks = Vec::with_capacity(len.into());
+        for _ in 0..len {
+            chunks.push(Self::read_chunk(source)?);
+        }
+
+        Ok(File { chunks, header })
+    }
+
+    fn read_key<In>(source: &mut In) -> Result<ArchiveKey<'bytes>>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        let (file, extension, directory) = source.read(Endian::Little)?;
+        let hash = Hash {
+            file,
+            extension,
+            directory,
+        };
+        let name: Bytes<'bytes> = source.read_protocol::<WString>(Endian::Little)?;
+        Ok(ArchiveKey {
+            hash: hash.into(),
+            name,
+        })
+    }
+
+    fn read_target<In>(source: &mut In) -> Result<Target>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        let key = Self::read_key(source)?;
+        let checksum = source.read(Endian::Little)?;
+        Ok(Target {
+            entry: (&key).into(),
+            checksum,
+        })
+    }
+}
+
+fn checksum(file: &File<'_>) -> u32 {
+    let mut crc = Crc::new();
+    for chunk in file {
+        crc.update(chunk.as_bytes());
+    }
+    crc.sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        containers::Bytes,
+        fo4::{
+            Archive, ArchiveKey, ArchiveOptions, Chunk, ChunkCompressionOptions, DX10Header, Error,
+            File, FileHeader, Format, Patch,
+        },
+        prelude::*,
+        Borrowed,
+    };
+
+    fn make(files: &[(&str, &'static [u8])]) -> Archive<'static> {
+        files
+            .iter()
+            .map(|&(name, data)| {
+                let chunk = Chunk::from_decompressed(data);
+                (ArchiveKey::from(name), [chunk].into_iter().collect())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() -> anyhow::Result<()> {
+        let old_options = ArchiveOptions::default();
+        let new_options = ArchiveOptions::builder().format(Format::DX10).build();
+        let compression = ChunkCompressionOptions::from(&new_options);
+        let mut old = make(&[
+            ("same.dds", b"same"),
+            ("changed.dds", b"old"),
+            ("removed.dds", b"gone"),
+            ("renamed.dds", b"name"),
+        ]);
+        let mut new = make(&[
+            ("same.dds", b"same"),
+            ("added.dds", b"here"),
+            ("renamed.dds", b"name"),
+        ]);
+
+        let mut file: File = [Chunk::from_decompressed(&[b'x'; 64][..]).compress(&compression)?]
+            .into_iter()
+            .collect();
+        file.header = FileHeader::DX10(DX10Header {
+            height: 8,
+            width: 8,
+            mip_count: 1,
+            ..Default::default()
+        });
+        new.insert(ArchiveKey::from(b"changed.dds"), file);
+
+        // names are normalized when keys are constructed, so emulate a name read from disk
+        let (mut key, file) = new.remove_entry(&ArchiveKey::from(b"renamed.dds")).unwrap();
+        key.name = Bytes::from_owned(b"Renamed.dds".to_vec().into());
+        new.insert(key, file);
+
+        let patch = Patch::new(&old, &old_options, &new, &new_options)?;
+        assert!(!patch.is_empty());
+        assert_eq!(patch.removed.len(), 1);
+        assert_eq!(patch.removed[0].entry.name, "removed.dds");
+        assert_eq!(patch.added.len(), 1);
+        assert_eq!(patch.replaced.len(), 2);
+        assert!(patch
+            .replaced
+            .iter()
+            .any(|x| x.key.name() == "Renamed.dds" && x.file.is_none()));
+
+        let mut stream = Vec::new();
+        patch.write(&mut stream)?;
+        let patch = Patch::read(Borrowed(&stream))?;
+
+        let mut options = old_options;
+        patch.apply(&mut old, &mut options)?;
+        assert_eq!(options.format(), Format::DX10);
+        assert!(old.diff(&options, &new, &new_options)?.is_empty());
+        let file = old.get(&ArchiveKey::from(b"changed.dds")).unwrap();
+        assert!(file[0].is_compressed());
+        assert!(matches!(file.header, FileHeader::DX10(x) if x.height == 8));
+        let (key, _) = old
+            .get_key_value(&ArchiveKey::from(b"renamed.dds"))
+            .unwrap();
+        assert_eq!(key.name(), "Renamed.dds");
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_compression_format() -> anyhow::Result<()> {
+        let options = ArchiveOptions::default();
+        let archive = make(&[("a.txt", b"a")]);
+        let patch = Patch::new(&Archive::default(), &options, &archive, &options)?;
+        let mut stream = Vec::new();
+        patch.write(&mut stream)?;
+
+        // magic, version, format, and archive version precede the compression format
+        stream[13] = 2;
+        match Patch::read(Borrowed(&stream)) {
+            Err(Error::InvalidCompressionFormat(2)) => (),
+            Err(err) => return Err(err.into()),
+            Ok(_) => anyhow::bail!("patch should not have been read"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_base() -> anyhow::Result<()> {
+        let options = ArchiveOptions::default();
+        let old = make(&[("a.txt", b"old")]);
+        let new = make(&[("a.txt", b"new")]);
+        let patch = Patch::new(&old, &options, &new, &options)?;
+
+        let mut base = make(&[("a.txt", b"???")]);
+        let mut base_options = options;
+        match patch.apply(&mut base, &mut base_options) {
+            Err(Error::PatchMismatch(name)) => assert_eq!(name, "a.txt"),
+            Err(err) => return Err(err.into()),
+            Ok(()) => anyhow::bail!("patch should not have applied"),
+        }
+        let file = base.get(&ArchiveKey::from(b"a.txt")).unwrap();
+        assert_eq!(file[0].as_bytes(), b"???");
+
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_base_for_additions() -> anyhow::Result<()> {
+        let options = ArchiveOptions::default();
+        let old = make(&[("a.txt", b"a")]);
+        let new = make(&[("a.txt", b"a"), ("b.txt", b"b")]);
+        let patch = Patch::new(&old, &options, &new, &options)?;
+        assert!(patch.removed.is_empty() && patch.replaced.is_empty());
+
+        for (mut base, mut base_options) in [
+            (make(&[("a.txt", b"?")]), options),
+            (make(&[("a.txt", b"a"), ("c.txt", b"c")]), options),
+            (
+                make(&[("a.txt", b"a")]),
+                ArchiveOptions::builder().strings(true).build(),
+            ),
+        ] {
+            match patch.clone().apply(&mut base, &mut base_options) {
+                Err(Error::PatchBaseMismatch) => (),
+                Err(err) => return Err(err.into()),
+                Ok(()) => anyhow::bail!("patch should not have applied"),
+            }
+            assert!(base.get(&ArchiveKey::from(b"b.txt")).is_none());
+        }
+
+        Ok(())
+    }
+}
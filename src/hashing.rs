@@ -28,12 +28,23 @@ const fn build_lookup_table() -> [u8; 256] {
 }
 
 #[must_use]
-fn map_byte(b: u8) -> u8 {
+pub(crate) fn map_byte(b: u8) -> u8 {
     const LUT: [u8; 256] = build_lookup_table();
     LUT[b as usize]
 }
 
 pub(crate) fn normalize_path(path: &mut BString) {
+    normalize_case_and_separators(path);
+    if path.is_empty() || path.len() >= 260 {
+        path.clear();
+        path.push(b'.');
+    }
+}
+
+/// Lowercases the path, and uses `\` as its separator, without any leading or trailing separators.
+///
+/// Unlike [`normalize_path`], paths which are empty or too long to be hashed are kept as they are, so that they can still be told apart.
+pub(crate) fn normalize_case_and_separators(path: &mut BString) {
     for b in path.iter_mut() {
         *b = map_byte(*b);
     }
@@ -45,11 +56,6 @@ pub(crate) fn normalize_path(path: &mut BString) {
     while path.first().is_some_and(|&x| x == b'\\') {
         path.remove(0);
     }
-
-    if path.is_empty() || path.len() >= 260 {
-        path.clear();
-        path.push(b'.');
-    }
 }
 
 #[cfg(test)]
@@ -3,9 +3,11 @@ use core::{mem, ops::Range};
 use memmap2::{Mmap, MmapOptions};
 use std::{
     fs::File,
-    io::{self, Write},
+    io::{self, Read, Seek, SeekFrom, Write},
     sync::Arc,
 };
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncSeek, AsyncSeekExt as _};
 
 #[derive(Clone, Copy)]
 pub(crate) enum Endian {
@@ -24,11 +26,28 @@ pub(crate) trait Source<'bytes> {
 
     fn read_into(&mut self, buf: &mut [u8]) -> io::Result<()>;
 
+    /// Whether the bytes in `pos..pos + len` are only fetched once reading has finished, and so can not be inspected while reading.
+    #[must_use]
+    fn is_deferred(&self, _pos: usize, _len: usize) -> bool {
+        false
+    }
+
+    /// Reads the data of a file, which may be deferred.
+    fn read_blob(&mut self, len: usize) -> io::Result<Bytes<'bytes>> {
+        self.read_bytes(len)
+    }
+
     fn seek_absolute(&mut self, pos: usize) -> io::Result<()>;
 
     #[must_use]
     fn stream_position(&self) -> usize;
 
+    /// The length of the whole input, which is longer than [`as_bytes`](Self::as_bytes) if only part of it is held in memory.
+    #[must_use]
+    fn stream_len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
     fn read<T>(&mut self, endian: Endian) -> io::Result<T>
     where
         T: BinaryReadable<'bytes, Item = T>,
@@ -183,6 +202,303 @@ impl TryFrom<&File> for MappedSource {
 
 make_sourceable!(MappedSource, 'static);
 
+/// A source which reads from a file using positional reads, rather than by memory-mapping it.
+///
+/// Small reads are served from a window which is read ahead of them, while byte blobs are read into buffers of their own. The window is the only part of the file which is held, and so is all that [`as_bytes`](Source::as_bytes) returns.
+pub(crate) struct BufferedSource<'file> {
+    file: &'file File,
+    len: usize,
+    pos: usize,
+    window: Vec<u8>,
+    window_pos: usize,
+}
+
+impl BufferedSource<'_> {
+    /// The size of the window which is read ahead of small reads.
+    const WINDOW: usize = 0x1_0000;
+
+    /// Fills `buf` with the bytes at `pos`, without going through the cursor of the file, which may be shared with others.
+    #[cfg(unix)]
+    fn read_at(&self, pos: usize, buf: &mut [u8]) -> io::Result<()> {
+        use std::os::unix::fs::FileExt as _;
+        self.file.read_exact_at(buf, pos as u64)
+    }
+
+    /// Fills `buf` with the bytes at `pos`, without going through the cursor of the file, which may be shared with others.
+    ///
+    /// Windows still moves the cursor past each read, but every read is positioned on its own, so it can not race with others.
+    #[cfg(windows)]
+    fn read_at(&self, pos: usize, mut buf: &mut [u8]) -> io::Result<()> {
+        use std::os::windows::fs::FileExt as _;
+        let mut pos = pos as u64;
+        while !buf.is_empty() {
+            match self.file.seek_read(buf, pos) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    pos += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the bytes in `pos..pos + len` within the window, if they lie entirely inside it.
+    #[must_use]
+    fn windowed(&self, pos: usize, len: usize) -> Option<&[u8]> {
+        let start = pos.checked_sub(self.window_pos)?;
+        self.window.get(start..start + len)
+    }
+}
+
+impl<'file> TryFrom<&'file File> for BufferedSource<'file> {
+    type Error = io::Error;
+
+    fn try_from(value: &'file File) -> Result<Self, Self::Error> {
+        let len = value.metadata()?.len();
+        Ok(Self {
+            file: value,
+            len: usize::try_from(len).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?,
+            pos: 0,
+            window: Vec::new(),
+            window_pos: 0,
+        })
+    }
+}
+
+impl Source<'static> for BufferedSource<'_> {
+    fn as_bytes(&self) -> &[u8] {
+        &self.window
+    }
+
+    fn read_bytes(&mut self, len: usize) -> io::Result<Bytes<'static>> {
+        let start = self.pos;
+        let stop = start + len;
+        if stop > self.len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let bytes = if let Some(bytes) = self.windowed(start, len) {
+            bytes.into()
+        } else {
+            let mut bytes = vec![0; len];
+            self.read_at(start, &mut bytes)?;
+            bytes.into_boxed_slice()
+        };
+        self.pos = stop;
+        Ok(Bytes::from_owned(bytes))
+    }
+
+    fn read_bytes_to_end(&mut self) -> Bytes<'static> {
+        self.read_bytes(self.len - self.pos).unwrap_or_default()
+    }
+
+    fn read_into(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let start = self.pos;
+        let stop = start + buf.len();
+        if stop > self.len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        if let Some(bytes) = self.windowed(start, buf.len()) {
+            buf.copy_from_slice(bytes);
+        } else if buf.len() >= Self::WINDOW {
+            self.read_at(start, buf)?;
+        } else {
+            let mut window = mem::take(&mut self.window);
+            window.resize(Self::WINDOW.min(self.len - start), 0);
+            self.read_at(start, &mut window)?;
+            self.window = window;
+            self.window_pos = start;
+            buf.copy_from_slice(&self.window[..buf.len()]);
+        }
+        self.pos = stop;
+        Ok(())
+    }
+
+    fn seek_absolute(&mut self, pos: usize) -> io::Result<()> {
+        if pos > self.len {
+            Err(io::ErrorKind::UnexpectedEof.into())
+        } else {
+            self.pos = pos;
+            Ok(())
+        }
+    }
+
+    fn stream_position(&self) -> usize {
+        self.pos
+    }
+
+    fn stream_len(&self) -> usize {
+        self.len
+    }
+}
+
+/// A source over the parts of a stream which have been fetched so far, used to read an archive's index without fetching the data of its files.
+///
+/// The data of files, i.e. blobs, which have not been entirely fetched are deferred, and are read as empty. Any other read which strays outside of the fetched regions fails, and marks the source as starved, so that more of the stream can be fetched before reading again.
+#[cfg(feature = "async")]
+pub(crate) struct DeferredSource {
+    /// Sorted, non-overlapping, and non-adjacent spans of the stream, as `(offset, bytes)`.
+    regions: Vec<(usize, Vec<u8>)>,
+    len: usize,
+    pos: usize,
+    starved: Option<usize>,
+}
+
+#[cfg(feature = "async")]
+impl DeferredSource {
+    /// The size of the smallest region fetched at once.
+    const WINDOW: usize = 0x1_0000;
+
+    /// Fetches the region which starts at `pos`, which grows with the amount already fetched.
+    async fn fetch<In>(&mut self, stream: &mut In, pos: usize) -> io::Result<()>
+    where
+        In: ?Sized + AsyncRead + AsyncSeek + Unpin,
+    {
+        let fetched: usize = self.regions.iter().map(|(_, bytes)| bytes.len()).sum();
+        let next = self
+            .regions
+            .iter()
+            .map(|&(offset, _)| offset)
+            .find(|&offset| offset > pos)
+            .unwrap_or(self.len);
+        let stop = (pos + fetched.max(Self::WINDOW)).min(next);
+        let bytes = read_range(stream, pos, stop - pos).await?;
+
+        self.regions.push((pos, bytes.into_vec()));
+        self.regions.sort_by_key(|&(offset, _)| offset);
+        let mut merged: Vec<(usize, Vec<u8>)> = Vec::with_capacity(self.regions.len());
+        for (offset, bytes) in self.regions.drain(..) {
+            match merged.last_mut() {
+                Some((last, prev)) if *last + prev.len() == offset => prev.extend(bytes),
+                _ => merged.push((offset, bytes)),
+            }
+        }
+        self.regions = merged;
+        Ok(())
+    }
+
+    /// Finds the fetched bytes in `pos..pos + len`.
+    ///
+    /// Returns `Ok(None)` if none of them have been fetched, and `Err(missing)` if only some of them have, where `missing` is the first byte which has not.
+    fn find(&self, pos: usize, len: usize) -> Result<Option<&[u8]>, usize> {
+        let stop = pos + len;
+        for (offset, bytes) in &self.regions {
+            let end = offset + bytes.len();
+            if (*offset..end).contains(&pos) {
+                return if stop <= end {
+                    Ok(Some(&bytes[pos - offset..stop - offset]))
+                } else {
+                    Err(end)
+                };
+            } else if pos < *offset && *offset < stop {
+                return Err(pos);
+            }
+        }
+        Ok(None)
+    }
+
+    #[must_use]
+    fn new(len: usize) -> Self {
+        Self {
+            regions: Vec::new(),
+            len,
+            pos: 0,
+            starved: None,
+        }
+    }
+
+    fn starve(&mut self, missing: usize) -> io::Error {
+        self.starved = Some(missing);
+        io::ErrorKind::UnexpectedEof.into()
+    }
+}
+
+#[cfg(feature = "async")]
+impl Source<'static> for DeferredSource {
+    fn as_bytes(&self) -> &[u8] {
+        match self.regions.first() {
+            Some((0, bytes)) => bytes,
+            _ => &[],
+        }
+    }
+
+    fn is_deferred(&self, pos: usize, len: usize) -> bool {
+        !matches!(self.find(pos, len), Ok(Some(_)))
+    }
+
+    fn read_blob(&mut self, len: usize) -> io::Result<Bytes<'static>> {
+        let start = self.pos;
+        if self.is_deferred(start, len) {
+            if start + len > self.len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.pos += len;
+            Ok(Bytes::default())
+        } else {
+            self.read_bytes(len)
+        }
+    }
+
+    fn read_bytes(&mut self, len: usize) -> io::Result<Bytes<'static>> {
+        let start = self.pos;
+        let stop = start + len;
+        if stop > self.len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        match self.find(start, len) {
+            Ok(Some(bytes)) => {
+                let bytes = Bytes::from_owned(bytes.into());
+                self.pos = stop;
+                Ok(bytes)
+            }
+            Ok(None) => Err(self.starve(start)),
+            Err(missing) => Err(self.starve(missing)),
+        }
+    }
+
+    fn read_bytes_to_end(&mut self) -> Bytes<'static> {
+        self.read_bytes(self.len - self.pos).unwrap_or_default()
+    }
+
+    fn read_into(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let start = self.pos;
+        let stop = start + buf.len();
+        if stop > self.len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        match self.find(start, buf.len()) {
+            Ok(Some(bytes)) => buf.copy_from_slice(bytes),
+            Ok(None) => return Err(self.starve(start)),
+            Err(missing) => return Err(self.starve(missing)),
+        }
+        self.pos = stop;
+        Ok(())
+    }
+
+    fn seek_absolute(&mut self, pos: usize) -> io::Result<()> {
+        if pos > self.len {
+            Err(io::ErrorKind::UnexpectedEof.into())
+        } else {
+            self.pos = pos;
+            Ok(())
+        }
+    }
+
+    fn stream_position(&self) -> usize {
+        self.pos
+    }
+
+    fn stream_len(&self) -> usize {
+        self.len
+    }
+}
+
 pub(crate) trait BinaryReadable<'bytes> {
     type Item;
 
@@ -538,3 +854,64 @@ where
         self.stream.write_all(bytes)
     }
 }
+
+/// Copies `len` bytes within `stream`, from `from` to `to`, where `to` must not come after `from`.
+pub(crate) fn copy_within<S>(stream: &mut S, from: usize, to: usize, len: usize) -> io::Result<()>
+where
+    S: ?Sized + Read + Write + Seek,
+{
+    debug_assert!(to <= from, "copying forward would overwrite the source");
+    let mut buf = vec![0; len.min(0x10_0000)];
+    let mut copied = 0;
+    while copied < len {
+        let chunk = &mut buf[..(len - copied).min(0x10_0000)];
+        stream.seek(SeekFrom::Start((from + copied) as u64))?;
+        stream.read_exact(chunk)?;
+        stream.seek(SeekFrom::Start((to + copied) as u64))?;
+        stream.write_all(chunk)?;
+        copied += chunk.len();
+    }
+    Ok(())
+}
+
+/// Reads `len` bytes from `stream`, starting at `offset`.
+#[cfg(feature = "async")]
+pub(crate) async fn read_range<In>(
+    stream: &mut In,
+    offset: usize,
+    len: usize,
+) -> io::Result<Box<[u8]>>
+where
+    In: ?Sized + AsyncRead + AsyncSeek + Unpin,
+{
+    let mut bytes = vec![0; len];
+    stream.seek(SeekFrom::Start(offset as u64)).await?;
+    stream.read_exact(&mut bytes).await?;
+    Ok(bytes.into())
+}
+
+/// Reads an archive's index from `stream` using `f`, fetching only as much of the stream as is needed to do so.
+///
+/// Whenever `f` runs out of bytes, more of the stream is fetched, and `f` is called again from the start.
+#[cfg(feature = "async")]
+pub(crate) async fn read_deferred<In, F, T, E>(stream: &mut In, mut f: F) -> Result<T, E>
+where
+    In: ?Sized + AsyncRead + AsyncSeek + Unpin,
+    F: FnMut(&mut DeferredSource) -> Result<T, E>,
+    E: From<io::Error>,
+{
+    let len = stream.seek(SeekFrom::End(0)).await?;
+    let len = usize::try_from(len).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+    let mut source = DeferredSource::new(len);
+    source.fetch(stream, 0).await?;
+    loop {
+        source.pos = 0;
+        match f(&mut source) {
+            Ok(result) => return Ok(result),
+            Err(err) => match source.starved.take() {
+                Some(pos) => source.fetch(stream, pos).await?,
+                None => return Err(err),
+            },
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::BTreeMap;
+
+/// A blob of data within a [`Region`], along with the bytes which preceded it on disk.
+#[derive(Clone, Debug)]
+struct Blob<Id> {
+    id: Id,
+    padding: Vec<u8>,
+    shared_with: Option<Id>,
+}
+
+/// Records the order in which blobs were laid out within some region of an archive, so that the region can be reproduced later.
+///
+/// Blobs which started at the same offset as the blob before them (i.e. data sharing) are remembered as such, and any bytes which did not belong to a blob (i.e. padding) are preserved as-is.
+#[derive(Clone, Debug)]
+pub(crate) struct Region<Id> {
+    blobs: Vec<Blob<Id>>,
+    trailing: Vec<u8>,
+}
+
+impl<Id> Default for Region<Id> {
+    fn default() -> Self {
+        Self {
+            blobs: Vec::new(),
+            trailing: Vec::new(),
+        }
+    }
+}
+
+impl<Id> Region<Id>
+where
+    Id: Clone + Ord,
+{
+    /// Captures the layout of `items` (given as `(id, offset, len)`), which were read from `bytes`, starting at `start`.
+    ///
+    /// Blobs which overlap one another, or which lie outside of the region, can not be reproduced, and are treated as if they were new.
+    #[must_use]
+    pub(crate) fn capture(bytes: &[u8], start: usize, mut items: Vec<(Id, usize, usize)>) -> Self {
+        items.sort_by_key(|&(_, offset, _)| offset);
+        let mut blobs = Vec::with_capacity(items.len());
+        let mut cursor = start;
+        let mut last: Option<(Id, usize, usize)> = None;
+        for (id, offset, len) in items {
+            if let Some((first, last_offset, last_len)) = &last {
+                if *last_offset == offset && *last_len == len {
+                    blobs.push(Blob {
+                        id,
+                        padding: Vec::new(),
+                        shared_with: Some(first.clone()),
+                    });
+                    continue;
+                }
+            }
+
+            let end = offset.saturating_add(len);
+            if offset < cursor || end > bytes.len() {
+                continue;
+            }
+
+            blobs.push(Blob {
+                id: id.clone(),
+                padding: bytes[cursor..offset].to_vec(),
+                shared_with: None,
+            });
+            cursor = end;
+            last = Some((id, offset, len));
+        }
+
+        Self {
+            blobs,
+            trailing: bytes.get(cursor..).unwrap_or_default().to_vec(),
+        }
+    }
+
+    /// Whether any bytes which did not belong to a blob were captured.
+    #[cfg(test)]
+    #[must_use]
+    pub(crate) fn is_padded(&self) -> bool {
+        !self.trailing.is_empty() || self.blobs.iter().any(|x| !x.padding.is_empty())
+    }
+
+    /// Places `items` (given as `(id, len)`) within the region, starting at `start`.
+    ///
+    /// Items which were captured are placed in their original order, with their original padding, and items which were not are placed after them, in the order given. A captured blob which shared its data is only shared again if `same` reports that both blobs are still identical.
+    #[must_use]
+    pub(crate) fn plan<I, F>(&self, start: usize, items: I, mut same: F) -> Plan<'_, Id>
+    where
+        I: IntoIterator<Item = (Id, usize)>,
+        F: FnMut(&Id, &Id) -> bool,
+    {
+        let items: Vec<_> = items.into_iter().collect();
+        let lens: BTreeMap<_, _> = items.iter().cloned().collect();
+        let mut offsets = BTreeMap::new();
+        let mut slots = Vec::with_capacity(items.len());
+        let mut cursor = start;
+
+        for blob in &self.blobs {
+            let Some(&len) = lens.get(&blob.id) else {
+                continue;
+            };
+            if offsets.contains_key(&blob.id) {
+                continue;
+            }
+
+            if let Some(first) = &blob.shared_with {
+                if let Some(&offset) = offsets.get(first) {
+                    if same(first, &blob.id) {
+                        offsets.insert(blob.id.clone(), offset);
+                        continue;
+                    }
+                }
+            }
+
+            cursor += blob.padding.len();
+            offsets.insert(blob.id.clone(), cursor);
+            slots.push(Slot {
+                id: blob.id.clone(),
+                padding: &blob.padding,
+            });
+            cursor += len;
+        }
+
+        for (id, len) in items {
+            if offsets.contains_key(&id) {
+                continue;
+            }
+            offsets.insert(id.clone(), cursor);
+            slots.push(Slot { id, padding: &[] });
+            cursor += len;
+        }
+
+        Plan {
+            slots,
+            offsets,
+            trailing: &self.trailing,
+            end: cursor + self.trailing.len(),
+        }
+    }
+}
+
+/// A blob which must be written, in order, to reproduce a [`Region`].
+pub(crate) struct Slot<'layout, Id> {
+    pub(crate) id: Id,
+    pub(crate) padding: &'layout [u8],
+}
+
+/// The placement of every blob within a [`Region`].
+pub(crate) struct Plan<'layout, Id> {
+    pub(crate) slots: Vec<Slot<'layout, Id>>,
+    pub(crate) offsets: BTreeMap<Id, usize>,
+    pub(crate) trailing: &'layout [u8],
+    /// The offset just past the end of the region.
+    pub(crate) end: usize,
+}
+
+impl<Id> Plan<'_, Id>
+where
+    Id: Ord,
+{
+    /// The offset at which the given blob was placed.
+    #[must_use]
+    pub(crate) fn offset(&self, id: &Id) -> usize {
+        self.offsets[id]
+    }
+}
+
+/// The order in which items were laid out within an archive.
+pub(crate) struct Order<Id>(BTreeMap<Id, usize>);
+
+impl<Id> Order<Id>
+where
+    Id: Ord,
+{
+    #[must_use]
+    pub(crate) fn new<I>(order: I) -> Self
+    where
+        I: IntoIterator<Item = Id>,
+    {
+        let mut positions = BTreeMap::new();
+        for (position, id) in order.into_iter().enumerate() {
+            positions.entry(id).or_insert(position);
+        }
+        Self(positions)
+    }
+
+    /// Stably sorts `items` into this order, placing any items which do not appear in it last.
+    pub(crate) fn arrange<T, F>(&self, items: &mut [T], mut key: F)
+    where
+        F: FnMut(&T) -> Id,
+    {
+        items.sort_by_key(|x| self.0.get(&key(x)).copied().unwrap_or(usize::MAX));
+    }
+}
+
+/// Tracks the blobs of an archive file which is being edited in place, and whose contents on disk are still current.
+#[derive(Clone, Debug)]
+pub(crate) struct Storage<Id> {
+    blobs: BTreeMap<Id, (usize, usize)>,
+    /// The offset just past the end of the index, i.e. where blobs may begin.
+    start: usize,
+    /// The length of the file.
+    end: usize,
+}
+
+impl<Id> Storage<Id>
+where
+    Id: Clone + Ord,
+{
+    /// Tracks `blobs` (given as `(id, offset, len)`), which were read from a file of length `end`, whose index ends at `start`.
+    #[must_use]
+    pub(crate) fn new(blobs: Vec<(Id, usize, usize)>, start: usize, end: usize) -> Self {
+        Self {
+            blobs: blobs
+                .into_iter()
+                .map(|(id, offset, len)| (id, (offset, len)))
+                .collect(),
+            start,
+            end,
+        }
+    }
+
+    /// The number of bytes past the index which do not belong to any blob.
+    #[must_use]
+    pub(crate) fn dead_space(&self) -> usize {
+        let live: usize = Self::spans(self.blobs.values().copied())
+            .into_iter()
+            .map(|(offset, len)| (offset + len).min(self.end).saturating_sub(offset))
+            .sum();
+        self.end.saturating_sub(self.start).saturating_sub(live)
+    }
+
+    /// Where the blob identified by `id` lies within the file, as `(offset, len)`.
+    #[cfg(feature = "async")]
+    #[must_use]
+    pub(crate) fn get(&self, id: &Id) -> Option<(usize, usize)> {
+        self.blobs.get(id).copied()
+    }
+
+    /// Forgets every blob for which `f` returns `false`, i.e. because its contents have changed.
+    pub(crate) fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&Id) -> bool,
+    {
+        self.blobs.retain(|id, _| f(id));
+    }
+
+    /// Places `items` (given as `(id, len)`) after an index which ends at `start`.
+    ///
+    /// Blobs which are still current, and which lie past the index, are left where they are. Every other item is appended to the end of the file, in the order given, and is returned alongside the plan.
+    #[must_use]
+    pub(crate) fn allocate<I>(&self, start: usize, items: I) -> (Plan<'static, Id>, Vec<Id>)
+    where
+        I: IntoIterator<Item = (Id, usize)>,
+    {
+        let mut offsets = BTreeMap::new();
+        let mut appended = Vec::new();
+        let mut cursor = self.end.max(start);
+        for (id, len) in items {
+            match self.blobs.get(&id) {
+                Some(&(offset, stored)) if offset >= start && stored == len => {
+                    offsets.insert(id, offset);
+                }
+                _ => {
+                    offsets.insert(id.clone(), cursor);
+                    appended.push(id);
+                    cursor += len;
+                }
+            }
+        }
+
+        let plan = Plan {
+            slots: Vec::new(),
+            offsets,
+            trailing: &[],
+            end: cursor,
+        };
+        (plan, appended)
+    }
+
+    /// Packs the blobs of `items` tightly after an index which ends at `start`, preserving their order on disk.
+    ///
+    /// Every item must be current, and must lie past the index. The returned moves must be performed in order, and never move a blob further into the file, so no blob is overwritten before it has been moved.
+    #[must_use]
+    pub(crate) fn compact<I>(&self, start: usize, items: I) -> (Plan<'static, Id>, Vec<Move>)
+    where
+        I: IntoIterator<Item = Id>,
+    {
+        let items: Vec<_> = items
+            .into_iter()
+            .filter_map(|id| self.blobs.get(&id).map(|&blob| (id, blob)))
+            .collect();
+        let spans = Self::spans(items.iter().map(|(_, blob)| *blob));
+
+        let mut moves = Vec::new();
+        let mut relocated = BTreeMap::new();
+        let mut cursor = start;
+        for (offset, len) in spans {
+            debug_assert!(offset >= cursor, "blobs must lie past the index");
+            if offset != cursor {
+                moves.push(Move {
+                    from: offset,
+                    to: cursor,
+                    len,
+                });
+            }
+            relocated.insert(offset, cursor);
+            cursor += len;
+        }
+
+        let offsets = items
+            .into_iter()
+            .map(|(id, (offset, _))| {
+                // find the span which contains this blob
+                let (&from, &to) = relocated
+                    .range(..=offset)
+                    .next_back()
+                    .expect("every blob belongs to a span");
+                (id, to + (offset - from))
+            })
+            .collect();
+        let plan = Plan {
+            slots: Vec::new(),
+            offsets,
+            trailing: &[],
+            end: cursor,
+        };
+        (plan, moves)
+    }
+
+    /// Merges the given blobs (given as `(offset, len)`) into disjoint spans, sorted by offset.
+    #[must_use]
+    fn spans<I>(blobs: I) -> Vec<(usize, usize)>
+    where
+        I: IntoIterator<Item = (usize, usize)>,
+    {
+        let mut blobs: Vec<_> = blobs.into_iter().collect();
+        blobs.sort_unstable();
+        let mut spans: Vec<(usize, usize)> = Vec::with_capacity(blobs.len());
+        for (offset, len) in blobs {
+            match spans.last_mut() {
+                Some((first, first_len)) if offset <= *first + *first_len => {
+                    *first_len = (*first_len).max(offset + len - *first);
+                }
+                _ => spans.push((offset, len)),
+            }
+        }
+        spans
+    }
+}
+
+/// A span of bytes which must be moved to compact a [`Storage`].
+pub(crate) struct Move {
+    pub(crate) from: usize,
+    pub(crate) to: usize,
+    pub(crate) len: usize,
+}
@@ -4,6 +4,19 @@
 //!
 //! # A note on strings
 //! The Creation Engine absolutely does not handle unicode correctly, and even has some nasty, extant bugs which exist related to characters that utilize the extended ascii range. As such, all strings are marked as binary strings, without encoding (see also [`BStr`] or [`BString`]). If you must re-encode strings, then, generally speaking, they are encoded using the system code page of whatever computer happened to write the archive. That means English copies of the game are encoded using Windows-1252, Russian copies using Windows-1251, etc. However, this is not a guarantee and is the source of much consternation when writing internationalized applications for the Creation Engine games.
+//!
+//! # Optional features
+//! * `async`: Adds an `AsyncArchive` to each format, which reads the index of an archive from a tokio [`AsyncRead`](https://docs.rs/tokio/latest/tokio/io/trait.AsyncRead.html) stream, and then fetches the data of each file on demand using range reads. Also adds `write_async` to each archive, which writes the index and then the data of each file in turn, without buffering the whole archive, and `compress_async`/`decompress_async` to compressable files and chunks, which offload their work to blocking tasks.
+//! * `directxtex` (default): Converts fo4 textures to and from dds files using [DirectXTex](https://docs.rs/directxtex), i.e. reading a [`fo4::File`] using [`fo4::Format::DX10`], or writing one out. Without it, texture archives can still be read, written, and unpacked chunk by chunk, but conversions fail with [`fo4::Error::TexturesDisabled`].
+//! * `encoding`: Adds the [`encoding`] module, which converts names to and from unicode using a legacy code page, for looking up, packing, and extracting files. Also lets keys encode and decode their names.
+//! * `libdeflate`: Uses libdeflate for zlib compression and decompression, which is considerably faster than zlib, but still falls back to the zlib backend when compressing with a restricted window, i.e. for the Xbox. Enables `miniz_oxide` to serve as that fallback, although `zlib` is still preferred when both are enabled.
+//! * `pure-rust`: Enables `miniz_oxide` and `lz4_flex`, for compression which does not require a c toolchain. Pair it with `default-features = false` to drop the c and c++ dependencies entirely, including `directxtex`.
+//! * `serde`: Implements `Serialize`/`Deserialize` for archive options, hashes, keys, file headers, and diffs, and adds a `Manifest` to each format, which describes an entire archive minus the contents of its files.
+//!
+//! # Compression backends
+//! Each compression format is implemented by one of several backends, which are chosen using cargo features. At least one backend must be enabled for each format. All of them produce output which the games can read, so the choice only affects speed and build requirements. If more than one backend is enabled for a format, then the c backend is preferred.
+//! * zlib: `zlib` (default) links against zlib, while `miniz_oxide` is pure rust, but can not restrict the size of its window, and so can not compress chunks for [`fo4::CompressionLevel::FO4Xbox`]. See also `libdeflate`.
+//! * lz4: `lzzzz` (default) links against liblz4, while `lz4_flex` is pure rust, but does not support compression levels.
 
 #![warn(
     clippy::pedantic,
@@ -18,17 +31,43 @@
 )]
 
 mod cc;
+mod codec;
 mod containers;
 mod derive;
+#[cfg(feature = "encoding")]
+pub mod encoding;
 pub mod fo4;
 mod guess;
 mod hashing;
 mod io;
+mod layout;
+mod policy;
+mod progress;
 mod protocols;
+mod query;
 pub mod tes3;
 pub mod tes4;
+pub mod tree;
+pub mod vfs;
 
+#[cfg(feature = "lz4_flex")]
+pub use codec::Lz4Error;
 pub use guess::{guess_format, FileFormat};
+pub use policy::{CompressionPolicy, CompressionPolicyBuilder};
+pub use progress::{
+    CancellationToken, Event as ProgressEvent, Observer as ProgressObserver, Progress,
+    ProgressBuilder,
+};
+pub use query::{Entry as QueryEntry, Glob};
+
+/// Reads the input using positional reads into owned buffers, rather than by memory-mapping it.
+///
+/// Reading from an [`fs::File`](std::fs::File) memory-maps it, which is unsafe if the file is truncated or modified while it is mapped, i.e. by another program, and which can be slow or unsupported on network filesystems. Reading through this wrapper instead copies the data of every file out of the input as the archive is read, so the result is independent of the input, and nothing is memory-mapped.
+///
+/// Since the input is never held in memory in its entirety, it can not be read while capturing its layout.
+///
+/// The data of every file is always loaded eagerly, while the archive is read. Loading the data of each file lazily, on first access, is out of scope here, and is instead served by the `AsyncArchive` of each format (see the `async` feature), which reads the index up front and fetches each file on demand.
+pub struct Buffered<'file>(pub &'file std::fs::File);
 
 /// Makes a shallow copy of the input.
 ///
@@ -76,6 +115,52 @@ pub enum CompressionResult {
     Decompressed,
 }
 
+/// Specifies how to resolve a file which is present in more than one archive, when merging archives.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum MergePolicy {
+    /// The file from the earliest archive is kept.
+    FirstWins,
+    /// The file from the latest archive is kept, in the same manner as load order.
+    #[default]
+    LastWins,
+    /// Merging fails with an error.
+    Error,
+}
+
+/// The length of an archive once it is written, broken down by section.
+///
+/// Refer to the `written_len` method of each archive for more info.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct WrittenLen {
+    /// The fixed-size header at the start of the archive.
+    pub header: usize,
+    /// The entries which describe every directory, file, and chunk, excluding their names.
+    pub index: usize,
+    /// The names of every directory and file, wherever they are stored.
+    pub names: usize,
+    /// The data of every file, including any data which prefixes it, i.e. embedded file names or decompressed lengths.
+    pub data: usize,
+}
+
+impl WrittenLen {
+    /// The length of the archive as a whole.
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.header + self.index + self.names + self.data
+    }
+}
+
+/// A trait that enables reading from various sources, while capturing how the source was laid out.
+///
+/// The captured layout can be used to write an archive back out identically to how it was read. Refer to the `write_with_layout` method of each archive for more info.
+pub trait ReaderWithLayout<T>: Sealed {
+    type Error;
+    type Item;
+
+    /// Reads an instance of `Self::Item` from the given source, along with its layout.
+    fn read_with_layout(source: T) -> core::result::Result<Self::Item, Self::Error>;
+}
+
 /// A trait that enables reading from various sources, with configuration options.
 pub trait ReaderWithOptions<T>: Sealed + Sized {
     type Error;
@@ -89,5 +174,7 @@ pub use bstr::{BStr, BString, ByteSlice, ByteVec};
 
 /// Convenience using statements for traits that are needed to work with the library.
 pub mod prelude {
-    pub use crate::{CompressableFrom as _, Reader as _, ReaderWithOptions as _};
+    pub use crate::{
+        CompressableFrom as _, Reader as _, ReaderWithLayout as _, ReaderWithOptions as _,
+    };
 }
@@ -0,0 +1,163 @@
+use bstr::{BStr, BString, ByteSlice as _};
+
+/// See also [`CompressionPolicy`].
+#[derive(Clone, Debug, Default)]
+#[repr(transparent)]
+pub struct CompressionPolicyBuilder(CompressionPolicy);
+
+impl CompressionPolicyBuilder {
+    #[must_use]
+    pub fn build(self) -> CompressionPolicy {
+        self.0
+    }
+
+    /// Files with the given extension are never compressed. Exclusions take precedence over inclusions.
+    #[must_use]
+    pub fn exclude_extension<E>(mut self, extension: &E) -> Self
+    where
+        E: ?Sized + AsRef<[u8]>,
+    {
+        self.0.exclude.push(normalize_extension(extension.as_ref()));
+        self
+    }
+
+    /// Only files with an included extension are compressed. If no extensions are included, then every file is eligible for compression.
+    #[must_use]
+    pub fn include_extension<E>(mut self, extension: &E) -> Self
+    where
+        E: ?Sized + AsRef<[u8]>,
+    {
+        self.0.include.push(normalize_extension(extension.as_ref()));
+        self
+    }
+
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether a file should be kept decompressed when compressing it does not make it any smaller. Defaults to `true`.
+    #[must_use]
+    pub fn require_smaller(mut self, require_smaller: bool) -> Self {
+        self.0.require_smaller = require_smaller;
+        self
+    }
+}
+
+/// Decides which files are compressed, when compressing an entire archive.
+///
+/// Extensions are matched case-insensitively, with or without a leading `.`.
+///
+/// ```rust
+/// use ba2::CompressionPolicy;
+///
+/// // Leave formats which are already compressed as they are
+/// let _ = CompressionPolicy::builder()
+///     .exclude_extension("fuz")
+///     .exclude_extension("ogg")
+///     .exclude_extension("png")
+///     .exclude_extension("xwm")
+///     .build();
+///
+/// // Keep sounds decompressed, so that they can be streamed by the engine
+/// let _ = CompressionPolicy::builder()
+///     .exclude_extension("wav")
+///     .build();
+///
+/// // Only compress meshes
+/// let _ = CompressionPolicy::builder()
+///     .include_extension("nif")
+///     .build();
+/// ```
+#[derive(Clone, Debug)]
+pub struct CompressionPolicy {
+    include: Vec<BString>,
+    exclude: Vec<BString>,
+    require_smaller: bool,
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            require_smaller: true,
+        }
+    }
+}
+
+impl CompressionPolicy {
+    #[must_use]
+    pub fn builder() -> CompressionPolicyBuilder {
+        CompressionPolicyBuilder::new()
+    }
+
+    /// Whether the file at the given path is eligible for compression, according to its extension.
+    #[must_use]
+    pub fn allows<P>(&self, path: &P) -> bool
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        let extension = extension(path.as_ref().as_bstr());
+        let matches = |rules: &[BString]| {
+            extension.is_some_and(|extension| {
+                rules
+                    .iter()
+                    .any(|rule| rule.eq_ignore_ascii_case(extension))
+            })
+        };
+
+        !matches(&self.exclude) && (self.include.is_empty() || matches(&self.include))
+    }
+
+    #[must_use]
+    pub fn require_smaller(&self) -> bool {
+        self.require_smaller
+    }
+
+    /// Whether a compressed result of `compressed_len` bytes should be kept over the original of `decompressed_len` bytes.
+    #[must_use]
+    pub(crate) fn keeps(&self, compressed_len: usize, decompressed_len: usize) -> bool {
+        !self.require_smaller || compressed_len < decompressed_len
+    }
+}
+
+fn extension(path: &BStr) -> Option<&[u8]> {
+    let name = match path.rfind_byteset(b"\\/") {
+        Some(pos) => &path[pos + 1..],
+        None => path,
+    };
+    let pos = name.rfind_byte(b'.')?;
+    Some(&name[pos + 1..])
+}
+
+fn normalize_extension(extension: &[u8]) -> BString {
+    extension.strip_prefix(b".").unwrap_or(extension).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CompressionPolicy;
+
+    #[test]
+    fn default_state() {
+        let policy = CompressionPolicy::default();
+        assert!(policy.require_smaller());
+        assert!(policy.allows("meshes/clutter/bucket.nif"));
+        assert!(policy.allows("no_extension"));
+    }
+
+    #[test]
+    fn extension_rules() {
+        let policy = CompressionPolicy::builder()
+            .include_extension(".NIF")
+            .include_extension("xwm")
+            .exclude_extension("xwm")
+            .build();
+        assert!(policy.allows("meshes\\clutter\\bucket.nif"));
+        assert!(policy.allows("meshes/clutter/BUCKET.Nif"));
+        assert!(!policy.allows("sound/fx/bucket.xwm"));
+        assert!(!policy.allows("textures/bucket.dds"));
+        assert!(!policy.allows("meshes.nif/bucket"));
+    }
+}
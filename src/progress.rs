@@ -0,0 +1,261 @@
+use bstr::BStr;
+use core::{
+    fmt::{self, Debug, Formatter},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use std::sync::Arc;
+
+/// Requests that a long-running operation stops early.
+///
+/// Tokens are cheap to clone, and every clone refers to the same request, so a token may be handed to an operation on one thread, and then cancelled from another, i.e. from the ui thread of an application. Cancelled operations stop before their next entry, and fail with a `Cancelled` error.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Describes an entry which was just processed by a long-running operation.
+#[derive(Clone, Copy, Debug)]
+pub struct Event<'name> {
+    name: &'name BStr,
+    bytes: usize,
+    completed: usize,
+    total: usize,
+    completed_bytes: usize,
+    total_bytes: usize,
+}
+
+impl<'name> Event<'name> {
+    /// The number of bytes which were processed for this entry.
+    #[must_use]
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// The number of entries which have been processed so far, including this one.
+    #[must_use]
+    pub fn completed(&self) -> usize {
+        self.completed
+    }
+
+    /// The number of bytes which have been processed so far, including those of this entry.
+    #[must_use]
+    pub fn completed_bytes(&self) -> usize {
+        self.completed_bytes
+    }
+
+    /// The name of the entry, which is empty if the entry does not have one.
+    #[must_use]
+    pub fn name(&self) -> &'name BStr {
+        self.name
+    }
+
+    /// The number of entries which will be processed by the operation in total.
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// The number of bytes which will be processed by the operation in total.
+    #[must_use]
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+}
+
+/// Receives an [`Event`] every time a long-running operation finishes processing an entry.
+///
+/// Observers are called on the same thread as the operation, so they should return quickly. This trait is implemented for any closure which accepts an event.
+pub trait Observer {
+    fn on_entry(&mut self, event: &Event<'_>);
+}
+
+impl<F> Observer for F
+where
+    F: FnMut(&Event<'_>),
+{
+    fn on_entry(&mut self, event: &Event<'_>) {
+        self(event);
+    }
+}
+
+/// See also [`Progress`].
+#[derive(Debug, Default)]
+#[repr(transparent)]
+pub struct ProgressBuilder<'observer>(Progress<'observer>);
+
+impl<'observer> ProgressBuilder<'observer> {
+    #[must_use]
+    pub fn build(self) -> Progress<'observer> {
+        self.0
+    }
+
+    /// The token which is consulted before every entry.
+    #[must_use]
+    pub fn cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.0.cancellation = Some(cancellation);
+        self
+    }
+
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The observer which receives an event after every entry.
+    #[must_use]
+    pub fn observer<O>(mut self, observer: O) -> Self
+    where
+        O: Observer + 'observer,
+    {
+        self.0.observer = Some(Box::new(observer));
+        self
+    }
+}
+
+/// Reports progress through, and allows cancellation of, a long-running operation, such as writing or compressing an archive.
+///
+/// The default instance reports nothing, and can not be cancelled.
+///
+/// ```rust
+/// use ba2::{
+///     tes3::{Archive, ArchiveKey, File},
+///     CancellationToken, Progress,
+/// };
+///
+/// let archive: Archive = [(ArchiveKey::from(b"hello.txt"), File::from(b"Hello world!\n"))]
+///     .into_iter()
+///     .collect();
+/// let token = CancellationToken::new();
+/// let mut progress = Progress::builder()
+///     .observer(|event: &ba2::ProgressEvent| {
+///         println!("{}/{} bytes", event.completed_bytes(), event.total_bytes());
+///     })
+///     .cancellation(token.clone())
+///     .build();
+///
+/// let mut bytes = Vec::new();
+/// assert!(archive.write_with_progress(&mut bytes, &mut progress).is_ok());
+///
+/// token.cancel();
+/// assert!(archive.write_with_progress(&mut bytes, &mut progress).is_err());
+/// ```
+#[derive(Default)]
+pub struct Progress<'observer> {
+    observer: Option<Box<dyn Observer + 'observer>>,
+    cancellation: Option<CancellationToken>,
+    completed: usize,
+    total: usize,
+    completed_bytes: usize,
+    total_bytes: usize,
+}
+
+impl Debug for Progress<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Progress")
+            .field("observer", &self.observer.is_some())
+            .field("cancellation", &self.cancellation)
+            .field("completed", &self.completed)
+            .field("total", &self.total)
+            .field("completed_bytes", &self.completed_bytes)
+            .field("total_bytes", &self.total_bytes)
+            .finish()
+    }
+}
+
+impl<'observer> Progress<'observer> {
+    #[must_use]
+    pub fn builder() -> ProgressBuilder<'observer> {
+        ProgressBuilder::new()
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// Resets the counters at the start of an operation, which will process `total` entries, holding `total_bytes` bytes between them.
+    pub(crate) fn start(&mut self, total: usize, total_bytes: usize) {
+        self.completed = 0;
+        self.total = total;
+        self.completed_bytes = 0;
+        self.total_bytes = total_bytes;
+    }
+
+    /// Reports that an entry has been processed.
+    pub(crate) fn advance(&mut self, name: &BStr, bytes: usize) {
+        self.completed += 1;
+        self.completed_bytes += bytes;
+        if let Some(observer) = &mut self.observer {
+            observer.on_entry(&Event {
+                name,
+                bytes,
+                completed: self.completed,
+                total: self.total,
+                completed_bytes: self.completed_bytes,
+                total_bytes: self.total_bytes,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CancellationToken, Progress, ProgressEvent};
+    use bstr::ByteSlice as _;
+
+    #[test]
+    fn default_state() {
+        let mut progress = Progress::default();
+        assert!(!progress.is_cancelled());
+        progress.start(1, 1);
+        progress.advance(b"".as_bstr(), 1);
+    }
+
+    #[test]
+    fn events_and_cancellation() {
+        let mut events = Vec::new();
+        let token = CancellationToken::new();
+        {
+            let mut progress = Progress::builder()
+                .observer(|event: &ProgressEvent| {
+                    events.push((
+                        event.name().to_owned(),
+                        event.bytes(),
+                        event.completed(),
+                        event.total(),
+                        event.completed_bytes(),
+                        event.total_bytes(),
+                    ));
+                })
+                .cancellation(token.clone())
+                .build();
+            progress.start(2, 5);
+            progress.advance(b"a".as_bstr(), 2);
+            assert!(!progress.is_cancelled());
+            token.cancel();
+            assert!(progress.is_cancelled());
+            progress.advance(b"b".as_bstr(), 3);
+        }
+
+        assert_eq!(
+            events,
+            [("a".into(), 2, 1, 2, 2, 5), ("b".into(), 3, 2, 2, 5, 5),]
+        );
+    }
+}
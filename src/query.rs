@@ -0,0 +1,309 @@
+use crate::hashing;
+use bstr::{BStr, BString, ByteSlice as _};
+
+/// Describes a file in an archive, when searching an archive using a predicate.
+///
+/// The path is normalized in the same manner as it is when hashed, i.e. it is lowercase, and uses `\` as its separator, except that it is never replaced with `.` for being too long. If the archive was read without strings, then the path may be empty or incomplete.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    path: BString,
+    len: usize,
+    decompressed_len: Option<usize>,
+}
+
+impl Entry {
+    #[must_use]
+    pub(crate) fn new(path: &[u8], len: usize, decompressed_len: Option<usize>) -> Self {
+        let mut path = BString::from(path);
+        hashing::normalize_case_and_separators(&mut path);
+        Self {
+            path,
+            len,
+            decompressed_len,
+        }
+    }
+
+    /// The length of the file once decompressed, if it is compressed.
+    #[must_use]
+    pub fn decompressed_len(&self) -> Option<usize> {
+        self.decompressed_len
+    }
+
+    #[must_use]
+    pub fn is_compressed(&self) -> bool {
+        self.decompressed_len.is_some()
+    }
+
+    #[must_use]
+    pub fn is_decompressed(&self) -> bool {
+        !self.is_compressed()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The length of the file, as it is stored in the archive.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the path of the file matches the given pattern.
+    #[must_use]
+    pub fn matches(&self, glob: &Glob) -> bool {
+        glob.is_match_normalized(&self.path)
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &BStr {
+        self.path.as_bstr()
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Token {
+    Literal(u8),
+    /// `?`
+    Any,
+    /// `*`
+    Star,
+    /// `**`
+    Globstar,
+    /// `**\`, which may also match nothing at all
+    GlobstarSeparator,
+    /// `[a-z]` or `[!a-z]`
+    Class {
+        negated: bool,
+        ranges: Vec<(u8, u8)>,
+    },
+}
+
+/// A glob pattern, which matches paths using the same normalization as the engine.
+///
+/// Patterns are matched case-insensitively, and `/` is treated the same as `\`.
+///
+/// | Syntax   | Matches                                                  |
+/// |----------|----------------------------------------------------------|
+/// | `?`      | Any one character, except a separator                    |
+/// | `*`      | Any run of characters, except a separator                |
+/// | `**`     | Any run of characters, including separators              |
+/// | `**/`    | Zero or more whole directories                           |
+/// | `[abc]`  | Any one of the listed characters, or ranges, i.e. `[a-z]` |
+/// | `[!abc]` | Any one character which is not listed                    |
+///
+/// A `[` without a matching `]` is matched literally.
+///
+/// ```rust
+/// use ba2::Glob;
+///
+/// let glob = Glob::new("textures/**/*_n.dds");
+/// assert!(glob.is_match(r"Textures\Armor\Iron\Cuirass_n.dds"));
+/// assert!(glob.is_match("textures/sky_n.dds"));
+/// assert!(!glob.is_match("textures/armor/iron/cuirass.dds"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Glob {
+    tokens: Vec<Token>,
+}
+
+impl Glob {
+    #[must_use]
+    pub fn new<P>(pattern: &P) -> Self
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        let pattern: Vec<u8> = pattern
+            .as_ref()
+            .iter()
+            .map(|&b| hashing::map_byte(b))
+            .collect();
+        let pattern = pattern.trim_start_with(|x| x == '\\');
+        let pattern = pattern.trim_end_with(|x| x == '\\');
+        Self {
+            tokens: tokenize(pattern),
+        }
+    }
+
+    /// Whether the given path matches the pattern.
+    #[must_use]
+    pub fn is_match<P>(&self, path: &P) -> bool
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        let mut path = BString::from(path.as_ref());
+        hashing::normalize_case_and_separators(&mut path);
+        self.is_match_normalized(&path)
+    }
+
+    #[must_use]
+    fn is_match_normalized(&self, path: &[u8]) -> bool {
+        // next[j] is whether the tokens after the current one match path[j..]
+        let mut next = vec![false; path.len() + 1];
+        let mut current = next.clone();
+        next[path.len()] = true;
+
+        for token in self.tokens.iter().rev() {
+            current[path.len()] = match token {
+                Token::Star | Token::Globstar | Token::GlobstarSeparator => next[path.len()],
+                _ => false,
+            };
+            // whether the remaining tokens match after any run which ends in a separator
+            let mut after_separator = false;
+            for j in (0..path.len()).rev() {
+                let byte = path[j];
+                current[j] = match token {
+                    Token::Literal(x) => byte == *x && next[j + 1],
+                    Token::Any => byte != b'\\' && next[j + 1],
+                    Token::Star => next[j] || (byte != b'\\' && current[j + 1]),
+                    Token::Globstar => next[j] || current[j + 1],
+                    Token::GlobstarSeparator => {
+                        after_separator |= byte == b'\\' && next[j + 1];
+                        next[j] || after_separator
+                    }
+                    Token::Class { negated, ranges } => {
+                        let contains = ranges.iter().any(|&(lo, hi)| (lo..=hi).contains(&byte));
+                        byte != b'\\' && contains != *negated && next[j + 1]
+                    }
+                };
+            }
+            core::mem::swap(&mut current, &mut next);
+        }
+
+        next[0]
+    }
+}
+
+fn tokenize(mut pattern: &[u8]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    while let Some((&first, rest)) = pattern.split_first() {
+        pattern = rest;
+        let token = match first {
+            b'?' => Token::Any,
+            b'*' => match pattern {
+                [b'*', b'\\', rest @ ..] => {
+                    pattern = rest;
+                    Token::GlobstarSeparator
+                }
+                [b'*', rest @ ..] => {
+                    pattern = rest;
+                    Token::Globstar
+                }
+                _ => Token::Star,
+            },
+            b'[' => match parse_class(pattern) {
+                Some((token, rest)) => {
+                    pattern = rest;
+                    token
+                }
+                None => Token::Literal(b'['),
+            },
+            x => Token::Literal(x),
+        };
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Parses the body of a class, which follows the opening `[`.
+fn parse_class(pattern: &[u8]) -> Option<(Token, &[u8])> {
+    let (negated, pattern) = match pattern.split_first() {
+        Some((b'!', rest)) => (true, rest),
+        _ => (false, pattern),
+    };
+    // a leading `]` is a member of the class, rather than its end
+    let end = pattern.iter().skip(1).position(|&x| x == b']')? + 1;
+    let (body, rest) = (&pattern[..end], &pattern[end + 1..]);
+
+    let mut ranges = Vec::new();
+    let mut body = body;
+    loop {
+        body = match body {
+            [lo, b'-', hi, rest @ ..] => {
+                ranges.push((*lo.min(hi), *lo.max(hi)));
+                rest
+            }
+            [x, rest @ ..] => {
+                ranges.push((*x, *x));
+                rest
+            }
+            [] => break,
+        };
+    }
+
+    Some((Token::Class { negated, ranges }, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Glob, QueryEntry};
+
+    #[test]
+    fn wildcards() {
+        let glob = Glob::new("meshes/*.nif");
+        assert!(glob.is_match("meshes/bucket.nif"));
+        assert!(glob.is_match("MESHES\\Bucket.NIF"));
+        assert!(!glob.is_match("meshes/clutter/bucket.nif"));
+        assert!(!glob.is_match("meshes/bucket.nif.bak"));
+
+        let glob = Glob::new("sound/voice/**");
+        assert!(glob.is_match("sound/voice/skyrim.esm/femalenord/hello.fuz"));
+        assert!(!glob.is_match("sound/fx/hello.wav"));
+
+        let glob = Glob::new("**/*_n.dds");
+        assert!(glob.is_match("sky_n.dds"));
+        assert!(glob.is_match("textures/armor/iron/cuirass_n.dds"));
+        assert!(!glob.is_match("textures/armor/iron_n/cuirass.dds"));
+
+        let glob = Glob::new("file?.bin");
+        assert!(glob.is_match("file1.bin"));
+        assert!(!glob.is_match("file10.bin"));
+        assert!(!glob.is_match("file\\.bin"));
+
+        assert!(Glob::new("*").is_match("anything.txt"));
+        assert!(!Glob::new("*").is_match("dir/anything.txt"));
+        assert!(Glob::new("**").is_match("dir/anything.txt"));
+    }
+
+    #[test]
+    fn classes() {
+        let glob = Glob::new("lod[0-9].nif");
+        assert!(glob.is_match("lod3.nif"));
+        assert!(!glob.is_match("lodx.nif"));
+
+        let glob = Glob::new("[!a-c]*.dds");
+        assert!(glob.is_match("d.dds"));
+        assert!(!glob.is_match("B.dds"));
+
+        let glob = Glob::new("[]x]");
+        assert!(glob.is_match("]"));
+        assert!(glob.is_match("x"));
+
+        let glob = Glob::new("weird[name");
+        assert!(glob.is_match("weird[name"));
+        assert!(!glob.is_match("weirdname"));
+    }
+
+    #[test]
+    fn entries() {
+        let entry = QueryEntry::new(b"/Textures/Sky_N.dds", 0x10, Some(0x20));
+        assert_eq!(entry.path(), "textures\\sky_n.dds");
+        assert!(entry.is_compressed());
+        assert_eq!(entry.len(), 0x10);
+        assert_eq!(entry.decompressed_len(), Some(0x20));
+        assert!(entry.matches(&Glob::new("textures/*_n.dds")));
+    }
+
+    #[test]
+    fn long_paths() {
+        // too long to be hashed by the engine, but the path should survive all the same
+        let path = format!("Meshes/{}/Bucket.nif", "Clutter/".repeat(40));
+        assert!(path.len() >= 260);
+        let entry = QueryEntry::new(path.as_bytes(), 0x10, None);
+        assert_eq!(entry.path(), path.to_lowercase().replace('/', "\\"));
+        assert!(entry.matches(&Glob::new("meshes/**/bucket.nif")));
+        assert!(!entry.matches(&Glob::new(".")));
+        assert!(Glob::new("**/*.nif").is_match(&path));
+    }
+}
@@ -1,12 +1,19 @@
 use crate::{
     containers::Bytes,
     derive,
-    io::{Endian, Sink, Source},
+    io::{self, BufferedSource, Endian, Sink, Source},
+    layout::{Order, Plan, Region, Storage},
     protocols::ZString,
-    tes3::{self, Error, File, FileHash, Hash, Result},
+    tes3::{self, Diff, Error, File, FileHash, Hash, Result},
+    Progress, QueryEntry, WrittenLen,
 };
 use bstr::BString;
-use std::io::Write;
+use std::{
+    fs,
+    io::{BufWriter, Seek as _, SeekFrom, Write},
+};
+#[cfg(feature = "async")]
+use tokio::io::{AsyncWrite, AsyncWriteExt as _};
 
 mod constants {
     pub(crate) const FILE_ENTRY_SIZE: usize = 0x8;
@@ -15,6 +22,7 @@ mod constants {
     pub(crate) const HEADER_SIZE: usize = 0xC;
 }
 
+#[derive(Clone, Copy, Default)]
 struct Offsets {
     name_offsets: usize,
     names: usize,
@@ -22,6 +30,13 @@ struct Offsets {
     file_data: usize,
 }
 
+/// Where a file's name and data were read from, relative to the start of their respective blocks.
+struct Record {
+    name_offset: usize,
+    data_offset: usize,
+    data_size: usize,
+}
+
 struct Header {
     hash_offset: u32,
     file_count: u32,
@@ -53,6 +68,26 @@ impl Key<'_> {
     }
 }
 
+/// Captures the on-disk layout of an archive, as it was read.
+///
+/// A layout is obtained by reading an archive using [`ReaderWithLayout`](crate::ReaderWithLayout), and is used by [`Archive::write_with_layout`] to write the archive back out identically. The default layout is the one used by [`Archive::write`].
+#[derive(Clone, Debug, Default)]
+pub struct Layout {
+    order: Vec<FileHash>,
+    names: Region<FileHash>,
+    data: Region<FileHash>,
+}
+
+/// The layout of an archive which is in the middle of being read.
+#[derive(Default)]
+struct Capture {
+    order: Vec<FileHash>,
+    names: Vec<(FileHash, usize, usize)>,
+    /// The data of each file, relative to the start of the file data.
+    data: Vec<(FileHash, usize, usize)>,
+    offsets: Offsets,
+}
+
 type ReadResult<T> = T;
 derive::archive! {
     /// Represents the TES3 revision of the bsa format.
@@ -60,65 +95,354 @@ derive::archive! {
     Map: (Key: FileHash) => File
 }
 
+type LayoutResult<T> = (T, Layout);
+derive::reader_with_layout!(Archive => LayoutResult);
+
 impl<'bytes> Archive<'bytes> {
+    /// Compares `self` (the old archive) against `other` (the new archive).
+    #[must_use]
+    pub fn diff(&self, other: &Self) -> Diff {
+        Diff::new(self, other)
+    }
+
+    /// Finds the key of every file which satisfies the given predicate.
+    ///
+    /// See also [`Glob`](crate::Glob).
+    pub fn find<'this, F>(&'this self, mut predicate: F) -> impl Iterator<Item = &'this Key<'bytes>>
+    where
+        F: FnMut(&QueryEntry) -> bool + 'this,
+    {
+        self.iter()
+            .filter(move |(key, file)| predicate(&Self::query_entry(key, file)))
+            .map(|(key, _)| key)
+    }
+
+    /// Makes a new archive from every file which satisfies the given predicate.
+    #[must_use]
+    pub fn select<F>(&self, mut predicate: F) -> Self
+    where
+        F: FnMut(&QueryEntry) -> bool,
+    {
+        self.iter()
+            .filter(|(key, file)| predicate(&Self::query_entry(key, file)))
+            .map(|(key, file)| (key.clone(), file.clone()))
+            .collect()
+    }
+
+    /// Moves a file into the given directory, keeping its file name.
+    ///
+    /// See also [`rename_file`](Self::rename_file).
+    pub fn move_file<P, D>(&mut self, path: &P, directory: &D) -> Result<()>
+    where
+        P: ?Sized + AsRef<[u8]>,
+        D: ?Sized + AsRef<[u8]>,
+    {
+        let path = path.as_ref();
+        let name = match path.iter().rposition(|&x| x == b'\\' || x == b'/') {
+            Some(pos) => &path[pos + 1..],
+            None => path,
+        };
+        let mut to = BString::from(directory.as_ref());
+        if !to.is_empty() {
+            to.push(b'\\');
+        }
+        to.extend_from_slice(name);
+        self.rename_file(path, &to)
+    }
+
+    /// Renames a file, using the full path of the file before and after.
+    ///
+    /// The file is moved as is, so data which is borrowed or memory-mapped from the source archive is never copied. Fails if no file exists at `from`, or if another file already exists at `to`, in which case the archive is left unchanged.
+    pub fn rename_file<P, Q>(&mut self, from: &P, to: &Q) -> Result<()>
+    where
+        P: ?Sized + AsRef<[u8]>,
+        Q: ?Sized + AsRef<[u8]>,
+    {
+        let from = Key::from(from.as_ref());
+        let to = Key::from(to.as_ref());
+        if !self.map.contains_key(from.hash()) {
+            return Err(Error::FileNotFound(from.name().into()));
+        }
+        if to.hash() != from.hash() && self.map.contains_key(to.hash()) {
+            return Err(Error::FileExists(to.name().into()));
+        }
+
+        if let Some(file) = self.map.remove(from.hash()) {
+            self.map.insert(to, file);
+        }
+        Ok(())
+    }
+
     pub fn write<Out>(&self, stream: &mut Out) -> Result<()>
     where
         Out: ?Sized + Write,
     {
-        let mut sink = Sink::new(stream);
-        let header = self.make_header()?;
-        Self::write_header(&mut sink, &header)?;
-        self.write_files(&mut sink)?;
-        self.write_name_offsets(&mut sink)?;
-        self.write_names(&mut sink)?;
-        self.write_hashes(&mut sink)?;
-        self.write_file_data(&mut sink)?;
+        self.write_with_layout(stream, &Layout::default())
+    }
 
+    /// See also [`write`](Self::write).
+    ///
+    /// The index is written first, followed by the data of each file in turn, which is written directly from the archive, so nothing but the index is buffered, and the executor is yielded to between files.
+    #[cfg(feature = "async")]
+    pub async fn write_async<Out>(&self, stream: &mut Out) -> Result<()>
+    where
+        Out: ?Sized + AsyncWrite + Unpin,
+    {
+        let layout = Layout::default();
+        let (keys, names, data) = self.plan_write(&layout);
+        let mut index = Vec::new();
+        self.write_index(&mut Sink::new(&mut index), &keys, &names, &data)?;
+        stream.write_all(&index).await?;
+        for slot in &data.slots {
+            if let Some(file) = self.map.get(&slot.id) {
+                stream.write_all(slot.padding).await?;
+                stream.write_all(file.as_bytes()).await?;
+            }
+        }
+        stream.write_all(data.trailing).await?;
         Ok(())
     }
 
-    fn make_header(&self) -> Result<Header> {
-        Ok(Header {
-            file_count: self.map.len().try_into()?,
-            hash_offset: {
-                let names_offset = 0xC * self.map.len();
-                let names_len: usize = self.map.keys().map(|x| x.name().len() + 1).sum();
-                (names_offset + names_len).try_into()?
-            },
-        })
+    /// Writes the archive using the given layout.
+    ///
+    /// Files are written in the same order, and at the same positions, as they were when the layout was captured, so writing an unmodified archive reproduces the original byte-for-byte. Files which were added since are written after all other files, and files which were removed are simply skipped.
+    pub fn write_with_layout<Out>(&self, stream: &mut Out, layout: &Layout) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        self.do_write(stream, layout, &mut Progress::default())
     }
 
-    fn write_files<Out>(&self, sink: &mut Sink<Out>) -> Result<()>
+    /// See also [`write`](Self::write).
+    ///
+    /// Every file is reported to `progress` once its data has been written. If the operation is cancelled, then the archive is left partially written.
+    pub fn write_with_progress<Out>(&self, stream: &mut Out, progress: &mut Progress) -> Result<()>
     where
         Out: ?Sized + Write,
     {
-        let mut offset: u32 = 0;
-        for file in self.map.values() {
-            let size: u32 = file.bytes.len().try_into()?;
-            sink.write(&(size, offset), Endian::Little)?;
-            offset += size;
+        self.do_write(stream, &Layout::default(), progress)
+    }
+
+    /// Computes the length of the archive as it would be written by [`write`](Self::write), without writing it.
+    ///
+    /// Fails in the same cases as [`write`](Self::write) would, i.e. when an offset or a length would not fit within the fields of the archive format.
+    pub fn written_len(&self) -> Result<WrittenLen> {
+        self.written_len_with_layout(&Layout::default())
+    }
+
+    /// See also [`written_len`](Self::written_len).
+    ///
+    /// Computes the length of the archive as it would be written by [`write_with_layout`](Self::write_with_layout). Any padding between names is counted as names, and any padding or trailing bytes after the index are counted as data.
+    pub fn written_len_with_layout(&self, layout: &Layout) -> Result<WrittenLen> {
+        let (keys, names, data) = self.plan_write(layout);
+        let mut stream = std::io::sink();
+        self.write_index(&mut Sink::new(&mut stream), &keys, &names, &data)?;
+        Ok(WrittenLen {
+            header: constants::HEADER_SIZE,
+            index: (constants::FILE_ENTRY_SIZE + 0x4 + constants::HASH_SIZE)
+                .checked_mul(keys.len())
+                .ok_or(Error::IntegralTruncation)?,
+            names: names.end,
+            data: data.end,
+        })
+    }
+
+    /// Appends the files which are not current within `storage` to the end of `stream`, and then rewrites the index.
+    ///
+    /// Files are sorted as they would be by [`write`](Self::write), since the index is rewritten in full anyways.
+    pub(super) fn write_in_place(
+        &self,
+        stream: &mut fs::File,
+        storage: &Storage<FileHash>,
+    ) -> Result<()> {
+        let keys: Vec<_> = self.map.keys().collect();
+        let region = Region::default();
+        let names = self.plan_names(&keys, &region);
+        let start = Self::index_len(keys.len(), &names);
+        let (data, appended) = storage.allocate(start, self.sections(&keys));
+
+        // data must be written first, since the index may grow over files which are being relocated
+        if let Some(first) = appended.first() {
+            stream.seek(SeekFrom::Start(data.offset(first).try_into()?))?;
+            let mut stream = BufWriter::new(&mut *stream);
+            for hash in &appended {
+                stream.write_all(self.map[hash].as_bytes())?;
+            }
+            stream.flush()?;
         }
 
+        let mut index = Vec::new();
+        self.write_index(
+            &mut Sink::new(&mut index),
+            &keys,
+            &names,
+            &Self::rebase(data, start),
+        )?;
+        stream.seek(SeekFrom::Start(0))?;
+        stream.write_all(&index)?;
+        stream.flush()?;
         Ok(())
     }
 
-    fn write_file_data<Out>(&self, sink: &mut Sink<Out>) -> Result<()>
+    /// Moves every file within `storage` down towards the index, and then rewrites the index, returning the new length of the file.
+    pub(super) fn compact_in_place(
+        &self,
+        stream: &mut fs::File,
+        storage: &Storage<FileHash>,
+    ) -> Result<usize> {
+        let keys: Vec<_> = self.map.keys().collect();
+        let region = Region::default();
+        let names = self.plan_names(&keys, &region);
+        let start = Self::index_len(keys.len(), &names);
+        let (data, moves) = storage.compact(start, keys.iter().map(|x| *x.hash()));
+        for x in moves {
+            io::copy_within(stream, x.from, x.to, x.len)?;
+        }
+
+        let end = data.end;
+        let mut index = Vec::new();
+        self.write_index(
+            &mut Sink::new(&mut index),
+            &keys,
+            &names,
+            &Self::rebase(data, start),
+        )?;
+        stream.seek(SeekFrom::Start(0))?;
+        stream.write_all(&index)?;
+        stream.flush()?;
+        Ok(end)
+    }
+
+    fn do_write<Out>(
+        &self,
+        stream: &mut Out,
+        layout: &Layout,
+        progress: &mut Progress,
+    ) -> Result<()>
     where
         Out: ?Sized + Write,
     {
-        for file in self.map.values() {
+        let mut sink = Sink::new(stream);
+        let (keys, names, data) = self.plan_write(layout);
+        self.write_index(&mut sink, &keys, &names, &data)?;
+        progress.start(
+            data.slots.len(),
+            data.slots.iter().map(|x| self.map[&x.id].len()).sum(),
+        );
+        for slot in &data.slots {
+            if progress.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let Some((key, file)) = self.map.get_key_value(&slot.id) else {
+                continue;
+            };
+            sink.write_bytes(slot.padding)?;
             sink.write_bytes(file.as_bytes())?;
+            progress.advance(key.name(), file.len());
         }
+        sink.write_bytes(data.trailing)?;
 
         Ok(())
     }
 
-    fn write_hashes<Out>(&self, sink: &mut Sink<Out>) -> Result<()>
+    /// Sorts the keys for writing, and plans where each name and the data of each file is written.
+    fn plan_write<'this, 'layout>(
+        &'this self,
+        layout: &'layout Layout,
+    ) -> (
+        Vec<&'this Key<'bytes>>,
+        Plan<'layout, FileHash>,
+        Plan<'layout, FileHash>,
+    ) {
+        let mut keys: Vec<_> = self.map.keys().collect();
+        Order::new(layout.order.iter()).arrange(&mut keys, |x| x.hash());
+
+        let names = self.plan_names(&keys, &layout.names);
+        let data = layout.data.plan(0, self.sections(&keys), |lhs, rhs| {
+            self.map[lhs].as_bytes() == self.map[rhs].as_bytes()
+        });
+        (keys, names, data)
+    }
+
+    /// The length of the index, i.e. where the file data begins.
+    #[must_use]
+    fn index_len(file_count: usize, names: &Plan<'_, FileHash>) -> usize {
+        constants::HEADER_SIZE
+            + (constants::FILE_ENTRY_SIZE + 0x4 + constants::HASH_SIZE) * file_count
+            + names.end
+    }
+
+    fn plan_names<'layout>(
+        &self,
+        keys: &[&Key<'bytes>],
+        region: &'layout Region<FileHash>,
+    ) -> Plan<'layout, FileHash> {
+        let name = |hash: &FileHash| self.map.get_key_value(hash).map(|(key, _)| key.name());
+        region.plan(
+            0,
+            keys.iter().map(|x| (*x.hash(), x.name().len() + 1)),
+            |lhs, rhs| name(lhs) == name(rhs),
+        )
+    }
+
+    #[must_use]
+    pub(crate) fn query_entry(key: &Key<'bytes>, file: &File<'bytes>) -> QueryEntry {
+        QueryEntry::new(key.name(), file.len(), None)
+    }
+
+    /// Makes the absolute offsets of the given plan relative to the start of the file data.
+    #[must_use]
+    fn rebase(mut data: Plan<'_, FileHash>, start: usize) -> Plan<'_, FileHash> {
+        for offset in data.offsets.values_mut() {
+            *offset -= start;
+        }
+        data
+    }
+
+    /// Lists the data of every file, along with its length.
+    fn sections(&self, keys: &[&Key<'bytes>]) -> Vec<(FileHash, usize)> {
+        keys.iter()
+            .map(|x| (*x.hash(), self.map[x.hash()].len()))
+            .collect()
+    }
+
+    fn write_index<Out>(
+        &self,
+        sink: &mut Sink<Out>,
+        keys: &[&Key<'bytes>],
+        names: &Plan<'_, FileHash>,
+        data: &Plan<'_, FileHash>,
+    ) -> Result<()>
     where
         Out: ?Sized + Write,
     {
-        for key in self.map.keys() {
-            let hash = &key.hash();
+        let header = Header {
+            file_count: keys.len().try_into()?,
+            hash_offset: ((constants::FILE_ENTRY_SIZE + 0x4) * keys.len() + names.end)
+                .try_into()?,
+        };
+        Self::write_header(sink, &header)?;
+
+        for key in keys {
+            let size: u32 = self.map[key.hash()].len().try_into()?;
+            let offset: u32 = data.offset(key.hash()).try_into()?;
+            sink.write(&(size, offset), Endian::Little)?;
+        }
+
+        for key in keys {
+            let offset: u32 = names.offset(key.hash()).try_into()?;
+            sink.write(&offset, Endian::Little)?;
+        }
+
+        let name = |hash: &FileHash| self.map.get_key_value(hash).map(|(key, _)| key.name());
+        for slot in &names.slots {
+            sink.write_bytes(slot.padding)?;
+            sink.write_protocol::<ZString>(name(&slot.id).unwrap_or_default(), Endian::Little)?;
+        }
+        sink.write_bytes(names.trailing)?;
+
+        for key in keys {
+            let hash = key.hash();
             sink.write(&(hash.lo, hash.hi), Endian::Little)?;
         }
 
@@ -140,31 +464,31 @@ impl<'bytes> Archive<'bytes> {
         Ok(())
     }
 
-    fn write_name_offsets<Out>(&self, sink: &mut Sink<Out>) -> Result<()>
+    fn do_read<In>(source: &mut In) -> Result<ReadResult<Self>>
     where
-        Out: ?Sized + Write,
+        In: ?Sized + Source<'bytes>,
     {
-        let mut offset: u32 = 0;
-        for key in self.map.keys() {
-            sink.write(&offset, Endian::Little)?;
-            offset += u32::try_from(key.name().len() + 1)?;
-        }
-
-        Ok(())
+        Self::read_archive(source, None)
     }
 
-    fn write_names<Out>(&self, sink: &mut Sink<Out>) -> Result<()>
+    fn do_read_with_layout<In>(source: &mut In) -> Result<LayoutResult<Self>>
     where
-        Out: ?Sized + Write,
+        In: ?Sized + Source<'bytes>,
     {
-        for key in self.map.keys() {
-            sink.write_protocol::<ZString>(key.name(), Endian::Little)?;
-        }
-
-        Ok(())
+        let mut capture = Capture::default();
+        let archive = Self::read_archive(source, Some(&mut capture))?;
+        let offsets = capture.offsets;
+        let bytes = source.as_bytes();
+        let region = |start, end| bytes.get(start..end).unwrap_or_default();
+        let layout = Layout {
+            order: capture.order,
+            names: Region::capture(region(offsets.names, offsets.hashes), 0, capture.names),
+            data: Region::capture(region(offsets.file_data, bytes.len()), 0, capture.data),
+        };
+        Ok((archive, layout))
     }
 
-    fn do_read<In>(source: &mut In) -> Result<ReadResult<Self>>
+    fn read_archive<In>(source: &mut In, mut capture: Option<&mut Capture>) -> Result<Self>
     where
         In: ?Sized + Source<'bytes>,
     {
@@ -173,10 +497,24 @@ impl<'bytes> Archive<'bytes> {
         let mut map = Map::default();
 
         for i in 0..header.file_count as usize {
-            let (key, value) = Self::read_file(source, i, &offsets)?;
+            let (key, value, record) = Self::read_file(source, i, &offsets)?;
+            if let Some(capture) = capture.as_deref_mut() {
+                let hash = *key.hash();
+                capture.order.push(hash);
+                capture
+                    .names
+                    .push((hash, record.name_offset, key.name().len() + 1));
+                capture
+                    .data
+                    .push((hash, record.data_offset, record.data_size));
+            }
             map.insert(key, value);
         }
 
+        if let Some(capture) = capture {
+            capture.offsets = offsets;
+        }
+
         Ok(Self { map })
     }
 
@@ -184,7 +522,7 @@ impl<'bytes> Archive<'bytes> {
         source: &mut In,
         idx: usize,
         offsets: &Offsets,
-    ) -> Result<(Key<'bytes>, File<'bytes>)>
+    ) -> Result<(Key<'bytes>, File<'bytes>, Record)>
     where
         In: ?Sized + Source<'bytes>,
     {
@@ -193,18 +531,19 @@ impl<'bytes> Archive<'bytes> {
             Self::read_hash(source)
         })??;
 
-        let name = source.save_restore_position(|source| -> Result<Bytes<'bytes>> {
-            source.seek_absolute(offsets.name_offsets + 0x4 * idx)?;
-            let offset: u32 = source.read(Endian::Little)?;
-            source.seek_absolute(offsets.names + offset as usize)?;
-            let name = source.read_protocol::<ZString>(Endian::Little)?;
-            Ok(name)
-        })??;
+        let (name, name_offset) =
+            source.save_restore_position(|source| -> Result<(Bytes<'bytes>, u32)> {
+                source.seek_absolute(offsets.name_offsets + 0x4 * idx)?;
+                let offset: u32 = source.read(Endian::Little)?;
+                source.seek_absolute(offsets.names + offset as usize)?;
+                let name = source.read_protocol::<ZString>(Endian::Little)?;
+                Ok((name, offset))
+            })??;
 
         let (size, offset): (u32, u32) = source.read(Endian::Little)?;
         let container = source.save_restore_position(|source| -> Result<Bytes<'bytes>> {
             source.seek_absolute(offsets.file_data + offset as usize)?;
-            let result = source.read_bytes(size as usize)?;
+            let result = source.read_blob(size as usize)?;
             Ok(result)
         })??;
 
@@ -214,6 +553,11 @@ impl<'bytes> Archive<'bytes> {
                 name,
             },
             File { bytes: container },
+            Record {
+                name_offset: name_offset as usize,
+                data_offset: offset as usize,
+                data_size: size as usize,
+            },
         ))
     }
 
@@ -240,12 +584,38 @@ impl<'bytes> Archive<'bytes> {
     }
 }
 
+impl Archive<'static> {
+    /// Reads the archive within `stream` into owned buffers, along with where the data of each file lies within it.
+    pub(super) fn read_in_place(stream: &fs::File) -> Result<(Self, Storage<FileHash>)> {
+        let mut source = BufferedSource::try_from(stream)?;
+        Self::read_storage(&mut source)
+    }
+
+    /// See also [`read_in_place`](Self::read_in_place).
+    pub(super) fn read_storage<In>(source: &mut In) -> Result<(Self, Storage<FileHash>)>
+    where
+        In: ?Sized + Source<'static>,
+    {
+        let mut capture = Capture::default();
+        let archive = Self::read_archive(source, Some(&mut capture))?;
+        let start = capture.offsets.file_data;
+        let data = capture
+            .data
+            .into_iter()
+            .map(|(hash, offset, len)| (hash, start + offset, len))
+            .collect();
+        let storage = Storage::new(data, start, source.stream_len());
+        Ok((archive, storage))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
+        layout::Region,
         prelude::*,
-        tes3::{Archive, ArchiveKey, Error, File, FileHash, Hash},
-        Borrowed,
+        tes3::{Archive, ArchiveKey, ArchiveLayout, Error, File, FileHash, Hash},
+        Borrowed, Buffered, CancellationToken, Glob, Progress, ProgressEvent, QueryEntry,
     };
     use anyhow::Context as _;
     use bstr::BString;
@@ -253,7 +623,7 @@ mod tests {
     use std::{
         ffi::OsStr,
         fs,
-        io::{self, Read as _},
+        io::{self, Read as _, Seek as _, SeekFrom},
         path::Path,
     };
     use walkdir::WalkDir;
@@ -331,6 +701,32 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn read_buffered() -> anyhow::Result<()> {
+        let path = Path::new("data/tes3_read_test/test.bsa");
+        let mapped = Archive::read(path)?;
+        let mut fd = fs::File::open(path)?;
+        fd.seek(SeekFrom::Start(3))?;
+        let buffered = Archive::read(Buffered(&fd))?;
+        assert!(mapped.diff(&buffered).is_empty());
+        // reads are positional, and so leave the cursor where the caller put it
+        #[cfg(unix)]
+        assert_eq!(fd.stream_position()?, 3);
+        for (key, file) in &mapped {
+            let other = buffered.get(key).context("file was missing")?;
+            assert_eq!(other.as_bytes(), file.as_bytes());
+        }
+
+        let fd = fs::File::open("data/tes3_invalid_test/invalid_exhausted.bsa")?;
+        match Archive::read(Buffered(&fd)) {
+            Err(Error::Io(io)) => assert_eq!(io.kind(), io::ErrorKind::UnexpectedEof),
+            Err(err) => return Err(err.into()),
+            Ok(_) => anyhow::bail!("read should have failed"),
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn writing() -> anyhow::Result<()> {
         struct Info {
@@ -399,6 +795,44 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn write_with_layout() -> anyhow::Result<()> {
+        for path in [
+            "data/tes3_read_test/test.bsa",
+            "data/common_guess_test/tes3.bsa",
+        ] {
+            let original = fs::read(path)?;
+            let (archive, layout) = Archive::read_with_layout(Borrowed(&original))
+                .with_context(|| format!("failed to read archive: {path}"))?;
+            let mut copy = Vec::new();
+            archive.write_with_layout(&mut copy, &layout)?;
+            assert!(copy == original, "archive was not reproduced: {path}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_with_modified_layout() -> anyhow::Result<()> {
+        let path = Path::new("data/tes3_read_test/test.bsa");
+        let (mut archive, layout) = Archive::read_with_layout(path)?;
+        let removed = archive.keys().next().context("archive was empty")?.clone();
+        archive.remove(removed.hash());
+        archive.insert(b"new/file.txt", File::from(b"Hello world!\n"));
+
+        let mut stream = Vec::new();
+        archive.write_with_layout(&mut stream, &layout)?;
+        let copy = Archive::read(Borrowed(&stream))?;
+        assert_eq!(copy.len(), archive.len());
+        assert!(copy.get(removed.hash()).is_none());
+        for (key, file) in &archive {
+            let other = copy.get(key.hash()).context("file was missing")?;
+            assert_eq!(other.as_bytes(), file.as_bytes());
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn assert_generic_interfaces_compile() -> anyhow::Result<()> {
         let mut bsa = Archive::default();
@@ -419,4 +853,179 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn query() {
+        let archive: Archive = [
+            ("meshes/clutter/bucket.nif", 3),
+            ("Textures/Clutter/Bucket.dds", 5),
+            ("textures/clutter/bucket_n.dds", 7),
+        ]
+        .into_iter()
+        .map(|(path, len)| (ArchiveKey::from(path), File::from(&b"payload"[..len])))
+        .collect();
+
+        let glob = Glob::new("textures/**/*.dds");
+        let mut found: Vec<_> = archive
+            .find(|x| x.matches(&glob))
+            .map(ArchiveKey::name)
+            .collect();
+        found.sort();
+        assert_eq!(
+            found,
+            [
+                "textures\\clutter\\bucket.dds",
+                "textures\\clutter\\bucket_n.dds"
+            ]
+        );
+        assert!(archive.find(QueryEntry::is_compressed).next().is_none());
+
+        let selected = archive.select(|x| x.len() < 7);
+        assert_eq!(selected.len(), 2);
+        assert!(selected
+            .get(&ArchiveKey::from("meshes\\clutter\\bucket.nif"))
+            .is_some());
+        assert!(selected
+            .get(&ArchiveKey::from("textures/clutter/bucket_n.dds"))
+            .is_none());
+    }
+
+    #[test]
+    fn rename_and_move() -> anyhow::Result<()> {
+        let archive: Archive = [
+            ("meshes/bucket.nif", b"bucket"),
+            ("meshes/broom.nif", b"broom!"),
+        ]
+        .into_iter()
+        .map(|(path, data)| (ArchiveKey::from(path), File::from(&data[..])))
+        .collect();
+        let mut bytes = Vec::new();
+        archive.write(&mut bytes)?;
+        let range = bytes.as_ptr_range();
+
+        let mut archive = Archive::read(Borrowed(&bytes))?;
+        archive.rename_file("meshes/bucket.nif", "Meshes/Clutter/Pail.nif")?;
+        archive.move_file("meshes\\broom.nif", "meshes/clutter")?;
+        assert_eq!(archive.len(), 2);
+        assert!(archive
+            .get(&ArchiveKey::from("meshes/bucket.nif"))
+            .is_none());
+
+        let (key, file) = archive
+            .get_key_value(&ArchiveKey::from("meshes/clutter/pail.nif"))
+            .context("renamed file was missing")?;
+        assert_eq!(key.name(), "meshes\\clutter\\pail.nif");
+        assert_eq!(file.as_bytes(), b"bucket");
+        assert!(range.contains(&file.as_bytes().as_ptr()));
+        let file = archive
+            .get(&ArchiveKey::from("meshes/clutter/broom.nif"))
+            .context("moved file was missing")?;
+        assert_eq!(file.as_bytes(), b"broom!");
+        assert!(range.contains(&file.as_bytes().as_ptr()));
+
+        match archive.rename_file("meshes/clutter/pail.nif", "meshes/clutter/broom.nif") {
+            Err(Error::FileExists(path)) => assert_eq!(path, "meshes\\clutter\\broom.nif"),
+            other => anyhow::bail!("rename should have collided: {other:?}"),
+        }
+        match archive.rename_file("meshes/missing.nif", "meshes/other.nif") {
+            Err(Error::FileNotFound(path)) => assert_eq!(path, "meshes\\missing.nif"),
+            other => anyhow::bail!("rename should have failed: {other:?}"),
+        }
+        assert_eq!(archive.len(), 2);
+
+        archive.rename_file("meshes/clutter/pail.nif", "MESHES/CLUTTER/PAIL.NIF")?;
+        assert_eq!(archive.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn written_len() -> anyhow::Result<()> {
+        let archive = Archive::read(Path::new("data/tes3_read_test/test.bsa"))?;
+        let len = archive.written_len()?;
+        let mut bytes = Vec::new();
+        archive.write(&mut bytes)?;
+        assert_eq!(len.total(), bytes.len());
+        assert_eq!(len.header, 0xC);
+        assert_eq!(len.index, archive.len() * 0x14);
+        assert_eq!(
+            len.names,
+            archive.keys().map(|x| x.name().len() + 1).sum::<usize>()
+        );
+
+        assert_eq!(Archive::new().written_len()?.total(), 0xC);
+        Ok(())
+    }
+
+    #[test]
+    fn written_len_with_layout() -> anyhow::Result<()> {
+        let archive = Archive::read(Path::new("data/tes3_read_test/test.bsa"))?;
+
+        // align the data of every file, as if the archive had been packed for a console
+        let keys: Vec<_> = archive.map.keys().collect();
+        let mut items = Vec::new();
+        let mut cursor: usize = 0;
+        for (hash, len) in archive.sections(&keys) {
+            cursor = cursor.next_multiple_of(0x100);
+            items.push((hash, cursor, len));
+            cursor += len;
+        }
+        let aligned = vec![0; cursor.next_multiple_of(0x100)];
+        let layout = ArchiveLayout {
+            data: Region::capture(&aligned, 0, items),
+            ..ArchiveLayout::default()
+        };
+
+        let len = archive.written_len_with_layout(&layout)?;
+        let mut bytes = Vec::new();
+        archive.write_with_layout(&mut bytes, &layout)?;
+        assert_eq!(len.total(), bytes.len());
+        assert!(len.total() > archive.written_len()?.total());
+
+        let (copy, layout) = Archive::read_with_layout(Borrowed(&bytes))?;
+        assert!(layout.data.is_padded());
+        assert_eq!(copy.written_len_with_layout(&layout)?, len);
+        Ok(())
+    }
+
+    #[test]
+    fn write_with_progress() -> anyhow::Result<()> {
+        let archive = Archive::read(Path::new("data/tes3_read_test/test.bsa"))?;
+        let mut events = Vec::new();
+        let mut progress = Progress::builder()
+            .observer(|event: &ProgressEvent| {
+                events.push((
+                    event.name().to_owned(),
+                    event.bytes(),
+                    event.completed(),
+                    event.total(),
+                    event.completed_bytes(),
+                    event.total_bytes(),
+                ));
+            })
+            .build();
+        let mut bytes = Vec::new();
+        archive.write_with_progress(&mut bytes, &mut progress)?;
+        drop(progress);
+
+        let total_bytes: usize = archive.values().map(File::len).sum();
+        assert_eq!(events.len(), archive.len());
+        for (i, (name, _, completed, total, _, expected)) in events.iter().enumerate() {
+            assert!(archive.get(&ArchiveKey::from(name.as_slice())).is_some());
+            assert_eq!(*completed, i + 1);
+            assert_eq!(*total, archive.len());
+            assert_eq!(*expected, total_bytes);
+        }
+        assert_eq!(events.last().map(|x| x.4), Some(total_bytes));
+
+        let mut original = Vec::new();
+        archive.write(&mut original)?;
+        assert_eq!(bytes, original);
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let mut progress = Progress::builder().cancellation(token).build();
+        let result = archive.write_with_progress(&mut Vec::new(), &mut progress);
+        assert!(matches!(result, Err(Error::Cancelled)));
+        Ok(())
+    }
 }
@@ -0,0 +1,171 @@
+use crate::{
+    containers::Bytes,
+    io,
+    layout::Storage,
+    tes3::{Archive, ArchiveKey, File, FileHash, Result},
+};
+use core::borrow::Borrow;
+use tokio::io::{AsyncRead, AsyncSeek};
+
+/// Reads a TES3 archive asynchronously, fetching the data of each file only when it is requested.
+///
+/// Only the index of the archive is read up front. The data of a file is read using a range read whenever it is fetched, rather than by memory-mapping the whole archive, which makes this suitable for streams which are slow, or which live on another machine.
+///
+/// ```rust
+/// use ba2::tes3::{ArchiveKey, AsyncArchive};
+///
+/// async fn example() -> Option<()> {
+///     let stream = tokio::fs::File::open("path/to/morrowind/Data Files/Morrowind.bsa")
+///         .await
+///         .ok()?;
+///     let mut archive = AsyncArchive::new(stream).await.ok()?;
+///     let key = ArchiveKey::from(b"meshes/m/probe_journeyman_01.nif");
+///     let file = archive.get(&key).await.ok()??;
+///     tokio::fs::write("probe_journeyman_01.nif", file.as_bytes())
+///         .await
+///         .ok()?;
+///     Some(())
+/// }
+/// ```
+pub struct AsyncArchive<In> {
+    stream: In,
+    /// The index of the archive, whose files are all empty.
+    index: Archive<'static>,
+    storage: Storage<FileHash>,
+}
+
+impl<In> AsyncArchive<In>
+where
+    In: AsyncRead + AsyncSeek + Unpin,
+{
+    /// Reads the index of the archive within the given stream.
+    pub async fn new(mut stream: In) -> Result<Self> {
+        let (index, storage) = io::read_deferred(&mut stream, Archive::read_storage).await?;
+        Ok(Self {
+            stream,
+            index,
+            storage,
+        })
+    }
+
+    /// Fetches the data of the file with the given hash.
+    pub async fn get<K>(&mut self, key: &K) -> Result<Option<File<'static>>>
+    where
+        K: Borrow<FileHash>,
+    {
+        match self.storage.get(key.borrow()) {
+            Some((offset, len)) => {
+                let bytes = io::read_range(&mut self.stream, offset, len).await?;
+                Ok(Some(File {
+                    bytes: Bytes::from_owned(bytes),
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Fetches the data of every file, in the order they are stored.
+    pub async fn into_archive(mut self) -> Result<Archive<'static>> {
+        let mut hashes: Vec<_> = self.index.keys().map(|key| *key.hash()).collect();
+        hashes.sort_by_key(|hash| self.storage.get(hash));
+
+        let mut archive = Archive::new();
+        for hash in hashes {
+            if let Some((key, _)) = self.index.remove_entry(&hash) {
+                if let Some(file) = self.get(&hash).await? {
+                    archive.insert(key, file);
+                }
+            }
+        }
+
+        Ok(archive)
+    }
+
+    #[must_use]
+    pub fn into_inner(self) -> In {
+        self.stream
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &ArchiveKey<'static>> {
+        self.index.keys()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        prelude::*,
+        tes3::{Archive, ArchiveKey, AsyncArchive, File},
+    };
+    use anyhow::Context as _;
+    use std::{io::Cursor, path::Path};
+
+    #[tokio::test]
+    async fn read_lazily() -> anyhow::Result<()> {
+        let path = Path::new("data/tes3_read_test/test.bsa");
+        let expected = Archive::read(path)?;
+        let stream = tokio::fs::File::open(path).await?;
+        let mut archive = AsyncArchive::new(stream).await?;
+        assert_eq!(archive.len(), expected.len());
+
+        for (key, file) in &expected {
+            let fetched = archive.get(key).await?.context("file was missing")?;
+            assert_eq!(fetched.as_bytes(), file.as_bytes());
+        }
+
+        let archive = archive.into_archive().await?;
+        assert!(archive.diff(&expected).is_empty());
+        for (key, file) in &expected {
+            let (other_key, other) = archive.get_key_value(key).context("file was missing")?;
+            assert_eq!(other_key.name(), key.name());
+            assert_eq!(other.as_bytes(), file.as_bytes());
+        }
+
+        let mut written = Vec::new();
+        archive.write_async(&mut written).await?;
+        let mut expected_bytes = Vec::new();
+        expected.write(&mut expected_bytes)?;
+        assert_eq!(written, expected_bytes);
+
+        let archive = AsyncArchive::new(Cursor::new(written)).await?;
+        assert_eq!(archive.len(), expected.len());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_large_archives() -> anyhow::Result<()> {
+        let payloads: Vec<Vec<u8>> = (0..8u8).map(|i| vec![i; 0x8000 << i]).collect();
+        let expected: Archive = payloads
+            .iter()
+            .enumerate()
+            .map(|(i, payload)| {
+                let key = ArchiveKey::from(format!("large/file{i}.bin").as_str());
+                (key, File::from(payload.as_slice()))
+            })
+            .collect();
+        let mut bytes = Vec::new();
+        expected.write(&mut bytes)?;
+
+        let mut archive = AsyncArchive::new(Cursor::new(bytes)).await?;
+        assert_eq!(archive.len(), expected.len());
+        for (key, file) in &expected {
+            let fetched = archive.get(key).await?.context("file was missing")?;
+            assert_eq!(fetched.as_bytes(), file.as_bytes());
+        }
+        assert!(archive
+            .get(&ArchiveKey::from(b"missing.bin"))
+            .await?
+            .is_none());
+        Ok(())
+    }
+}
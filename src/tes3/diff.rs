@@ -0,0 +1,182 @@
+use crate::tes3::{Archive, ArchiveKey, FileHash};
+use bstr::BString;
+use core::fmt::{self, Display, Formatter};
+
+/// A file reported by a [`Diff`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Entry {
+    pub hash: FileHash,
+    /// The name of the file, which may be empty.
+    pub name: BString,
+}
+
+impl From<&ArchiveKey<'_>> for Entry {
+    fn from(value: &ArchiveKey<'_>) -> Self {
+        Self {
+            hash: *value.hash(),
+            name: value.name().into(),
+        }
+    }
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "{:016x}", self.hash.numeric())
+        } else {
+            write!(f, "{}", self.name)
+        }
+    }
+}
+
+/// A file whose name differs between archives, while its hash remains the same.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rename {
+    pub hash: FileHash,
+    pub old: BString,
+    pub new: BString,
+}
+
+/// The differences between two TES3 archives.
+///
+/// ```rust
+/// use ba2::tes3::{Archive, ArchiveKey, File};
+///
+/// let old: Archive = [(ArchiveKey::from(b"a.txt"), File::from(b"foo"))]
+///     .into_iter()
+///     .collect();
+/// let new: Archive = [(ArchiveKey::from(b"a.txt"), File::from(b"bar"))]
+///     .into_iter()
+///     .collect();
+/// let diff = old.diff(&new);
+/// assert_eq!(diff.changed.len(), 1);
+/// println!("{diff}");
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diff {
+    /// Files which exist only in the new archive.
+    pub added: Vec<Entry>,
+    /// Files which exist only in the old archive.
+    pub removed: Vec<Entry>,
+    /// Files which exist in both archives, but whose contents differ.
+    pub changed: Vec<Entry>,
+    /// Files which exist in both archives, but whose names differ.
+    pub renamed: Vec<Rename>,
+}
+
+impl Diff {
+    /// Returns `true` if both archives were found to be identical.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.renamed.is_empty()
+    }
+
+    #[must_use]
+    pub(crate) fn new(old: &Archive, new: &Archive) -> Self {
+        let mut result = Self::default();
+        for (new_key, new_file) in new {
+            match old.get_key_value(new_key.hash()) {
+                Some((old_key, old_file)) => {
+                    if old_file.as_bytes() != new_file.as_bytes() {
+                        result.changed.push(new_key.into());
+                    }
+                    if !old_key.name().is_empty()
+                        && !new_key.name().is_empty()
+                        && old_key.name() != new_key.name()
+                    {
+                        result.renamed.push(Rename {
+                            hash: *new_key.hash(),
+                            old: old_key.name().into(),
+                            new: new_key.name().into(),
+                        });
+                    }
+                }
+                None => result.added.push(new_key.into()),
+            }
+        }
+
+        for old_key in old.keys() {
+            if new.get(old_key.hash()).is_none() {
+                result.removed.push(old_key.into());
+            }
+        }
+
+        result
+    }
+}
+
+impl Display for Diff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for entry in &self.added {
+            writeln!(f, "+ {entry}")?;
+        }
+        for entry in &self.removed {
+            writeln!(f, "- {entry}")?;
+        }
+        for entry in &self.changed {
+            writeln!(f, "~ {entry}")?;
+        }
+        for rename in &self.renamed {
+            writeln!(f, "> {} -> {}", rename.old, rename.new)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        containers::Bytes,
+        tes3::{Archive, ArchiveKey, File},
+    };
+
+    #[test]
+    fn detects_all_changes() {
+        // names are normalized when keys are constructed, so emulate a name read from disk
+        let mut renamed = ArchiveKey::from(b"renamed.txt");
+        renamed.name = Bytes::from_owned(b"RENAMED.txt".to_vec().into());
+
+        let old: Archive = [
+            (ArchiveKey::from(b"same.txt"), File::from(b"same")),
+            (ArchiveKey::from(b"changed.txt"), File::from(b"old")),
+            (ArchiveKey::from(b"removed.txt"), File::from(b"gone")),
+            (ArchiveKey::from(b"renamed.txt"), File::from(b"name")),
+        ]
+        .into_iter()
+        .collect();
+        let new: Archive = [
+            (ArchiveKey::from(b"same.txt"), File::from(b"same")),
+            (ArchiveKey::from(b"changed.txt"), File::from(b"new")),
+            (ArchiveKey::from(b"added.txt"), File::from(b"here")),
+            (renamed, File::from(b"name")),
+        ]
+        .into_iter()
+        .collect();
+
+        let diff = old.diff(&new);
+        assert!(!diff.is_empty());
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].name, "added.txt");
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].name, "removed.txt");
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].name, "changed.txt");
+        assert_eq!(diff.renamed.len(), 1);
+        assert_eq!(diff.renamed[0].old, "renamed.txt");
+        assert_eq!(diff.renamed[0].new, "RENAMED.txt");
+
+        let text = diff.to_string();
+        assert!(text.contains("+ added.txt\n"));
+        assert!(text.contains("- removed.txt\n"));
+        assert!(text.contains("~ changed.txt\n"));
+        assert!(text.contains("> renamed.txt -> RENAMED.txt\n"));
+
+        assert!(old.diff(&old).is_empty());
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{
+    layout::Storage,
+    tes3::{Archive, ArchiveKey, File, FileHash, Result},
+};
+use core::borrow::Borrow;
+use std::fs;
+
+/// Edits a TES3 archive file in place, without rewriting the whole file.
+///
+/// Changes are made to the archive in memory, and are written out by [`commit`](Self::commit), which appends the data of every new or replaced file to the end of the file, and then rewrites only the header, the file entries, the names, and the hashes. File data which the index would grow over is relocated to the end of the file before the index is rewritten, so nothing is lost if it grows.
+///
+/// The space held by files which were replaced or removed is not reclaimed until the archive is [`compact`](Self::compact)ed, which can be done whenever [`dead_space`](Self::dead_space) grows too large.
+///
+/// The archive is read into owned buffers, in the same manner as reading through [`Buffered`](crate::Buffered), and is read again after every commit. Nothing is memory-mapped, so files obtained from the editor remain valid regardless of how the file changes, but the data of every file is held in memory while editing.
+///
+/// ```rust
+/// use ba2::tes3::{ArchiveKey, Editor, File};
+/// use std::fs;
+///
+/// fn example() -> Option<()> {
+///     let stream = fs::File::options()
+///         .read(true)
+///         .write(true)
+///         .open("path/to/morrowind/Data Files/Morrowind.bsa")
+///         .ok()?;
+///     let mut editor = Editor::new(stream).ok()?;
+///     editor.insert(ArchiveKey::from(b"hello.txt"), File::from(b"Hello world!\n"));
+///     editor.commit().ok()?;
+///     Some(())
+/// }
+/// ```
+pub struct Editor<'bytes> {
+    stream: fs::File,
+    archive: Archive<'bytes>,
+    storage: Storage<FileHash>,
+}
+
+impl<'bytes> Editor<'bytes> {
+    /// Opens the archive within the given file, which must be both readable and writable.
+    pub fn new(stream: fs::File) -> Result<Self> {
+        let (archive, storage) = Archive::read_in_place(&stream)?;
+        Ok(Self {
+            stream,
+            archive,
+            storage,
+        })
+    }
+
+    #[must_use]
+    pub fn archive(&self) -> &Archive<'bytes> {
+        &self.archive
+    }
+
+    /// The number of bytes within the file which no longer hold any data, as of the last commit.
+    #[must_use]
+    pub fn dead_space(&self) -> usize {
+        self.storage.dead_space()
+    }
+
+    /// Inserts the given file, replacing any file with the same hash.
+    pub fn insert<K>(&mut self, key: K, file: File<'bytes>) -> Option<File<'bytes>>
+    where
+        K: Into<ArchiveKey<'bytes>>,
+    {
+        let key = key.into();
+        let result = self.remove(key.hash());
+        self.archive.insert(key, file);
+        result
+    }
+
+    #[must_use]
+    pub fn into_inner(self) -> fs::File {
+        self.stream
+    }
+
+    pub fn remove<K>(&mut self, key: &K) -> Option<File<'bytes>>
+    where
+        K: Borrow<FileHash>,
+    {
+        let hash = key.borrow();
+        self.storage.retain(|x| x != hash);
+        self.archive.remove(hash)
+    }
+
+    /// Writes every change made since the last commit to the file.
+    pub fn commit(&mut self) -> Result<()> {
+        self.archive
+            .write_in_place(&mut self.stream, &self.storage)?;
+        self.reload()
+    }
+
+    /// Commits any changes, and then reclaims all dead space within the file, by moving file data towards the start of the file and truncating it.
+    pub fn compact(&mut self) -> Result<()> {
+        self.commit()?;
+        let len = self
+            .archive
+            .compact_in_place(&mut self.stream, &self.storage)?;
+        self.stream.set_len(len.try_into()?)?;
+        self.reload()
+    }
+
+    fn reload(&mut self) -> Result<()> {
+        let (archive, storage) = Archive::read_in_place(&self.stream)?;
+        self.archive = archive;
+        self.storage = storage;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        prelude::*,
+        tes3::{Archive, ArchiveKey, Editor, File},
+        Copied,
+    };
+    use anyhow::Context as _;
+    use std::{fs, path::Path};
+
+    #[test]
+    fn append_and_compact() -> anyhow::Result<()> {
+        let source = Path::new("data/tes3_read_test/test.bsa");
+        let original = Archive::read(source)?;
+        let path = std::env::temp_dir().join("ba2_tes3_editor_append_and_compact.bsa");
+        fs::copy(source, &path)?;
+        let stream = fs::File::options().read(true).write(true).open(&path)?;
+        let mut editor = Editor::new(stream)?;
+        assert_eq!(editor.dead_space(), 0);
+
+        let removed = original.keys().next().context("archive was empty")?.clone();
+        assert!(editor.remove(removed.hash()).is_some());
+        for i in 0..64 {
+            let key = ArchiveKey::from(format!("new/file{i}.txt").as_str());
+            editor.insert(key, File::from(b"Hello world!\n"));
+        }
+        editor.commit()?;
+        editor.insert(
+            ArchiveKey::from(b"new/file0.txt"),
+            File::from(b"Goodbye world!\n"),
+        );
+        editor.commit()?;
+        assert_eq!(editor.dead_space(), 13);
+
+        // the index grew over the data which followed it, so it must have been relocated
+        let bytes = fs::read(&path)?;
+        let copy = Archive::read(Copied(&bytes))?;
+        assert_eq!(copy.len(), original.len() + 63);
+        assert!(copy.get(removed.hash()).is_none());
+        for (key, file) in &original {
+            if key.hash() == removed.hash() {
+                continue;
+            }
+            let other = copy.get(key.hash()).context("file was missing")?;
+            assert_eq!(other.as_bytes(), file.as_bytes());
+        }
+        let file = copy
+            .get(&ArchiveKey::from(b"new/file7.txt"))
+            .context("file was missing")?;
+        assert_eq!(file.as_bytes(), b"Hello world!\n");
+
+        editor.compact()?;
+        assert_eq!(editor.dead_space(), 0);
+        let bytes = fs::read(&path)?;
+        let compacted = Archive::read(Copied(&bytes))?;
+        assert!(compacted.diff(&copy).is_empty());
+
+        let mut rewritten = Vec::new();
+        compacted.write(&mut rewritten)?;
+        assert_eq!(bytes.len(), rewritten.len());
+
+        Ok(())
+    }
+}
@@ -4,6 +4,7 @@ use core::cmp::Ordering;
 
 /// The underlying hash object used to uniquely identify objects within the archive.
 #[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 pub struct Hash {
     pub lo: u32,
@@ -0,0 +1,62 @@
+use crate::tes3::{Archive, ArchiveKey};
+use serde::{Deserialize, Serialize};
+
+/// Describes everything about a TES3 archive, except for the contents of its files.
+///
+/// A manifest can be stored alongside an archive's extracted files, so that the archive can be rebuilt identically later. Keys are stored with both their hash and their name (as a byte string), so that neither is lost, even if the name does not hash to the same value.
+///
+/// ```rust
+/// use ba2::tes3::{Archive, ArchiveKey, File, Manifest};
+///
+/// fn example() -> Option<()> {
+///     let file: File = b"Hello world!\n".into();
+///     let archive: Archive = [(ArchiveKey::from(b"hello.txt"), file)]
+///         .into_iter()
+///         .collect();
+///     let manifest = Manifest::new(&archive);
+///     let json = serde_json::to_string(&manifest).ok()?;
+///     let manifest: Manifest = serde_json::from_str(&json).ok()?;
+///     assert_eq!(manifest.files[0].name(), "hello.txt");
+///     Some(())
+/// }
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Manifest {
+    pub files: Vec<ArchiveKey<'static>>,
+}
+
+impl Manifest {
+    /// Describes the given archive.
+    #[must_use]
+    pub fn new(archive: &Archive<'_>) -> Self {
+        Self {
+            files: archive.keys().map(|x| x.clone().into_owned()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        prelude::*,
+        tes3::{Archive, Manifest},
+    };
+    use std::path::Path;
+
+    #[test]
+    fn round_trip() -> anyhow::Result<()> {
+        let path = Path::new("data/tes3_read_test/test.bsa");
+        let archive = Archive::read(path)?;
+        let manifest = Manifest::new(&archive);
+        let json = serde_json::to_string(&manifest)?;
+        let copy: Manifest = serde_json::from_str(&json)?;
+
+        assert_eq!(copy.files.len(), archive.len());
+        for (key, original) in copy.files.iter().zip(archive.keys()) {
+            assert_eq!(key.hash(), original.hash());
+            assert_eq!(key.name(), original.name());
+        }
+
+        Ok(())
+    }
+}
@@ -44,21 +44,45 @@
 //! ```
 
 mod archive;
+#[cfg(feature = "async")]
+mod async_archive;
+mod diff;
+mod editor;
 mod file;
 mod hashing;
+#[cfg(feature = "serde")]
+mod manifest;
 
 pub use self::{
-    archive::{Archive, Key as ArchiveKey},
+    archive::{Archive, Key as ArchiveKey, Layout as ArchiveLayout},
+    diff::{Diff, Entry as DiffEntry, Rename as DiffRename},
+    editor::Editor,
     file::File,
     hashing::{hash_file, hash_file_in_place, FileHash, Hash},
 };
 
+#[cfg(feature = "async")]
+pub use self::async_archive::AsyncArchive;
+
+#[cfg(feature = "serde")]
+pub use self::manifest::Manifest;
+
+use bstr::BString;
 use core::num::TryFromIntError;
 use std::io;
 
 #[non_exhaustive]
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
+    #[error("the operation was cancelled")]
+    Cancelled,
+
+    #[error("a file already exists at the given path: {0}")]
+    FileExists(BString),
+
+    #[error("no file exists at the given path: {0}")]
+    FileNotFound(BString),
+
     #[error("an operation on an integer would have truncated and corrupted data")]
     IntegralTruncation,
 
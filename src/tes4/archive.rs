@@ -1,21 +1,34 @@
+#[cfg(feature = "async")]
+use crate::io::BorrowedSource;
 use crate::{
     containers::{Bytes, CompressableBytes},
     derive,
-    io::{Endian, Sink, Source},
+    io::{self, BufferedSource, Endian, Sink, Source},
+    layout::{Order, Plan, Region, Storage},
     protocols::{self, BZString, ZString},
     tes4::{
-        self, directory::Map as DirectoryMap, Directory, DirectoryHash, DirectoryKey, Error, File,
-        Hash, Result, Version,
+        self, directory::Map as DirectoryMap, Diff, Directory, DirectoryHash, DirectoryKey, Error,
+        File, FileCompressionOptions, FileHash, Hash, Result, Version,
     },
+    CompressionPolicy, MergePolicy, Progress, QueryEntry, WrittenLen,
 };
 use bstr::{BStr, BString, ByteSlice as _};
 use core::mem;
-use std::{borrow::Cow, io::Write};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs,
+    io::{BufWriter, Seek as _, SeekFrom, Write},
+};
+#[cfg(feature = "async")]
+use tokio::io::{AsyncWrite, AsyncWriteExt as _};
 
 bitflags::bitflags! {
     /// Archive flags can impact the layout of an archive, or how it is read.
     #[repr(transparent)]
     #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    #[cfg_attr(feature = "serde", serde(transparent))]
     pub struct Flags: u32 {
         /// Includes directory paths within the archive.
         ///
@@ -137,6 +150,8 @@ bitflags::bitflags! {
     /// It's not apparent if the game actually uses these flags for anything.
     #[repr(transparent)]
     #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    #[cfg_attr(feature = "serde", serde(transparent))]
     pub struct Types: u16 {
         const MESHES = 1 << 0;
         const TEXTURES = 1 << 1;
@@ -227,6 +242,7 @@ struct Header {
     directory_names_len: u32,
     file_names_len: u32,
     archive_types: Types,
+    padding: u16,
 }
 
 impl Header {
@@ -315,6 +331,15 @@ impl OptionsBuilder {
         self
     }
 
+    /// Whether the archive types should be inferred from the contents of the archive when it is written, instead of using [`types`](Self::types).
+    ///
+    /// See also [`Archive::infer_types`].
+    #[must_use]
+    pub fn infer_types(mut self, infer_types: bool) -> Self {
+        self.0.infer_types = infer_types;
+        self
+    }
+
     #[must_use]
     pub fn new() -> Self {
         Self::default()
@@ -334,10 +359,13 @@ impl OptionsBuilder {
 }
 
 #[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Options {
     version: Version,
     flags: Flags,
     types: Types,
+    #[cfg_attr(feature = "serde", serde(default))]
+    infer_types: bool,
 }
 
 impl Options {
@@ -351,6 +379,11 @@ impl Options {
         self.flags
     }
 
+    #[must_use]
+    pub fn infer_types(&self) -> bool {
+        self.infer_types
+    }
+
     #[must_use]
     pub fn types(&self) -> Types {
         self.types
@@ -362,6 +395,52 @@ impl Options {
     }
 }
 
+/// Fields of a directory entry which are not otherwise represented.
+#[derive(Clone, Copy, Debug, Default)]
+struct DirectoryRecord {
+    /// The unused fields surrounding the file entries offset in v105 archives.
+    padding: [u32; 2],
+    /// The difference between the file entries offset as read, and the one we would have computed.
+    offset_delta: u32,
+}
+
+/// Fields of a file entry which are not otherwise represented.
+#[derive(Clone, Copy, Debug, Default)]
+struct FileRecord {
+    size_flags: u32,
+    offset_flags: u32,
+}
+
+/// Captures the on-disk layout of an archive, as it was read.
+///
+/// A layout is obtained by reading an archive using [`ReaderWithLayout`](crate::ReaderWithLayout), and is used by [`Archive::write_with_layout`] to write the archive back out identically. The default layout is the one used by [`Archive::write`].
+#[derive(Clone, Debug, Default)]
+pub struct Layout {
+    header_padding: u16,
+    directories: Vec<(DirectoryHash, DirectoryRecord)>,
+    files: Vec<((DirectoryHash, FileHash), FileRecord)>,
+    data: Region<(DirectoryHash, FileHash)>,
+}
+
+/// A file entry, as it was read.
+struct FileEntry {
+    flags: FileRecord,
+    data_offset: usize,
+    data_size: usize,
+}
+
+/// The layout of an archive which is in the middle of being read.
+#[derive(Default)]
+struct Capture {
+    layout: Layout,
+    data: Vec<((DirectoryHash, FileHash), usize, usize)>,
+    /// Where the file data begins, i.e. just past the index.
+    file_data: usize,
+}
+
+/// Identifies a file within an archive.
+type FileId = (DirectoryHash, FileHash);
+
 type ReadResult<T> = (T, Options);
 derive::archive! {
     /// Represents the TES4 revision of the bsa format.
@@ -369,67 +448,768 @@ derive::archive! {
     Map: (Key: DirectoryHash) => Directory
 }
 
+type LayoutResult<T> = (T, Options, Layout);
+derive::reader_with_layout!(Archive => LayoutResult);
+
+fn split_path(path: &[u8]) -> (&BStr, &BStr) {
+    match path.iter().rposition(|&x| x == b'\\' || x == b'/') {
+        Some(pos) => (path[..pos].as_bstr(), path[pos + 1..].as_bstr()),
+        None => (b"".as_bstr(), path.as_bstr()),
+    }
+}
+
+fn classify(directory: &BStr, file: &BStr) -> Types {
+    let mut components = directory
+        .split(|&x| x == b'\\' || x == b'/')
+        .filter(|x| !x.is_empty());
+    let is = |component: Option<&[u8]>, name: &[u8]| {
+        component.is_some_and(|x| x.eq_ignore_ascii_case(name))
+    };
+    let root = components.next();
+    let voices = is(root, b"sound") && is(components.next(), b"voice");
+
+    let extension = file
+        .rfind_byte(b'.')
+        .map(|pos| file[pos + 1..].to_ascii_lowercase());
+    match extension.as_deref() {
+        Some(b"nif" | b"kf") => return Types::MESHES,
+        Some(b"dds") => return Types::TEXTURES,
+        Some(b"swf" | b"xml") => return Types::MENUS,
+        Some(b"mp3" | b"ogg" | b"wav" | b"xwm") if voices => return Types::VOICES,
+        Some(b"mp3" | b"ogg" | b"wav" | b"xwm") => return Types::SOUNDS,
+        Some(b"fuz" | b"lip") => return Types::VOICES,
+        Some(b"fxp") => return Types::SHADERS,
+        Some(b"spt") => return Types::TREES,
+        Some(b"fnt" | b"tex") => return Types::FONTS,
+        _ => (),
+    }
+
+    let by_directory = [
+        (&b"meshes"[..], Types::MESHES),
+        (b"textures", Types::TEXTURES),
+        (b"menus", Types::MENUS),
+        (b"interface", Types::MENUS),
+        (b"music", Types::SOUNDS),
+        (b"shaders", Types::SHADERS),
+        (b"trees", Types::TREES),
+        (b"fonts", Types::FONTS),
+    ];
+    if voices {
+        Types::VOICES
+    } else if is(root, b"sound") {
+        Types::SOUNDS
+    } else if let Some(&(_, types)) = by_directory.iter().find(|(name, _)| is(root, name)) {
+        types
+    } else {
+        Types::MISC
+    }
+}
+
 impl<'bytes> Archive<'bytes> {
+    /// Iterates over every file in the archive, paired with its full path.
+    ///
+    /// The path is joined from the directory and file names, in the same manner as embedded file names. If the archive was read without strings, then the path may be incomplete.
+    pub fn files(&self) -> impl Iterator<Item = (Cow<'_, BStr>, &File<'bytes>)> {
+        self.iter().flat_map(|(directory_key, directory)| {
+            directory.iter().map(move |(file_key, file)| {
+                (
+                    Self::concat_directory_and_file_name(directory_key, file_key),
+                    file,
+                )
+            })
+        })
+    }
+
+    /// Finds the keys of every file which satisfies the given predicate, alongside the key of the directory which holds it.
+    ///
+    /// Files are matched using their full path, i.e. `dir\file.ext`. See also [`Glob`](crate::Glob).
+    pub fn find<'this, F>(
+        &'this self,
+        mut predicate: F,
+    ) -> impl Iterator<Item = (&'this Key<'bytes>, &'this DirectoryKey<'bytes>)>
+    where
+        F: FnMut(&QueryEntry) -> bool + 'this,
+    {
+        self.iter()
+            .flat_map(|(directory_key, directory)| {
+                directory
+                    .iter()
+                    .map(move |(file_key, file)| (directory_key, file_key, file))
+            })
+            .filter(move |(directory_key, file_key, file)| {
+                predicate(&Self::query_entry(directory_key, file_key, file))
+            })
+            .map(|(directory_key, file_key, _)| (directory_key, file_key))
+    }
+
+    /// Makes a new archive from every file which satisfies the given predicate.
+    ///
+    /// Directories which are left without any files are omitted.
+    #[must_use]
+    pub fn select<F>(&self, mut predicate: F) -> Self
+    where
+        F: FnMut(&QueryEntry) -> bool,
+    {
+        self.iter()
+            .filter_map(|(directory_key, directory)| {
+                let selected: Directory = directory
+                    .iter()
+                    .filter(|(file_key, file)| {
+                        predicate(&Self::query_entry(directory_key, file_key, file))
+                    })
+                    .map(|(file_key, file)| (file_key.clone(), file.clone()))
+                    .collect();
+                (!selected.is_empty()).then(|| (directory_key.clone(), selected))
+            })
+            .collect()
+    }
+
+    /// Gets a file using its full path, i.e. `dir\file.ext`.
+    #[must_use]
+    pub fn get_file<P>(&self, path: &P) -> Option<&File<'bytes>>
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        let (directory, file) = Self::hash_path(path.as_ref());
+        self.get(&directory)?.get(&file)
+    }
+
+    /// See also [`get_file`](Self::get_file).
+    #[must_use]
+    pub fn get_file_mut<P>(&mut self, path: &P) -> Option<&mut File<'bytes>>
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        let (directory, file) = Self::hash_path(path.as_ref());
+        self.get_mut(&directory)?.get_mut(&file)
+    }
+
+    /// Inserts a file using its full path, i.e. `dir\file.ext`.
+    ///
+    /// The parent directory is created if it does not already exist.
+    pub fn insert_file<P>(&mut self, path: &P, file: File<'bytes>) -> Option<File<'bytes>>
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        let (directory, name) = split_path(path.as_ref());
+        self.map
+            .entry(Key::from(directory))
+            .or_default()
+            .insert(DirectoryKey::from(name), file)
+    }
+
+    /// Removes a file using its full path, i.e. `dir\file.ext`.
+    ///
+    /// The parent directory is removed as well, if it is left empty.
+    pub fn remove_file<P>(&mut self, path: &P) -> Option<File<'bytes>>
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        let (directory_hash, file_hash) = Self::hash_path(path.as_ref());
+        let directory = self.get_mut(&directory_hash)?;
+        let file = directory.remove(&file_hash)?;
+        if directory.is_empty() {
+            self.remove(&directory_hash);
+        }
+        Some(file)
+    }
+
+    /// Moves a file into the given directory, keeping its file name.
+    ///
+    /// See also [`rename_file`](Self::rename_file).
+    pub fn move_file<P, D>(&mut self, path: &P, directory: &D) -> Result<()>
+    where
+        P: ?Sized + AsRef<[u8]>,
+        D: ?Sized + AsRef<[u8]>,
+    {
+        let (_, name) = split_path(path.as_ref());
+        let mut to = BString::from(directory.as_ref());
+        if !to.is_empty() {
+            to.push(b'\\');
+        }
+        to.extend_from_slice(name);
+        self.rename_file(path, &to)
+    }
+
+    /// Renames a file, using its full path before and after, i.e. `dir\file.ext`.
+    ///
+    /// The file is moved between directories as needed: the new parent directory is created if it does not already exist, and the old parent directory is removed if it is left empty. The file is moved as is, so data which is borrowed or memory-mapped from the source archive is never copied. Fails if no file exists at `from`, or if another file already exists at `to`, in which case the archive is left unchanged.
+    pub fn rename_file<P, Q>(&mut self, from: &P, to: &Q) -> Result<()>
+    where
+        P: ?Sized + AsRef<[u8]>,
+        Q: ?Sized + AsRef<[u8]>,
+    {
+        let key = |path: &[u8]| {
+            let (directory, name) = split_path(path);
+            (Key::from(directory), DirectoryKey::from(name))
+        };
+        let contains = |this: &Self, (directory, name): &(Key, DirectoryKey)| {
+            this.get(directory.hash())
+                .is_some_and(|directory| directory.get(name.hash()).is_some())
+        };
+        let from = key(from.as_ref());
+        let to = key(to.as_ref());
+
+        if !contains(self, &from) {
+            return Err(Error::FileNotFound(
+                Self::concat_directory_and_file_name(&from.0, &from.1).into_owned(),
+            ));
+        }
+        if (from.0.hash(), from.1.hash()) != (to.0.hash(), to.1.hash()) && contains(self, &to) {
+            return Err(Error::FileExists(
+                Self::concat_directory_and_file_name(&to.0, &to.1).into_owned(),
+            ));
+        }
+
+        let file = self
+            .get_mut(from.0.hash())
+            .and_then(|directory| directory.remove(from.1.hash()));
+        if self.get(from.0.hash()).is_some_and(Directory::is_empty) {
+            self.remove(from.0.hash());
+        }
+        if let Some(file) = file {
+            self.map.entry(to.0).or_default().insert(to.1, file);
+        }
+        Ok(())
+    }
+
+    /// Compresses every decompressed file in the archive which the given policy allows, using the given options.
+    ///
+    /// Files which are already compressed are left as they are. Every file retains its own compression state, so the archive may be written with or without [`ArchiveFlags::COMPRESSED`](tes4::ArchiveFlags::COMPRESSED), and the compression bit of each file is flipped as needed.
+    pub fn compress_files(
+        &mut self,
+        options: &FileCompressionOptions,
+        policy: &CompressionPolicy,
+    ) -> Result<()> {
+        self.compress_files_with_progress(options, policy, &mut Progress::default())
+    }
+
+    /// See also [`compress_files`](Self::compress_files).
+    ///
+    /// Every file is reported to `progress` once it has been considered, whether or not it was compressed. If the operation is cancelled, then the files which were already compressed remain so.
+    pub fn compress_files_with_progress(
+        &mut self,
+        options: &FileCompressionOptions,
+        policy: &CompressionPolicy,
+        progress: &mut Progress,
+    ) -> Result<()> {
+        progress.start(
+            self.values().map(Directory::len).sum(),
+            self.values()
+                .flat_map(Directory::values)
+                .map(File::len)
+                .sum(),
+        );
+        for (directory_key, directory) in &mut self.map {
+            for (key, file) in directory.iter_mut() {
+                if progress.is_cancelled() {
+                    return Err(Error::Cancelled);
+                }
+                let len = file.len();
+                if !file.is_compressed() && policy.allows(key.name()) {
+                    let compressed = file.compress(options)?;
+                    // compressed files are prefixed with their decompressed length
+                    if policy.keeps(compressed.len() + mem::size_of::<u32>(), file.len()) {
+                        *file = compressed;
+                    }
+                }
+                let name = Self::concat_directory_and_file_name(directory_key, key);
+                progress.advance(&name, len);
+            }
+        }
+        Ok(())
+    }
+
+    /// Computes the archive types which describe the contents of the archive.
+    ///
+    /// Every file is classified by its extension, i.e. `.nif`, `.dds`, `.fuz`, etc., in the same manner as the official archive tools, except that sounds within `sound\voice` are considered voices. Files with an unrecognized extension are classified by the top-level directory they reside in instead, and files which can not be classified otherwise are considered misc. Files without names are ignored, since they can not be classified.
+    #[must_use]
+    pub fn infer_types(&self) -> Types {
+        let mut types = Types::empty();
+        for (directory_key, directory) in self {
+            for file_key in directory.keys() {
+                if !file_key.name().is_empty() {
+                    types |= classify(directory_key.name(), file_key.name());
+                }
+            }
+        }
+        types
+    }
+
+    /// Compares `self` (the old archive) against `other` (the new archive).
+    ///
+    /// Each archive must be paired with the options it was read with, so that its files can be decompressed for comparison.
+    pub fn diff(&self, options: &Options, other: &Self, other_options: &Options) -> Result<Diff> {
+        Diff::new((self, options), (other, other_options))
+    }
+
+    /// Merges several archives into one, resolving duplicate files using the given policy.
+    ///
+    /// See also [`merge_with`](Self::merge_with).
+    pub fn merge<I>(sources: I, policy: MergePolicy) -> Result<(Self, Options)>
+    where
+        I: IntoIterator<Item = (Self, Options)>,
+    {
+        Self::merge_with(
+            sources,
+            |directory, file, existing, incoming| match policy {
+                MergePolicy::FirstWins => Ok(existing),
+                MergePolicy::LastWins => Ok(incoming),
+                MergePolicy::Error => Err(Error::MergeConflict(
+                    Self::concat_directory_and_file_name(directory, file).into_owned(),
+                )),
+            },
+        )
+    }
+
+    /// Merges several archives into one, resolving duplicate files using the given callback.
+    ///
+    /// The callback is given the keys of the duplicate file, the file merged so far, and the file from the next archive, and returns the file to keep.
+    ///
+    /// The merged archive uses the version and flags of the first archive, since flags such as [`XBOX_ARCHIVE`](Flags::XBOX_ARCHIVE) describe the layout of the archive rather than its contents. Archive types are unioned, since they describe the files contained within. Compressed files from an archive with a different version or compression codec are decompressed, since their compression would not be valid in the merged archive.
+    pub fn merge_with<I, F>(sources: I, mut resolve: F) -> Result<(Self, Options)>
+    where
+        I: IntoIterator<Item = (Self, Options)>,
+        F: FnMut(
+            &Key<'bytes>,
+            &DirectoryKey<'bytes>,
+            File<'bytes>,
+            File<'bytes>,
+        ) -> Result<File<'bytes>>,
+    {
+        let mut merged = Self::new();
+        let mut merged_options: Option<Options> = None;
+        for (archive, options) in sources {
+            let merged_options = merged_options.get_or_insert(options);
+            merged_options.types |= options.types;
+            let compression_options = (options.version != merged_options.version
+                || options.flags.xbox_compressed() != merged_options.flags.xbox_compressed())
+            .then(|| FileCompressionOptions::from(&options));
+
+            for (directory_key, directory) in archive {
+                let (directory_key, mut merged_directory) =
+                    match merged.remove_entry(directory_key.hash()) {
+                        Some((existing_key, existing)) if !existing_key.name().is_empty() => {
+                            (existing_key, existing)
+                        }
+                        Some((_, existing)) => (directory_key, existing),
+                        None => (directory_key, Directory::new()),
+                    };
+                for (file_key, mut file) in directory {
+                    if let Some(compression_options) = &compression_options {
+                        if file.is_compressed() {
+                            file = file.decompress(compression_options)?;
+                        }
+                    }
+                    let (key, file) = match merged_directory.remove_entry(file_key.hash()) {
+                        Some((existing_key, existing)) => {
+                            let key = if existing_key.name().is_empty() {
+                                file_key
+                            } else {
+                                existing_key
+                            };
+                            let file = resolve(&directory_key, &key, existing, file)?;
+                            (key, file)
+                        }
+                        None => (file_key, file),
+                    };
+                    merged_directory.insert(key, file);
+                }
+                merged.map.insert(directory_key, merged_directory);
+            }
+        }
+
+        Ok((merged, merged_options.unwrap_or_default()))
+    }
+
     pub fn write<Out>(&self, stream: &mut Out, options: &Options) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        self.write_with_layout(stream, options, &Layout::default())
+    }
+
+    /// See also [`write`](Self::write).
+    ///
+    /// The index is written first, followed by the data of each file in turn, which is written directly from the archive, so nothing but the index is buffered, and the executor is yielded to between files.
+    #[cfg(feature = "async")]
+    pub async fn write_async<Out>(&self, stream: &mut Out, options: &Options) -> Result<()>
+    where
+        Out: ?Sized + AsyncWrite + Unpin,
+    {
+        let options = *options;
+        let layout = Layout::default();
+        let (header, directories) = self.plan_write(options, &layout)?;
+        let files = Self::files_by_id(&directories);
+        let data = Self::plan_data(&header, &layout, &directories, &files);
+        let mut buffer = Vec::new();
+        Self::write_index(
+            &mut Sink::new(&mut buffer),
+            options,
+            &header,
+            &layout,
+            &directories,
+            &data,
+        )?;
+        stream.write_all(&buffer).await?;
+        for slot in &data.slots {
+            let file = files[&slot.id];
+            buffer.clear();
+            let mut sink = Sink::new(&mut buffer);
+            sink.write_bytes(slot.padding)?;
+            Self::write_file_prefix(&mut sink, file.this, file.embedded_name.as_deref())?;
+            stream.write_all(&buffer).await?;
+            stream.write_all(file.this.as_bytes()).await?;
+        }
+        stream.write_all(data.trailing).await?;
+        Ok(())
+    }
+
+    /// Writes the archive using the given options and layout.
+    ///
+    /// Directories and files are written in the same order, and at the same positions, as they were when the layout was captured, along with any header fields that would otherwise be discarded, so writing an unmodified archive using the options it was read with reproduces the original byte-for-byte. Directories and files which were added since are written after all others, and those which were removed are simply skipped.
+    pub fn write_with_layout<Out>(
+        &self,
+        stream: &mut Out,
+        options: &Options,
+        layout: &Layout,
+    ) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        self.do_write(stream, *options, layout, &mut Progress::default())
+    }
+
+    /// See also [`write`](Self::write).
+    ///
+    /// Every file is reported to `progress` once its data has been written. If the operation is cancelled, then the archive is left partially written.
+    pub fn write_with_progress<Out>(
+        &self,
+        stream: &mut Out,
+        options: &Options,
+        progress: &mut Progress,
+    ) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        self.do_write(stream, *options, &Layout::default(), progress)
+    }
+
+    /// Computes the length of the archive as it would be written by [`write`](Self::write), using the given options, without writing it.
+    ///
+    /// Fails in the same cases as [`write`](Self::write) would, i.e. when an offset, a length, or a count would not fit within the fields of the archive format, or when a name is too long to be stored.
+    pub fn written_len(&self, options: &Options) -> Result<WrittenLen> {
+        self.written_len_with_layout(options, &Layout::default())
+    }
+
+    /// See also [`written_len`](Self::written_len).
+    ///
+    /// Computes the length of the archive as it would be written by [`write_with_layout`](Self::write_with_layout). Any padding or trailing bytes preserved by the layout are counted as data.
+    pub fn written_len_with_layout(
+        &self,
+        options: &Options,
+        layout: &Layout,
+    ) -> Result<WrittenLen> {
+        let options = *options;
+        let (header, directories) = self.plan_write(options, layout)?;
+        let files = Self::files_by_id(&directories);
+        let data = Self::plan_data(&header, layout, &directories, &files);
+        let mut stream = std::io::sink();
+        let mut sink = Sink::new(&mut stream);
+        Self::write_index(&mut sink, options, &header, layout, &directories, &data)?;
+        for slot in &data.slots {
+            let file = files[&slot.id];
+            Self::write_file_prefix(&mut sink, file.this, file.embedded_name.as_deref())?;
+        }
+
+        let offsets = header.compute_offsets();
+        let directory_names = if options.flags.directory_strings() {
+            // bzstrings are prefixed with their length
+            header.directory_names_len as usize + header.directory_count as usize
+        } else {
+            0
+        };
+        let file_names = if options.flags.file_strings() {
+            header.file_names_len as usize
+        } else {
+            0
+        };
+        let names = directory_names + file_names;
+        let header_len = constants::HEADER_SIZE as usize;
+        Ok(WrittenLen {
+            header: header_len,
+            index: offsets
+                .file_data
+                .checked_sub(header_len)
+                .and_then(|x| x.checked_sub(names))
+                .ok_or(Error::IntegralOverflow)?,
+            names,
+            data: data
+                .end
+                .checked_sub(offsets.file_data)
+                .ok_or(Error::IntegralOverflow)?,
+        })
+    }
+
+    /// Appends the files which are not current within `storage` to the end of `stream`, and then rewrites the index.
+    ///
+    /// Directories and files are sorted as they would be by [`write`](Self::write), since the index is rewritten in full anyways.
+    pub(super) fn write_in_place(
+        &self,
+        stream: &mut fs::File,
+        options: Options,
+        layout: &Layout,
+        storage: &Storage<FileId>,
+    ) -> Result<()> {
+        let header = Header {
+            padding: layout.header_padding,
+            ..self.make_header(options)?
+        };
+        let directories = self.sort_for_write(options);
+        let (data, appended) = storage.allocate(
+            header.compute_offsets().file_data,
+            Self::sections(&directories),
+        );
+
+        // data must be written first, since the index may grow over files which are being relocated
+        if let Some(first) = appended.first() {
+            let files = Self::files_by_id(&directories);
+            stream.seek(SeekFrom::Start(data.offset(first).try_into()?))?;
+            let mut stream = BufWriter::new(&mut *stream);
+            let mut sink = Sink::new(&mut stream);
+            for id in &appended {
+                let file = files[id];
+                Self::write_file_data(&mut sink, file.this, file.embedded_name.as_deref())?;
+            }
+            stream.flush()?;
+        }
+
+        let mut index = Vec::new();
+        Self::write_index(
+            &mut Sink::new(&mut index),
+            options,
+            &header,
+            layout,
+            &directories,
+            &data,
+        )?;
+        stream.seek(SeekFrom::Start(0))?;
+        stream.write_all(&index)?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    /// Moves every file within `storage` down towards the index, and then rewrites the index, returning the new length of the file.
+    pub(super) fn compact_in_place(
+        &self,
+        stream: &mut fs::File,
+        options: Options,
+        layout: &Layout,
+        storage: &Storage<FileId>,
+    ) -> Result<usize> {
+        let header = Header {
+            padding: layout.header_padding,
+            ..self.make_header(options)?
+        };
+        let directories = self.sort_for_write(options);
+        let (data, moves) = storage.compact(
+            header.compute_offsets().file_data,
+            Self::sections(&directories).into_iter().map(|(id, _)| id),
+        );
+        for x in moves {
+            io::copy_within(stream, x.from, x.to, x.len)?;
+        }
+
+        let mut index = Vec::new();
+        Self::write_index(
+            &mut Sink::new(&mut index),
+            options,
+            &header,
+            layout,
+            &directories,
+            &data,
+        )?;
+        stream.seek(SeekFrom::Start(0))?;
+        stream.write_all(&index)?;
+        stream.flush()?;
+        Ok(data.end)
+    }
+
+    fn do_write<Out>(
+        &self,
+        stream: &mut Out,
+        options: Options,
+        layout: &Layout,
+        progress: &mut Progress,
+    ) -> Result<()>
     where
         Out: ?Sized + Write,
     {
         let mut sink = Sink::new(stream);
-        let header = self.make_header(*options)?;
-        Self::write_header(&mut sink, &header)?;
+        let (header, directories) = self.plan_write(options, layout)?;
+        let files = Self::files_by_id(&directories);
+        let data = Self::plan_data(&header, layout, &directories, &files);
+        Self::write_index(&mut sink, options, &header, layout, &directories, &data)?;
+        let directory_keys: BTreeMap<_, _> = directories
+            .iter()
+            .map(|directory| (*directory.key.hash(), directory.key))
+            .collect();
+        progress.start(
+            data.slots.len(),
+            data.slots.iter().map(|x| files[&x.id].this.len()).sum(),
+        );
+        for slot in &data.slots {
+            if progress.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let file = files[&slot.id];
+            sink.write_bytes(slot.padding)?;
+            Self::write_file_data(&mut sink, file.this, file.embedded_name.as_deref())?;
+            if let Some(directory) = directory_keys.get(&slot.id.0) {
+                let name = Self::concat_directory_and_file_name(directory, file.key);
+                progress.advance(&name, file.this.len());
+            }
+        }
+        sink.write_bytes(data.trailing)?;
+
+        Ok(())
+    }
+
+    /// Makes the header, and sorts the directories and files for writing.
+    fn plan_write<'this>(
+        &'this self,
+        options: Options,
+        layout: &Layout,
+    ) -> Result<(Header, Vec<SortedDirectory<'this, 'bytes>>)> {
+        let header = Header {
+            padding: layout.header_padding,
+            ..self.make_header(options)?
+        };
+
+        let mut directories = self.sort_for_write(options);
+        Order::new(layout.directories.iter().map(|(hash, _)| hash))
+            .arrange(&mut directories, |x| x.key.hash());
+        let order = Order::new(layout.files.iter().map(|(id, _)| *id));
+        for directory in &mut directories {
+            let hash = *directory.key.hash();
+            order.arrange(&mut directory.files, |x| (hash, *x.key.hash()));
+        }
+        Ok((header, directories))
+    }
+
+    /// Plans where the data of each file is written.
+    fn plan_data<'layout>(
+        header: &Header,
+        layout: &'layout Layout,
+        directories: &[SortedDirectory<'_, 'bytes>],
+        files: &BTreeMap<FileId, &SortedFile<'_, 'bytes>>,
+    ) -> Plan<'layout, FileId> {
+        layout.data.plan(
+            header.compute_offsets().file_data,
+            Self::sections(directories),
+            |lhs, rhs| {
+                let (lhs, rhs) = (files[lhs], files[rhs]);
+                // shared data also shares the embedded name of whichever file was written first
+                let name_len = |x: &SortedFile| x.embedded_name.as_ref().map(|x| x.len());
+                lhs.this.as_bytes() == rhs.this.as_bytes()
+                    && lhs.this.decompressed_len() == rhs.this.decompressed_len()
+                    && name_len(lhs) == name_len(rhs)
+            },
+        )
+    }
+
+    fn files_by_id<'this>(
+        directories: &'this [SortedDirectory<'this, 'bytes>],
+    ) -> BTreeMap<(DirectoryHash, FileHash), &'this SortedFile<'this, 'bytes>> {
+        directories
+            .iter()
+            .flat_map(|directory| {
+                let hash = *directory.key.hash();
+                directory
+                    .files
+                    .iter()
+                    .map(move |file| ((hash, *file.key.hash()), file))
+            })
+            .collect()
+    }
+
+    /// Lists the data of every file, along with its length.
+    fn sections(
+        directories: &[SortedDirectory<'_, 'bytes>],
+    ) -> Vec<((DirectoryHash, FileHash), usize)> {
+        directories
+            .iter()
+            .flat_map(|directory| {
+                let hash = *directory.key.hash();
+                directory.files.iter().map(move |file| {
+                    let size = Self::file_data_size(file.this, file.embedded_name.as_deref());
+                    ((hash, *file.key.hash()), size)
+                })
+            })
+            .collect()
+    }
+
+    fn write_index<Out>(
+        sink: &mut Sink<Out>,
+        options: Options,
+        header: &Header,
+        layout: &Layout,
+        directories: &[SortedDirectory<'_, 'bytes>],
+        data: &Plan<'_, (DirectoryHash, FileHash)>,
+    ) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        Self::write_header(sink, header)?;
 
         let offsets = header.compute_offsets();
-        let directories = self.sort_for_write(*options);
+        let directory_records: BTreeMap<_, _> = layout.directories.iter().copied().collect();
+        let file_records: BTreeMap<_, _> = layout.files.iter().copied().collect();
 
         // let mut file_entries_offset = offsets.file_entries + header.file_names_len;
         let mut file_entries_offset = u32::try_from(offsets.file_entries)?
             .checked_add(header.file_names_len)
             .ok_or(Error::IntegralOverflow)?;
-        for directory in &directories {
+        for directory in directories {
             Self::write_directory_entry(
-                &mut sink,
-                *options,
+                sink,
+                options,
                 directory.key,
                 directory.this,
+                directory_records
+                    .get(directory.key.hash())
+                    .copied()
+                    .unwrap_or_default(),
                 &mut file_entries_offset,
             )?;
         }
 
-        let mut file_data_offset = u32::try_from(offsets.file_data)?;
-        for directory in &directories {
+        for directory in directories {
             if options.flags.directory_strings() {
                 sink.write_protocol::<BZString>(directory.key.name(), Endian::Little)?;
             }
             for file in &directory.files {
+                let id = (*directory.key.hash(), *file.key.hash());
                 Self::write_file_entry(
-                    &mut sink,
-                    *options,
+                    sink,
+                    options,
                     file.key,
                     file.this,
-                    &mut file_data_offset,
-                    file.embedded_name.as_ref().map(AsRef::as_ref),
+                    data.offset(&id).try_into()?,
+                    file_records.get(&id).copied().unwrap_or_default(),
+                    file.embedded_name.as_deref(),
                 )?;
             }
         }
 
         if options.flags.file_strings() {
-            for directory in &directories {
+            for directory in directories {
                 for file in &directory.files {
                     sink.write_protocol::<ZString>(file.key.name(), Endian::Little)?;
                 }
             }
         }
 
-        for directory in &directories {
-            for file in &directory.files {
-                Self::write_file_data(
-                    &mut sink,
-                    file.this,
-                    file.embedded_name.as_ref().map(AsRef::as_ref),
-                )?;
-            }
-        }
-
         Ok(())
     }
 
@@ -466,7 +1246,12 @@ impl<'bytes> Archive<'bytes> {
             file_count: files.count.try_into()?,
             directory_names_len: directories.names_len.try_into()?,
             file_names_len: files.names_len.try_into()?,
-            archive_types: options.types,
+            archive_types: if options.infer_types {
+                self.infer_types()
+            } else {
+                options.types
+            },
+            padding: 0,
         })
     }
 
@@ -502,6 +1287,22 @@ impl<'bytes> Archive<'bytes> {
         }
     }
 
+    #[must_use]
+    pub(super) fn hash_path(path: &[u8]) -> (DirectoryHash, FileHash) {
+        let (directory, file) = split_path(path);
+        (tes4::hash_directory(directory).0, tes4::hash_file(file).0)
+    }
+
+    #[must_use]
+    fn query_entry(
+        directory: &Key<'bytes>,
+        key: &DirectoryKey<'bytes>,
+        file: &File<'bytes>,
+    ) -> QueryEntry {
+        let path = Self::concat_directory_and_file_name(directory, key);
+        QueryEntry::new(&path, file.len(), file.decompressed_len())
+    }
+
     fn sort_for_write<'this>(&'this self, options: Options) -> Vec<SortedDirectory<'this, 'bytes>> {
         let mut directories: Vec<_> = self
             .iter()
@@ -548,6 +1349,7 @@ impl<'bytes> Archive<'bytes> {
         options: Options,
         key: &Key<'bytes>,
         directory: &Directory<'bytes>,
+        record: DirectoryRecord,
         file_entries_offset: &mut u32,
     ) -> Result<()>
     where
@@ -559,13 +1361,16 @@ impl<'bytes> Archive<'bytes> {
         sink.write(&file_count, Endian::Little)?;
 
         if options.version == Version::v105 {
-            sink.write(&0u32, Endian::Little)?;
+            sink.write(&record.padding[0], Endian::Little)?;
         }
 
-        sink.write(file_entries_offset, Endian::Little)?;
+        sink.write(
+            &file_entries_offset.wrapping_add(record.offset_delta),
+            Endian::Little,
+        )?;
 
         if options.version == Version::v105 {
-            sink.write(&0u32, Endian::Little)?;
+            sink.write(&record.padding[1], Endian::Little)?;
         }
 
         if options.flags.directory_strings() {
@@ -595,6 +1400,20 @@ impl<'bytes> Archive<'bytes> {
         file: &File<'bytes>,
         embedded_file_name: Option<&BStr>,
     ) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        Self::write_file_prefix(sink, file, embedded_file_name)?;
+        sink.write_bytes(file.as_bytes())?;
+        Ok(())
+    }
+
+    /// Writes everything which precedes the data of a file, i.e. its embedded name and decompressed length.
+    fn write_file_prefix<Out>(
+        sink: &mut Sink<Out>,
+        file: &File<'bytes>,
+        embedded_file_name: Option<&BStr>,
+    ) -> Result<()>
     where
         Out: ?Sized + Write,
     {
@@ -607,7 +1426,6 @@ impl<'bytes> Archive<'bytes> {
             sink.write(&len, Endian::Little)?;
         }
 
-        sink.write_bytes(file.as_bytes())?;
         Ok(())
     }
 
@@ -616,7 +1434,8 @@ impl<'bytes> Archive<'bytes> {
         options: Options,
         key: &DirectoryKey<'bytes>,
         file: &File<'bytes>,
-        file_data_offset: &mut u32,
+        file_data_offset: u32,
+        record: FileRecord,
         embedded_file_name: Option<&BStr>,
     ) -> Result<()>
     where
@@ -624,38 +1443,38 @@ impl<'bytes> Archive<'bytes> {
     {
         Self::write_hash(sink, options, (*key.hash()).into())?;
 
-        let (size_with_info, size) = {
-            let mut size = file.len();
-            if let Some(name) = embedded_file_name {
-                // include prefix byte
-                size += name.len() + 1;
-            }
-            if file.is_compressed() {
-                size += mem::size_of::<u32>();
-            }
-
-            let size: u32 = size.try_into()?;
+        let size_with_info = {
+            let size: u32 = Self::file_data_size(file, embedded_file_name).try_into()?;
             let masked = size & !(constants::FILE_FLAG_COMPRESSION | constants::FILE_FLAG_CHECKED);
             if masked != size {
                 return Err(Error::IntegralTruncation);
             }
 
             if file.is_compressed() == options.flags.compressed() {
-                (size, masked)
+                size | record.size_flags
             } else {
-                (size | constants::FILE_FLAG_COMPRESSION, masked)
+                size | record.size_flags | constants::FILE_FLAG_COMPRESSION
             }
         };
-        sink.write(&(size_with_info, *file_data_offset), Endian::Little)?;
-
-        // file_data_offset += size;
-        *file_data_offset = file_data_offset
-            .checked_add(size)
-            .ok_or(Error::IntegralOverflow)?;
+        let file_data_offset = file_data_offset | record.offset_flags;
+        sink.write(&(size_with_info, file_data_offset), Endian::Little)?;
 
         Ok(())
     }
 
+    #[must_use]
+    fn file_data_size(file: &File<'bytes>, embedded_file_name: Option<&BStr>) -> usize {
+        let mut size = file.len();
+        if let Some(name) = embedded_file_name {
+            // include prefix byte
+            size += name.len() + 1;
+        }
+        if file.is_compressed() {
+            size += mem::size_of::<u32>();
+        }
+        size
+    }
+
     fn write_hash<Out>(sink: &mut Sink<Out>, options: Options, hash: Hash) -> Result<()>
     where
         Out: ?Sized + Write,
@@ -690,7 +1509,7 @@ impl<'bytes> Archive<'bytes> {
                 header.directory_names_len,
                 header.file_names_len,
                 header.archive_types.bits(),
-                0u16,
+                header.padding,
             ),
             Endian::Little,
         )?;
@@ -698,24 +1517,52 @@ impl<'bytes> Archive<'bytes> {
     }
 
     fn do_read<In>(source: &mut In) -> Result<ReadResult<Self>>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        Self::read_archive(source, None)
+    }
+
+    fn do_read_with_layout<In>(source: &mut In) -> Result<LayoutResult<Self>>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        let mut capture = Capture::default();
+        let (archive, options) = Self::read_archive(source, Some(&mut capture))?;
+        capture.layout.data = Region::capture(source.as_bytes(), capture.file_data, capture.data);
+        Ok((archive, options, capture.layout))
+    }
+
+    fn read_archive<In>(
+        source: &mut In,
+        mut capture: Option<&mut Capture>,
+    ) -> Result<ReadResult<Self>>
     where
         In: ?Sized + Source<'bytes>,
     {
         let header = Self::read_header(source)?;
         let mut offsets = header.compute_offsets();
+        let file_data = offsets.file_data;
         let mut map = Map::default();
 
         for _ in 0..header.directory_count {
-            let (key, value) = Self::read_directory(source, &header, &mut offsets)?;
+            let (key, value) =
+                Self::read_directory(source, &header, &mut offsets, capture.as_deref_mut())?;
             map.insert(key, value);
         }
 
+        if let Some(capture) = capture {
+            capture.layout.header_padding = header.padding;
+            capture.file_data = file_data;
+        }
+
         Ok((
             Self { map },
             Options {
                 version: header.version,
                 flags: header.archive_flags,
                 types: header.archive_types,
+                infer_types: false,
             },
         ))
     }
@@ -724,16 +1571,31 @@ impl<'bytes> Archive<'bytes> {
         source: &mut In,
         header: &Header,
         offsets: &mut Offsets,
+        mut capture: Option<&mut Capture>,
     ) -> Result<(Key<'bytes>, Directory<'bytes>)>
     where
         In: ?Sized + Source<'bytes>,
     {
-        let hash = Self::read_hash(source, header.hash_endian())?;
+        let hash: DirectoryHash = Self::read_hash(source, header.hash_endian())?.into();
         let file_count: u32 = source.read(Endian::Little)?;
-        #[allow(clippy::cast_possible_wrap)]
-        match header.version {
-            Version::v103 | Version::v104 => source.seek_relative(mem::size_of::<u32>() as isize)?,
-            Version::v105 => source.seek_relative((mem::size_of::<u32>() * 3) as isize)?,
+        let (padding, file_entries_offset) = match header.version {
+            Version::v103 | Version::v104 => ([0, 0], source.read(Endian::Little)?),
+            Version::v105 => {
+                let (first, offset, second): (u32, u32, u32) = source.read(Endian::Little)?;
+                ([first, second], offset)
+            }
+        };
+
+        if let Some(capture) = capture.as_deref_mut() {
+            // the offset we would have written is relative to the file names, which come after
+            let expected = u32::try_from(offsets.file_entries)?.wrapping_add(header.file_names_len);
+            capture.layout.directories.push((
+                hash,
+                DirectoryRecord {
+                    padding,
+                    offset_delta: file_entries_offset.wrapping_sub(expected),
+                },
+            ));
         }
 
         let mut map = DirectoryMap::default();
@@ -746,7 +1608,15 @@ impl<'bytes> Archive<'bytes> {
                     None
                 };
                 for _ in 0..file_count {
-                    let (key, value) = Self::read_file_entry(source, header, offsets, &mut name)?;
+                    let (key, value, record) =
+                        Self::read_file_entry(source, header, offsets, &mut name)?;
+                    if let Some(capture) = capture.as_deref_mut() {
+                        let id = (hash, *key.hash());
+                        capture.layout.files.push((id, record.flags));
+                        capture
+                            .data
+                            .push((id, record.data_offset, record.data_size));
+                    }
                     map.insert(key, value);
                 }
                 offsets.file_entries = source.stream_position();
@@ -754,13 +1624,7 @@ impl<'bytes> Archive<'bytes> {
             },
         )??;
 
-        Ok((
-            Key {
-                hash: hash.into(),
-                name,
-            },
-            directory,
-        ))
+        Ok((Key { hash, name }, directory))
     }
 
     fn read_file_entry<In>(
@@ -768,19 +1632,23 @@ impl<'bytes> Archive<'bytes> {
         header: &Header,
         offsets: &mut Offsets,
         directory_name: &mut Option<Bytes<'bytes>>,
-    ) -> Result<(DirectoryKey<'bytes>, File<'bytes>)>
+    ) -> Result<(DirectoryKey<'bytes>, File<'bytes>, FileEntry)>
     where
         In: ?Sized + Source<'bytes>,
     {
         let hash = Self::read_hash(source, header.hash_endian())?;
-        let (compression_flipped, mut data_size, data_offset) = {
-            let (size, offset): (u32, u32) = source.read(Endian::Little)?;
-            (
-                (size & constants::FILE_FLAG_COMPRESSION) != 0,
-                (size & !(constants::FILE_FLAG_COMPRESSION | constants::FILE_FLAG_CHECKED))
-                    as usize,
-                (offset & !constants::FILE_FLAG_SECONDARY_ARCHIVE) as usize,
-            )
+        let (size, offset): (u32, u32) = source.read(Endian::Little)?;
+        let compression_flipped = (size & constants::FILE_FLAG_COMPRESSION) != 0;
+        let data_size =
+            (size & !(constants::FILE_FLAG_COMPRESSION | constants::FILE_FLAG_CHECKED)) as usize;
+        let data_offset = (offset & !constants::FILE_FLAG_SECONDARY_ARCHIVE) as usize;
+        let entry = FileEntry {
+            flags: FileRecord {
+                size_flags: size & constants::FILE_FLAG_CHECKED,
+                offset_flags: offset & constants::FILE_FLAG_SECONDARY_ARCHIVE,
+            },
+            data_offset,
+            data_size,
         };
 
         let mut name = if header.archive_flags.file_strings() {
@@ -794,41 +1662,35 @@ impl<'bytes> Archive<'bytes> {
             None
         };
 
-        let container =
-            source.save_restore_position(|source| -> Result<CompressableBytes<'bytes>> {
+        let compressed = header.archive_flags.compressed() != compression_flipped;
+        let (embedded_name, container) = source.save_restore_position(
+            |source| -> Result<(Option<Bytes<'bytes>>, CompressableBytes<'bytes>)> {
                 source.seek_absolute(data_offset)?;
-
-                if matches!(header.version,
-                    Version::v104 | Version::v105 if header.archive_flags.embedded_file_names())
-                {
-                    let mut s = source.read_protocol::<protocols::BString>(Endian::Little)?;
-                    data_size -= s.len() + 1; // include prefix byte
-                    if let Some(pos) = s.as_bytes().iter().rposition(|&x| x == b'\\' || x == b'/') {
-                        if directory_name.is_none() {
-                            *directory_name = Some(s.copy_slice(0..pos));
-                        }
-                        s = s.copy_slice(pos + 1..s.len());
-                    }
-                    if name.is_none() {
-                        name = Some(s);
-                    }
+                if source.is_deferred(data_offset, data_size) {
+                    // the prefix will be read once the data has been fetched
+                    let bytes = source.read_blob(data_size)?;
+                    Ok((None, bytes.into_compressable(compressed.then_some(0))))
+                } else {
+                    Self::read_file_data(
+                        source,
+                        header.version,
+                        header.archive_flags,
+                        data_size,
+                        compressed,
+                    )
                 }
+            },
+        )??;
 
-                let decompressed_len =
-                    match (header.archive_flags.compressed(), compression_flipped) {
-                        (true, false) | (false, true) => {
-                            let result: u32 = source.read(Endian::Little)?;
-                            data_size -= mem::size_of::<u32>();
-                            Some(result as usize)
-                        }
-                        (true, true) | (false, false) => None,
-                    };
-
-                let container = source
-                    .read_bytes(data_size)?
-                    .into_compressable(decompressed_len);
-                Ok(container)
-            })??;
+        if let Some(embedded_name) = embedded_name {
+            let (embedded_directory, embedded_file) = Self::split_embedded_name(embedded_name);
+            if directory_name.is_none() {
+                *directory_name = embedded_directory;
+            }
+            if name.is_none() {
+                name = Some(embedded_file);
+            }
+        }
 
         Ok((
             DirectoryKey {
@@ -836,9 +1698,61 @@ impl<'bytes> Archive<'bytes> {
                 name: name.unwrap_or_default(),
             },
             File { bytes: container },
+            entry,
         ))
     }
 
+    /// Splits a name which was embedded before the data of a file into the name of its directory, if it has one, and the name of the file.
+    #[must_use]
+    fn split_embedded_name(name: Bytes<'bytes>) -> (Option<Bytes<'bytes>>, Bytes<'bytes>) {
+        match name
+            .as_bytes()
+            .iter()
+            .rposition(|&x| x == b'\\' || x == b'/')
+        {
+            Some(pos) => (
+                Some(name.copy_slice(0..pos)),
+                name.copy_slice(pos + 1..name.len()),
+            ),
+            None => (None, name),
+        }
+    }
+
+    /// Reads the data of a file, which is `data_size` bytes long, along with the name embedded before it, if any.
+    fn read_file_data<In>(
+        source: &mut In,
+        version: Version,
+        flags: Flags,
+        mut data_size: usize,
+        compressed: bool,
+    ) -> Result<(Option<Bytes<'bytes>>, CompressableBytes<'bytes>)>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        let embedded_name = if matches!(version,
+            Version::v104 | Version::v105 if flags.embedded_file_names())
+        {
+            let s = source.read_protocol::<protocols::BString>(Endian::Little)?;
+            data_size -= s.len() + 1; // include prefix byte
+            Some(s)
+        } else {
+            None
+        };
+
+        let decompressed_len = if compressed {
+            let result: u32 = source.read(Endian::Little)?;
+            data_size -= mem::size_of::<u32>();
+            Some(result as usize)
+        } else {
+            None
+        };
+
+        let container = source
+            .read_bytes(data_size)?
+            .into_compressable(decompressed_len);
+        Ok((embedded_name, container))
+    }
+
     fn read_hash<In>(source: &mut In, endian: Endian) -> Result<Hash>
     where
         In: ?Sized + Source<'bytes>,
@@ -869,7 +1783,6 @@ impl<'bytes> Archive<'bytes> {
             archive_types,
             padding,
         ) = source.read(Endian::Little)?;
-        let _: u16 = padding;
 
         if magic != constants::BSA {
             return Err(Error::InvalidMagic(magic));
@@ -898,19 +1811,73 @@ impl<'bytes> Archive<'bytes> {
             directory_names_len,
             file_names_len,
             archive_types,
+            padding,
         })
     }
 }
 
+impl Archive<'static> {
+    /// Reads the archive within `stream` into owned buffers, along with where the data of each file lies within it.
+    pub(super) fn read_in_place(
+        stream: &fs::File,
+    ) -> Result<(Self, Options, Layout, Storage<FileId>)> {
+        let mut source = BufferedSource::try_from(stream)?;
+        Self::read_storage(&mut source)
+    }
+
+    /// See also [`read_in_place`](Self::read_in_place).
+    pub(super) fn read_storage<In>(
+        source: &mut In,
+    ) -> Result<(Self, Options, Layout, Storage<FileId>)>
+    where
+        In: ?Sized + Source<'static>,
+    {
+        let mut capture = Capture::default();
+        let (archive, options) = Self::read_archive(source, Some(&mut capture))?;
+        let storage = Storage::new(capture.data, capture.file_data, source.stream_len());
+        Ok((archive, options, capture.layout, storage))
+    }
+
+    /// Reads the data of a file whose data was deferred while reading the archive, once it has been fetched.
+    ///
+    /// The name embedded before the data, if any, is returned alongside it, split into the name of its directory, if it has one, and the name of the file.
+    #[cfg(feature = "async")]
+    #[allow(clippy::type_complexity)]
+    pub(super) fn read_deferred_file(
+        bytes: &[u8],
+        options: Options,
+        compressed: bool,
+    ) -> Result<(
+        File<'static>,
+        Option<(Option<Bytes<'static>>, Bytes<'static>)>,
+    )> {
+        let mut source = BorrowedSource::from(bytes);
+        let (embedded_name, container) = Archive::read_file_data(
+            &mut source,
+            options.version,
+            options.flags,
+            bytes.len(),
+            compressed,
+        )?;
+        let file = File {
+            bytes: container.into_owned(),
+        };
+        let embedded_name = embedded_name.map(|x| Archive::split_embedded_name(x.into_owned()));
+        Ok((file, embedded_name))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
+        layout::Region,
         prelude::*,
         tes4::{
-            Archive, ArchiveFlags, ArchiveKey, ArchiveOptions, Directory, DirectoryKey, Error,
-            File, FileCompressionOptions, Version,
+            Archive, ArchiveFlags, ArchiveKey, ArchiveLayout, ArchiveOptions, ArchiveTypes,
+            Directory, DirectoryKey, Error, File, FileCompressionOptions, Version,
         },
-        Borrowed,
+        Borrowed, Buffered, CancellationToken, CompressionPolicy, Glob, MergePolicy, Progress,
+        ProgressEvent, QueryEntry,
     };
     use anyhow::Context as _;
     use memmap2::Mmap;
@@ -984,6 +1951,34 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn read_buffered() -> anyhow::Result<()> {
+        for path in [
+            "data/tes4_compression_test/test_104.bsa",
+            "data/tes4_compression_test/test_105.bsa",
+            "data/tes4_xbox_read_test/xbox.bsa",
+        ] {
+            let (mapped, mapped_options) = Archive::read(Path::new(path))?;
+            let fd = fs::File::open(path)?;
+            let (buffered, buffered_options) = Archive::read(Buffered(&fd))?;
+            assert_eq!(mapped_options.flags(), buffered_options.flags());
+            assert!(mapped
+                .diff(&mapped_options, &buffered, &buffered_options)?
+                .is_empty());
+            for (directory, files) in &mapped {
+                for (key, file) in files {
+                    let other = buffered
+                        .get(directory)
+                        .and_then(|x| x.get(key))
+                        .context("file was missing")?;
+                    assert_eq!(other.as_bytes(), file.as_bytes());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn xbox_decompressed_read() -> anyhow::Result<()> {
         let root = Path::new("data/tes4_xbox_read_test");
@@ -1330,4 +2325,541 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn full_path_access() -> anyhow::Result<()> {
+        let path = Path::new("data/tes4_data_sharing_name_test/share.bsa");
+        let (mut archive, _) = Archive::read(path).context("failed to read archive")?;
+
+        let file = archive
+            .get_file("misc1/example1.txt")
+            .context("failed to get file by forward slash path")?;
+        assert_eq!(file.as_bytes(), b"hello world!");
+        assert!(archive.get_file(r"MISC2\Example2.txt").is_some());
+        assert!(archive.get_file("misc1/example2.txt").is_none());
+
+        let paths: Vec<_> = archive.files().map(|(path, _)| path.into_owned()).collect();
+        assert_eq!(paths.len(), 2);
+        assert!(paths.contains(&r"misc1\example1.txt".into()));
+        assert!(paths.contains(&r"misc2\example2.txt".into()));
+
+        let old = archive.insert_file("misc1/example3.txt", File::from_decompressed(b"foo"));
+        assert!(old.is_none());
+        assert_eq!(archive.len(), 2);
+        assert_eq!(
+            archive
+                .get_file(r"misc1\example3.txt")
+                .context("failed to get inserted file")?
+                .as_bytes(),
+            b"foo"
+        );
+
+        assert!(archive
+            .insert_file("root.txt", File::from_decompressed(b"bar"))
+            .is_none());
+        assert!(archive.get(&ArchiveKey::from(".")).is_some());
+
+        assert!(archive.remove_file("misc2/example2.txt").is_some());
+        assert!(archive.get(&ArchiveKey::from("misc2")).is_none());
+        assert!(archive.remove_file("misc2/example2.txt").is_none());
+        assert!(archive.remove_file("misc1/example1.txt").is_some());
+        assert!(archive.get(&ArchiveKey::from("misc1")).is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn merging() -> anyhow::Result<()> {
+        let make = |files: &[(&str, &'static [u8])], options: ArchiveOptions| {
+            let mut archive = Archive::new();
+            for &(path, data) in files {
+                archive.insert_file(path, File::from_decompressed(data));
+            }
+            (archive, options)
+        };
+        let sources = || {
+            [
+                make(
+                    &[("a/shared.txt", b"first"), ("a/first.txt", b"1")],
+                    ArchiveOptions::builder()
+                        .version(Version::SSE)
+                        .types(ArchiveTypes::MISC)
+                        .flags(ArchiveFlags::DIRECTORY_STRINGS | ArchiveFlags::FILE_STRINGS)
+                        .build(),
+                ),
+                make(
+                    &[("a/shared.txt", b"last"), ("b/last.txt", b"2")],
+                    ArchiveOptions::builder()
+                        .version(Version::SSE)
+                        .types(ArchiveTypes::TEXTURES)
+                        .flags(ArchiveFlags::COMPRESSED)
+                        .build(),
+                ),
+            ]
+        };
+
+        let (merged, options) = Archive::merge(sources(), MergePolicy::FirstWins)?;
+        assert_eq!(merged.len(), 2);
+        assert_eq!(
+            merged.get_file("a/shared.txt").unwrap().as_bytes(),
+            b"first"
+        );
+        assert!(merged.get_file("a/first.txt").is_some());
+        assert!(merged.get_file("b/last.txt").is_some());
+        assert_eq!(options.version(), Version::SSE);
+        assert_eq!(options.types(), ArchiveTypes::MISC | ArchiveTypes::TEXTURES);
+        assert_eq!(
+            options.flags(),
+            ArchiveFlags::DIRECTORY_STRINGS | ArchiveFlags::FILE_STRINGS
+        );
+
+        let (merged, _) = Archive::merge(sources(), MergePolicy::LastWins)?;
+        assert_eq!(merged.get_file("a/shared.txt").unwrap().as_bytes(), b"last");
+
+        match Archive::merge(sources(), MergePolicy::Error) {
+            Err(Error::MergeConflict(path)) => assert_eq!(path, "a\\shared.txt"),
+            _ => anyhow::bail!("merge should have failed"),
+        }
+
+        let mut conflicts = 0;
+        let (merged, _) = Archive::merge_with(sources(), |directory, file, existing, incoming| {
+            conflicts += 1;
+            assert_eq!(directory.name(), "a");
+            assert_eq!(file.name(), "shared.txt");
+            let mut bytes = existing.as_bytes().to_vec();
+            bytes.extend_from_slice(incoming.as_bytes());
+            Ok(File::from_decompressed(bytes.into_boxed_slice()))
+        })?;
+        assert_eq!(conflicts, 1);
+        assert_eq!(
+            merged.get_file("a/shared.txt").unwrap().as_bytes(),
+            b"firstlast"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn compress_files_with_policy() -> anyhow::Result<()> {
+        let options = ArchiveOptions::builder()
+            .version(Version::SSE)
+            .flags(ArchiveFlags::DIRECTORY_STRINGS | ArchiveFlags::FILE_STRINGS)
+            .build();
+        let payload = [b'x'; 256];
+        let mut archive = Archive::new();
+        archive.insert_file("meshes/bucket.nif", File::from_decompressed(&payload[..]));
+        archive.insert_file("sound/bucket.XWM", File::from_decompressed(&payload[..]));
+        archive.insert_file("meshes/tiny.nif", File::from_decompressed(b"1"));
+
+        let policy = CompressionPolicy::builder()
+            .exclude_extension("xwm")
+            .build();
+        archive.compress_files(&FileCompressionOptions::from(&options), &policy)?;
+        assert!(archive
+            .get_file("meshes/bucket.nif")
+            .unwrap()
+            .is_compressed());
+        assert!(!archive
+            .get_file("sound/bucket.XWM")
+            .unwrap()
+            .is_compressed());
+        assert!(!archive.get_file("meshes/tiny.nif").unwrap().is_compressed());
+
+        // the mixed compression states survive a round trip, regardless of the archive flag
+        for flags in [options.flags(), options.flags() | ArchiveFlags::COMPRESSED] {
+            let options = ArchiveOptions::builder()
+                .version(options.version())
+                .flags(flags)
+                .build();
+            let mut stream = Vec::new();
+            archive.write(&mut stream, &options)?;
+            let (copy, _) = Archive::read(Borrowed(&stream))?;
+            for (path, file) in archive.files() {
+                let other = copy.get_file(path.as_ref()).context("file was missing")?;
+                assert_eq!(other.is_compressed(), file.is_compressed());
+                assert_eq!(other.as_bytes(), file.as_bytes());
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn infer_types() -> anyhow::Result<()> {
+        let mut archive = Archive::new();
+        for path in [
+            "meshes/clutter/bucket.nif",
+            "Textures/clutter/bucket.dds",
+            "sound/voice/skyrim.esm/femaleeventoned/hello.fuz",
+            "sound/fx/bucket.wav",
+            "loose.xwm",
+            "scripts/bucket.pex",
+        ] {
+            archive.insert_file(path, File::from_decompressed(b"1"));
+        }
+        let expected = ArchiveTypes::MESHES
+            | ArchiveTypes::TEXTURES
+            | ArchiveTypes::VOICES
+            | ArchiveTypes::SOUNDS
+            | ArchiveTypes::MISC;
+        assert_eq!(archive.infer_types(), expected);
+
+        // extensions take precedence over directories
+        let mut misplaced = Archive::new();
+        misplaced.insert_file("textures/foo.nif", File::from_decompressed(b"1"));
+        misplaced.insert_file("meshes/foo.txt", File::from_decompressed(b"1"));
+        assert_eq!(misplaced.infer_types(), ArchiveTypes::MESHES);
+
+        let options = ArchiveOptions::builder()
+            .version(Version::SSE)
+            .flags(ArchiveFlags::DIRECTORY_STRINGS | ArchiveFlags::FILE_STRINGS)
+            .types(ArchiveTypes::FONTS)
+            .infer_types(true)
+            .build();
+        let mut stream = Vec::new();
+        archive.write(&mut stream, &options)?;
+        let (_, copy) = Archive::read(Borrowed(&stream))?;
+        assert_eq!(copy.types(), expected);
+        assert!(!copy.infer_types());
+
+        Ok(())
+    }
+
+    #[test]
+    fn merging_decompresses_mismatched_versions() -> anyhow::Result<()> {
+        let fo3 = ArchiveOptions::builder().version(Version::FO3).build();
+        let sse = ArchiveOptions::builder().version(Version::SSE).build();
+        let mut first = Archive::new();
+        first.insert_file("a/first.txt", File::from_decompressed(b"1"));
+        let mut last = Archive::new();
+        let file = File::from_decompressed(&[b'x'; 64][..]);
+        last.insert_file(
+            "a/last.txt",
+            file.compress(&FileCompressionOptions::from(&sse))?,
+        );
+
+        let (merged, options) = Archive::merge([(first, fo3), (last, sse)], MergePolicy::Error)?;
+        assert_eq!(options.version(), Version::FO3);
+        let file = merged.get_file("a/last.txt").unwrap();
+        assert!(!file.is_compressed());
+        assert_eq!(file.as_bytes(), [b'x'; 64]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn merging_keeps_first_archive_flags() -> anyhow::Result<()> {
+        let root = Path::new("data/tes4_xbox_read_test");
+        let (normal, normal_options) = Archive::read(root.join("normal.bsa").as_path())?;
+        let (xbox, xbox_options) = Archive::read(root.join("xbox.bsa").as_path())?;
+        assert!(!normal_options.flags().xbox_archive());
+        assert!(xbox_options.flags().xbox_archive());
+
+        let expected: Vec<_> = normal
+            .iter()
+            .flat_map(|(directory_key, directory)| {
+                directory.iter().map(|(file_key, file)| {
+                    (
+                        directory_key.name().to_vec(),
+                        file_key.name().to_vec(),
+                        file.as_bytes().to_vec(),
+                    )
+                })
+            })
+            .collect();
+        let (merged, options) = Archive::merge(
+            [(normal, normal_options), (xbox, xbox_options)],
+            MergePolicy::FirstWins,
+        )?;
+        assert_eq!(options.flags(), normal_options.flags());
+
+        let mut stream = Vec::new();
+        merged.write(&mut stream, &options)?;
+        let (copy, copy_options) = Archive::read(Borrowed(&stream))?;
+        assert_eq!(copy_options.flags(), normal_options.flags());
+        assert_eq!(copy.len(), merged.len());
+        for (directory, file, data) in expected {
+            let file = copy
+                .get(&ArchiveKey::from(directory))
+                .and_then(|x| x.get(&DirectoryKey::from(file)))
+                .context("merged file is missing")?;
+            assert_eq!(file.as_bytes(), data);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_with_layout() -> anyhow::Result<()> {
+        let paths = [
+            "data/common_guess_test/tes4.bsa",
+            "data/tes4_compression_test/test_104.bsa",
+            "data/tes4_compression_test/test_105.bsa",
+            "data/tes4_data_sharing_name_test/share.bsa",
+            "data/tes4_xbox_read_test/normal.bsa",
+            "data/tes4_xbox_read_test/xbox.bsa",
+            "data/tes4_xbox_write_test/in.bsa",
+            "data/tes4_xmem_test/xmem.bsa",
+        ];
+        for path in paths {
+            let original = fs::read(path)?;
+            let (archive, options, layout) = Archive::read_with_layout(Borrowed(&original))
+                .with_context(|| format!("failed to read archive: {path}"))?;
+            let mut copy = Vec::new();
+            archive.write_with_layout(&mut copy, &options, &layout)?;
+            assert!(copy == original, "archive was not reproduced: {path}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_with_modified_layout() -> anyhow::Result<()> {
+        let path = Path::new("data/tes4_compression_test/test_105.bsa");
+        let (mut archive, options, layout) = Archive::read_with_layout(path)?;
+        let compression_options = FileCompressionOptions::from(&options);
+        assert!(archive.remove_file("License.txt").is_some());
+        archive.insert_file(
+            "new/file.txt",
+            File::from_decompressed(b"Hello world!\n").compress(&compression_options)?,
+        );
+
+        let mut stream = Vec::new();
+        archive.write_with_layout(&mut stream, &options, &layout)?;
+        let (copy, _) = Archive::read(Borrowed(&stream))?;
+        assert!(copy.get_file("License.txt").is_none());
+        assert_eq!(
+            copy.get_file("new/file.txt")
+                .context("file was missing")?
+                .decompress(&compression_options)?
+                .as_bytes(),
+            b"Hello world!\n"
+        );
+        let original = archive
+            .get_file("Preview.png")
+            .context("file was missing")?;
+        let copied = copy.get_file("Preview.png").context("file was missing")?;
+        assert_eq!(copied.as_bytes(), original.as_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn query() -> anyhow::Result<()> {
+        let options = FileCompressionOptions::default();
+        let mut archive = Archive::new();
+        for (path, compress) in [
+            ("meshes\\clutter\\bucket.nif", false),
+            ("textures\\clutter\\bucket.dds", true),
+            ("textures\\clutter\\bucket_n.dds", false),
+            ("textures\\sky_n.dds", true),
+        ] {
+            let file = File::from_decompressed(&b"payload payload payload"[..]);
+            let file = if compress {
+                file.compress(&options)?
+            } else {
+                file.into_owned()
+            };
+            archive.insert_file(path, file);
+        }
+
+        let glob = Glob::new("Textures/**/*_N.DDS");
+        let mut found: Vec<_> = archive
+            .find(|x| x.matches(&glob))
+            .map(|(directory, file)| format!("{}\\{}", directory.name(), file.name()))
+            .collect();
+        found.sort();
+        assert_eq!(
+            found,
+            ["textures\\clutter\\bucket_n.dds", "textures\\sky_n.dds"]
+        );
+
+        let selected = archive.select(QueryEntry::is_compressed);
+        assert_eq!(selected.len(), 2);
+        assert!(selected.get_file("textures/clutter/bucket.dds").is_some());
+        assert!(selected.get_file("textures/sky_n.dds").is_some());
+        assert!(selected
+            .get(&ArchiveKey::from(b"meshes\\clutter"))
+            .is_none());
+
+        let selected = archive.select(|x| x.path().starts_with(b"meshes\\"));
+        assert_eq!(selected.len(), 1);
+        assert!(selected.get_file("meshes\\clutter\\bucket.nif").is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn rename_and_move() -> anyhow::Result<()> {
+        let options = ArchiveOptions::default();
+        let mut archive = Archive::new();
+        archive.insert_file(
+            "meshes\\bucket.nif",
+            File::from_decompressed(&b"bucket"[..]),
+        );
+        archive.insert_file("meshes\\broom.nif", File::from_decompressed(&b"broom!"[..]));
+        archive.insert_file("textures\\sky.dds", File::from_decompressed(&b"sky"[..]));
+        let mut bytes = Vec::new();
+        archive.write(&mut bytes, &options)?;
+        let range = bytes.as_ptr_range();
+
+        let (mut archive, _) = Archive::read(Borrowed(&bytes))?;
+        archive.rename_file("meshes\\bucket.nif", "meshes/clutter/pail.nif")?;
+        archive.move_file("textures/sky.dds", "textures\\sky")?;
+        assert_eq!(archive.len(), 3);
+        assert!(archive.get(&ArchiveKey::from(b"textures")).is_none());
+        assert_eq!(
+            archive
+                .get(&ArchiveKey::from(b"meshes"))
+                .context("directory was missing")?
+                .len(),
+            1
+        );
+
+        let file = archive
+            .get_file("meshes\\clutter\\pail.nif")
+            .context("renamed file was missing")?;
+        assert_eq!(file.as_bytes(), b"bucket");
+        assert!(range.contains(&file.as_bytes().as_ptr()));
+        let file = archive
+            .get_file("textures\\sky\\sky.dds")
+            .context("moved file was missing")?;
+        assert_eq!(file.as_bytes(), b"sky");
+        assert!(range.contains(&file.as_bytes().as_ptr()));
+
+        archive.move_file("meshes\\broom.nif", "meshes\\clutter")?;
+        assert!(archive.get(&ArchiveKey::from(b"meshes")).is_none());
+        match archive.rename_file("meshes\\clutter\\pail.nif", "meshes\\clutter\\broom.nif") {
+            Err(Error::FileExists(path)) => assert_eq!(path, "meshes\\clutter\\broom.nif"),
+            other => anyhow::bail!("rename should have collided: {other:?}"),
+        }
+        match archive.rename_file("meshes\\missing.nif", "meshes\\other.nif") {
+            Err(Error::FileNotFound(path)) => assert_eq!(path, "meshes\\missing.nif"),
+            other => anyhow::bail!("rename should have failed: {other:?}"),
+        }
+        assert!(archive.get_file("meshes\\clutter\\pail.nif").is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn written_len() -> anyhow::Result<()> {
+        for path in [
+            "data/tes4_compression_test/test_104.bsa",
+            "data/tes4_compression_test/test_105.bsa",
+            "data/tes4_xbox_read_test/normal.bsa",
+            "data/tes4_xbox_read_test/xbox.bsa",
+        ] {
+            let (archive, options) = Archive::read(Path::new(path))?;
+            let len = archive.written_len(&options)?;
+            let mut bytes = Vec::new();
+            archive.write(&mut bytes, &options)?;
+            assert_eq!(len.total(), bytes.len(), "{path}");
+            assert_eq!(len.header, 0x24);
+        }
+
+        let options = ArchiveOptions::builder()
+            .flags(ArchiveFlags::DIRECTORY_STRINGS | ArchiveFlags::FILE_STRINGS)
+            .build();
+        let mut archive = Archive::new();
+        archive.insert_file("a\\b.txt", File::from_decompressed(&b"hello"[..]));
+        let len = archive.written_len(&options)?;
+        // "a" as a bzstring, and "b.txt" as a zstring
+        assert_eq!(len.names, 3 + 6);
+        assert_eq!(len.data, 5);
+
+        let name = "x".repeat(0x100);
+        archive.insert_file(&format!("{name}\\b.txt"), File::new());
+        assert!(archive.written_len(&options).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn written_len_with_layout() -> anyhow::Result<()> {
+        let (archive, options) =
+            Archive::read(Path::new("data/tes4_compression_test/test_105.bsa"))?;
+
+        // align the data of every file, as if the archive had been packed for a console
+        let (header, directories) = archive.plan_write(options, &ArchiveLayout::default())?;
+        let start = header.compute_offsets().file_data;
+        let mut items = Vec::new();
+        let mut cursor = start;
+        for (id, len) in Archive::sections(&directories) {
+            cursor = cursor.next_multiple_of(0x100);
+            items.push((id, cursor, len));
+            cursor += len;
+        }
+        let aligned = vec![0; cursor.next_multiple_of(0x100)];
+        let layout = ArchiveLayout {
+            data: Region::capture(&aligned, start, items),
+            ..ArchiveLayout::default()
+        };
+
+        let len = archive.written_len_with_layout(&options, &layout)?;
+        let mut bytes = Vec::new();
+        archive.write_with_layout(&mut bytes, &options, &layout)?;
+        assert_eq!(len.total(), bytes.len());
+        assert!(len.total() > archive.written_len(&options)?.total());
+
+        let (copy, options, layout) = Archive::read_with_layout(Borrowed(&bytes))?;
+        assert!(layout.data.is_padded());
+        assert_eq!(copy.written_len_with_layout(&options, &layout)?, len);
+        Ok(())
+    }
+
+    #[test]
+    fn progress() -> anyhow::Result<()> {
+        let options = ArchiveOptions::builder()
+            .version(Version::SSE)
+            .flags(ArchiveFlags::DIRECTORY_STRINGS | ArchiveFlags::FILE_STRINGS)
+            .build();
+        let payload = [b'x'; 256];
+        let mut archive = Archive::new();
+        archive.insert_file("meshes/bucket.nif", File::from_decompressed(&payload[..]));
+        archive.insert_file("meshes/tiny.nif", File::from_decompressed(b"1"));
+        archive.insert_file("sound/bucket.wav", File::from_decompressed(&payload[..]));
+
+        let mut events = Vec::new();
+        let mut progress = Progress::builder()
+            .observer(|event: &ProgressEvent| {
+                events.push((event.name().to_owned(), event.bytes(), event.total_bytes()));
+            })
+            .build();
+        archive.compress_files_with_progress(
+            &FileCompressionOptions::from(&options),
+            &CompressionPolicy::default(),
+            &mut progress,
+        )?;
+        drop(progress);
+        events.sort();
+        assert_eq!(
+            events,
+            [
+                ("meshes\\bucket.nif".into(), 256, 513),
+                ("meshes\\tiny.nif".into(), 1, 513),
+                ("sound\\bucket.wav".into(), 256, 513),
+            ]
+        );
+
+        let mut events = Vec::new();
+        let mut progress = Progress::builder()
+            .observer(|event: &ProgressEvent| {
+                events.push((event.name().to_owned(), event.bytes(), event.total_bytes()));
+            })
+            .build();
+        let mut bytes = Vec::new();
+        archive.write_with_progress(&mut bytes, &options, &mut progress)?;
+        drop(progress);
+        let mut original = Vec::new();
+        archive.write(&mut original, &options)?;
+        assert_eq!(bytes, original);
+        assert_eq!(events.len(), 3);
+        let total: usize = events.iter().map(|x| x.1).sum();
+        assert!(events.iter().all(|x| x.2 == total));
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let mut progress = Progress::builder().cancellation(token).build();
+        let result = archive.write_with_progress(&mut Vec::new(), &options, &mut progress);
+        assert!(matches!(result, Err(Error::Cancelled)));
+        Ok(())
+    }
 }
@@ -0,0 +1,337 @@
+use crate::{
+    containers::Bytes,
+    io,
+    layout::Storage,
+    tes4::{
+        Archive, ArchiveKey, ArchiveOptions, Directory, DirectoryHash, DirectoryKey, File,
+        FileHash, Result,
+    },
+};
+use tokio::io::{AsyncRead, AsyncSeek};
+
+/// Reads a TES4 archive asynchronously, fetching the data of each file only when it is requested.
+///
+/// Only the index of the archive is read up front. The data of a file is read using a range read whenever it is fetched, rather than by memory-mapping the whole archive, which makes this suitable for streams which are slow, or which live on another machine. Files are returned as they are stored, so compressed files must still be decompressed, i.e. using [`File::decompress_async`].
+///
+/// Archives which lack directory or file strings, but which embed the name of each file before its data, only reveal those names once the data has been fetched. Until then, the affected keys have empty names. Fetching a file fills in its name, and the name of its directory, and [`into_archive`](Self::into_archive) fetches every file, and so recovers every name.
+///
+/// ```rust
+/// use ba2::tes4::{AsyncArchive, FileCompressionOptions};
+///
+/// async fn example() -> Option<()> {
+///     let stream = tokio::fs::File::open("path/to/skyrim/Data/Skyrim - Misc.bsa")
+///         .await
+///         .ok()?;
+///     let mut archive = AsyncArchive::new(stream).await.ok()?;
+///     let options = FileCompressionOptions::from(archive.options());
+///     let file = archive
+///         .get_file(r"strings\skyrim_english.strings")
+///         .await
+///         .ok()??;
+///     let file = if file.is_compressed() {
+///         file.decompress_async(&options).await.ok()?
+///     } else {
+///         file
+///     };
+///     tokio::fs::write("skyrim_english.strings", file.as_bytes())
+///         .await
+///         .ok()?;
+///     Some(())
+/// }
+/// ```
+pub struct AsyncArchive<In> {
+    stream: In,
+    /// The index of the archive, whose files are all empty, but which are still marked as compressed.
+    index: Archive<'static>,
+    options: ArchiveOptions,
+    storage: Storage<(DirectoryHash, FileHash)>,
+}
+
+impl<In> AsyncArchive<In>
+where
+    In: AsyncRead + AsyncSeek + Unpin,
+{
+    /// Reads the index of the archive within the given stream.
+    pub async fn new(mut stream: In) -> Result<Self> {
+        let (index, options, _, storage) =
+            io::read_deferred(&mut stream, Archive::read_storage).await?;
+        Ok(Self {
+            stream,
+            index,
+            options,
+            storage,
+        })
+    }
+
+    /// Fetches the data of a file using its full path, i.e. `dir\file.ext`.
+    pub async fn get_file<P>(&mut self, path: &P) -> Result<Option<File<'static>>>
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        self.fetch(Archive::hash_path(path.as_ref())).await
+    }
+
+    /// Fetches the data of every file, in the order they are stored.
+    pub async fn into_archive(mut self) -> Result<(Archive<'static>, ArchiveOptions)> {
+        let mut ids: Vec<_> = self
+            .index
+            .iter()
+            .flat_map(|(directory, files)| {
+                files.keys().map(move |file| (directory.hash, file.hash))
+            })
+            .collect();
+        ids.sort_by_key(|id| self.storage.get(id));
+
+        let mut files = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(file) = self.fetch(id).await? {
+                files.push((id, file));
+            }
+        }
+
+        // names may have been recovered while fetching, so the keys are taken from the index afterwards
+        let mut archive: Archive = self
+            .index
+            .keys()
+            .map(|directory| (directory.clone(), Directory::new()))
+            .collect();
+        for ((directory, key), file) in files {
+            let key = self
+                .index
+                .get(&directory)
+                .and_then(|x| x.get_key_value(&key))
+                .map(|(key, _)| key.clone());
+            if let (Some(key), Some(files)) = (key, archive.get_mut(&directory)) {
+                files.insert(key, file);
+            }
+        }
+
+        Ok((archive, self.options))
+    }
+
+    #[must_use]
+    pub fn into_inner(self) -> In {
+        self.stream
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Iterates over the keys of every file, alongside the key of the directory which holds it.
+    pub fn keys(&self) -> impl Iterator<Item = (&ArchiveKey<'static>, &DirectoryKey<'static>)> {
+        self.index
+            .iter()
+            .flat_map(|(directory, files)| files.keys().map(move |file| (directory, file)))
+    }
+
+    #[must_use]
+    pub fn options(&self) -> &ArchiveOptions {
+        &self.options
+    }
+
+    async fn fetch(&mut self, id: (DirectoryHash, FileHash)) -> Result<Option<File<'static>>> {
+        let Some(compressed) = self
+            .index
+            .get(&id.0)
+            .and_then(|directory| directory.get(&id.1))
+            .map(File::is_compressed)
+        else {
+            return Ok(None);
+        };
+        let Some((offset, len)) = self.storage.get(&id) else {
+            return Ok(None);
+        };
+
+        let bytes = io::read_range(&mut self.stream, offset, len).await?;
+        let (file, embedded_name) = Archive::read_deferred_file(&bytes, self.options, compressed)?;
+        if let Some((directory_name, file_name)) = embedded_name {
+            self.recover_names(id, directory_name, file_name);
+        }
+        Ok(Some(file))
+    }
+
+    /// Fills in whichever of the names of a file, and of its directory, are missing from the index, using the name which was embedded before its data.
+    fn recover_names(
+        &mut self,
+        id: (DirectoryHash, FileHash),
+        directory_name: Option<Bytes<'static>>,
+        file_name: Bytes<'static>,
+    ) {
+        let Some((mut directory_key, mut directory)) = self.index.remove_entry(&id.0) else {
+            return;
+        };
+        if let Some(name) = directory_name {
+            if directory_key.name.is_empty() {
+                directory_key.name = name;
+            }
+        }
+        if let Some((mut key, file)) = directory.remove_entry(&id.1) {
+            if key.name.is_empty() {
+                key.name = file_name;
+            }
+            directory.insert(key, file);
+        }
+        self.index.insert(directory_key, directory);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        prelude::*,
+        tes4::{
+            Archive, ArchiveFlags, ArchiveKey, ArchiveOptions, AsyncArchive, Directory,
+            DirectoryKey, File, FileCompressionOptions, Version,
+        },
+    };
+    use anyhow::Context as _;
+    use std::{io::Cursor, path::Path};
+
+    async fn compare(
+        expected: &Archive<'_>,
+        mut archive: AsyncArchive<impl tokio::io::AsyncRead + tokio::io::AsyncSeek + Unpin>,
+    ) -> anyhow::Result<()> {
+        let options = FileCompressionOptions::from(archive.options());
+        for (directory, files) in expected {
+            for (key, file) in files {
+                let path = format!("{}\\{}", directory.name(), key.name());
+                let fetched = archive.get_file(&path).await?.context("file was missing")?;
+                assert_eq!(fetched.is_compressed(), file.is_compressed());
+                assert_eq!(fetched.decompressed_len(), file.decompressed_len());
+                assert_eq!(fetched.as_bytes(), file.as_bytes());
+                if fetched.is_compressed() {
+                    let decompressed = fetched.decompress_async(&options).await?;
+                    assert_eq!(
+                        decompressed.as_bytes(),
+                        file.decompress(&options)?.as_bytes()
+                    );
+                }
+            }
+        }
+
+        let (archive, options) = archive.into_archive().await?;
+        assert!(archive.diff(&options, expected, &options)?.is_empty());
+        for (directory, files) in expected {
+            let (key, other) = archive
+                .get_key_value(directory)
+                .context("directory was missing")?;
+            assert_eq!(key.name(), directory.name());
+            assert_eq!(other.len(), files.len());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_lazily() -> anyhow::Result<()> {
+        for path in [
+            "data/tes4_compression_test/test_104.bsa",
+            "data/tes4_compression_test/test_105.bsa",
+            "data/tes4_xbox_read_test/normal.bsa",
+            "data/tes4_xbox_read_test/xbox.bsa",
+        ] {
+            let path = Path::new(path);
+            let (expected, options) = Archive::read(path)?;
+            let stream = tokio::fs::File::open(path).await?;
+            let archive = AsyncArchive::new(stream).await?;
+            assert_eq!(archive.options().flags(), options.flags());
+            assert_eq!(
+                archive.keys().count(),
+                expected.values().map(Directory::len).sum::<usize>()
+            );
+            compare(&expected, archive).await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_large_archives() -> anyhow::Result<()> {
+        let options = ArchiveOptions::builder()
+            .flags(
+                ArchiveFlags::DIRECTORY_STRINGS
+                    | ArchiveFlags::FILE_STRINGS
+                    | ArchiveFlags::COMPRESSED
+                    | ArchiveFlags::EMBEDDED_FILE_NAMES,
+            )
+            .version(Version::SSE)
+            .build();
+        let compression_options = FileCompressionOptions::from(&options);
+        let payload: Vec<u8> = (0..0x8000u32)
+            .flat_map(|i| (i * i % 997).to_le_bytes())
+            .collect();
+
+        let mut expected = Archive::new();
+        for i in 0..8 {
+            let file = File::from_decompressed(&payload[i * 0x1000..]);
+            let file = if i % 2 == 0 {
+                file.compress(&compression_options)?
+            } else {
+                file.into_owned()
+            };
+            expected.insert_file(&format!("large\\file{i}.bin"), file);
+        }
+
+        let mut bytes = Vec::new();
+        expected.write_async(&mut bytes, &options).await?;
+        assert!(bytes.len() > 0x40000);
+        let mut expected_bytes = Vec::new();
+        expected.write(&mut expected_bytes, &options)?;
+        assert_eq!(bytes, expected_bytes);
+
+        let archive = AsyncArchive::new(Cursor::new(bytes)).await?;
+        let directory = ArchiveKey::from(b"large");
+        let key = DirectoryKey::from(b"file3.bin");
+        assert!(archive.keys().any(|(x, y)| x == &directory && y == &key));
+        compare(&expected, archive).await
+    }
+
+    #[tokio::test]
+    async fn recover_embedded_names() -> anyhow::Result<()> {
+        let path = Path::new("data/tes4_embedded_names_test/in.bsa");
+        let (expected, options) = Archive::read(path)?;
+        assert!(options.flags().embedded_file_names());
+        assert!(!options.flags().directory_strings() && !options.flags().file_strings());
+        let names = |archive: &Archive| -> Vec<String> {
+            archive
+                .iter()
+                .flat_map(|(directory, files)| {
+                    files
+                        .keys()
+                        .map(move |file| format!("{}\\{}", directory.name(), file.name()))
+                })
+                .collect()
+        };
+        assert!(names(&expected).contains(&"sound\\fx\\drip.wav".to_owned()));
+
+        let stream = tokio::fs::File::open(path).await?;
+        let mut archive = AsyncArchive::new(stream).await?;
+        let find = |archive: &AsyncArchive<_>, directory: &ArchiveKey, file: &DirectoryKey| {
+            archive
+                .keys()
+                .find(|(x, y)| x.hash() == directory.hash() && y.hash() == file.hash())
+                .map(|(_, y)| y.name().to_owned())
+        };
+        let mut deferred = 0;
+        for (directory, files) in &expected {
+            for key in files.keys() {
+                if find(&archive, directory, key)
+                    .context("key was missing")?
+                    .is_empty()
+                {
+                    deferred += 1;
+                    let path = format!("{}\\{}", directory.name(), key.name());
+                    archive.get_file(&path).await?.context("file was missing")?;
+                    assert_eq!(find(&archive, directory, key), Some(key.name().to_owned()));
+                }
+            }
+        }
+        assert!(deferred > 0, "no file was deferred");
+
+        let stream = tokio::fs::File::open(path).await?;
+        let archive = AsyncArchive::new(stream).await?;
+        let (archive, _) = archive.into_archive().await?;
+        assert_eq!(names(&archive), names(&expected));
+        Ok(())
+    }
+}
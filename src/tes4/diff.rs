@@ -0,0 +1,372 @@
+use crate::tes4::{
+    Archive, ArchiveFlags, ArchiveKey, ArchiveOptions, ArchiveTypes, DirectoryHash, DirectoryKey,
+    File, FileCompressionOptions, FileHash, Result, Version,
+};
+use bstr::{BString, ByteSlice as _};
+use core::fmt::{self, Debug, Display, Formatter};
+use std::borrow::Cow;
+
+/// A file reported by a [`Diff`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Entry {
+    pub directory: DirectoryHash,
+    pub file: FileHash,
+    /// The full path of the file, which may be empty or incomplete.
+    pub path: BString,
+}
+
+impl Entry {
+    pub(crate) fn new(directory: &ArchiveKey<'_>, file: &DirectoryKey<'_>) -> Self {
+        Self {
+            directory: *directory.hash(),
+            file: *file.hash(),
+            path: join(directory, file),
+        }
+    }
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(
+                f,
+                "{:016x}\\{:016x}",
+                self.directory.numeric(),
+                self.file.numeric()
+            )
+        } else {
+            write!(f, "{}", self.path)
+        }
+    }
+}
+
+/// A value which differs between archives.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Change<T> {
+    pub old: T,
+    pub new: T,
+}
+
+impl<T> Change<T>
+where
+    T: PartialEq,
+{
+    fn new(old: T, new: T) -> Option<Self> {
+        (old != new).then_some(Self { old, new })
+    }
+}
+
+/// A file whose compression state differs between archives.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CompressionChange {
+    pub entry: Entry,
+    /// Whether or not the file is compressed, in each archive.
+    pub compressed: Change<bool>,
+}
+
+/// A file whose path differs between archives, while its hashes remain the same.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rename {
+    pub directory: DirectoryHash,
+    pub file: FileHash,
+    pub old: BString,
+    pub new: BString,
+}
+
+/// The differences between two TES4 archives.
+///
+/// File contents are compared after decompression, so recompressing a file does not count as a change to its contents. Instead, such changes are reported by [`compression`](Self::compression).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diff {
+    /// Files which exist only in the new archive.
+    pub added: Vec<Entry>,
+    /// Files which exist only in the old archive.
+    pub removed: Vec<Entry>,
+    /// Files which exist in both archives, but whose contents differ.
+    pub changed: Vec<Entry>,
+    /// Files which exist in both archives, but whose paths differ.
+    pub renamed: Vec<Rename>,
+    /// Files which exist in both archives, but whose compression state differs.
+    pub compression: Vec<CompressionChange>,
+    pub version: Option<Change<Version>>,
+    pub flags: Option<Change<ArchiveFlags>>,
+    pub types: Option<Change<ArchiveTypes>>,
+}
+
+impl Diff {
+    /// Returns `true` if both archives were found to be identical.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.renamed.is_empty()
+            && self.compression.is_empty()
+            && self.version.is_none()
+            && self.flags.is_none()
+            && self.types.is_none()
+    }
+
+    pub(crate) fn new(
+        old: (&Archive, &ArchiveOptions),
+        new: (&Archive, &ArchiveOptions),
+    ) -> Result<Self> {
+        let (old, old_options) = old;
+        let (new, new_options) = new;
+        let old_compression: FileCompressionOptions = old_options.into();
+        let new_compression: FileCompressionOptions = new_options.into();
+
+        let mut result = Self {
+            version: Change::new(old_options.version(), new_options.version()),
+            flags: Change::new(old_options.flags(), new_options.flags()),
+            types: Change::new(old_options.types(), new_options.types()),
+            ..Default::default()
+        };
+
+        for (new_directory_key, new_directory) in new {
+            let old_directory = old.get_key_value(new_directory_key.hash());
+            for (new_file_key, new_file) in new_directory {
+                let entry = Entry::new(new_directory_key, new_file_key);
+                let Some((old_directory_key, old_file_key, old_file)) =
+                    old_directory.and_then(|(directory_key, directory)| {
+                        directory
+                            .get_key_value(new_file_key.hash())
+                            .map(|(file_key, file)| (directory_key, file_key, file))
+                    })
+                else {
+                    result.added.push(entry);
+                    continue;
+                };
+
+                let old_path = join(old_directory_key, old_file_key);
+                if is_complete(old_directory_key, old_file_key)
+                    && is_complete(new_directory_key, new_file_key)
+                    && old_path != entry.path
+                {
+                    result.renamed.push(Rename {
+                        directory: entry.directory,
+                        file: entry.file,
+                        old: old_path,
+                        new: entry.path.clone(),
+                    });
+                }
+
+                if let Some(compressed) =
+                    Change::new(old_file.is_compressed(), new_file.is_compressed())
+                {
+                    result.compression.push(CompressionChange {
+                        entry: entry.clone(),
+                        compressed,
+                    });
+                }
+
+                let same = if old_file.is_compressed() == new_file.is_compressed()
+                    && old_file.as_bytes() == new_file.as_bytes()
+                {
+                    true
+                } else {
+                    decompressed(old_file, &old_compression)?
+                        == decompressed(new_file, &new_compression)?
+                };
+                if !same {
+                    result.changed.push(entry);
+                }
+            }
+        }
+
+        for (old_directory_key, old_directory) in old {
+            let new_directory = new.get(old_directory_key.hash());
+            for old_file_key in old_directory.keys() {
+                if new_directory
+                    .and_then(|x| x.get(old_file_key.hash()))
+                    .is_none()
+                {
+                    result
+                        .removed
+                        .push(Entry::new(old_directory_key, old_file_key));
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+impl Display for Diff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fn option<T: Debug>(
+            f: &mut Formatter<'_>,
+            name: &str,
+            change: Option<&Change<T>>,
+        ) -> fmt::Result {
+            match change {
+                Some(change) => writeln!(f, "{name}: {:?} -> {:?}", change.old, change.new),
+                None => Ok(()),
+            }
+        }
+
+        option(f, "version", self.version.as_ref())?;
+        option(f, "flags", self.flags.as_ref())?;
+        option(f, "types", self.types.as_ref())?;
+        for entry in &self.added {
+            writeln!(f, "+ {entry}")?;
+        }
+        for entry in &self.removed {
+            writeln!(f, "- {entry}")?;
+        }
+        for entry in &self.changed {
+            writeln!(f, "~ {entry}")?;
+        }
+        for rename in &self.renamed {
+            writeln!(f, "> {} -> {}", rename.old, rename.new)?;
+        }
+        for change in &self.compression {
+            let state = |x| if x { "compressed" } else { "decompressed" };
+            writeln!(
+                f,
+                "* {}: {} -> {}",
+                change.entry,
+                state(change.compressed.old),
+                state(change.compressed.new)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn decompressed<'file>(
+    file: &'file File<'_>,
+    options: &FileCompressionOptions,
+) -> Result<Cow<'file, [u8]>> {
+    if file.is_compressed() {
+        let mut bytes = Vec::new();
+        file.decompress_into(&mut bytes, options)?;
+        Ok(Cow::Owned(bytes))
+    } else {
+        Ok(Cow::Borrowed(file.as_bytes()))
+    }
+}
+
+fn is_complete(directory: &ArchiveKey<'_>, file: &DirectoryKey<'_>) -> bool {
+    !directory.name().is_empty() && !file.name().is_empty()
+}
+
+fn join(directory: &ArchiveKey<'_>, file: &DirectoryKey<'_>) -> BString {
+    let directory = match directory.name().as_bytes() {
+        b"" | b"." | b"/" | b"\\" => b"".as_bstr(),
+        x => x.as_bstr(),
+    };
+    let mut path = BString::from(directory.as_bytes());
+    if !path.is_empty() && !file.name().is_empty() {
+        path.push(b'\\');
+    }
+    path.extend_from_slice(file.name());
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        containers::Bytes,
+        prelude::*,
+        tes4::{
+            self, Archive, ArchiveFlags, ArchiveOptions, DirectoryKey, File, FileCompressionOptions,
+        },
+    };
+
+    fn make(files: &[(&str, &[u8])]) -> Archive<'static> {
+        let mut archive = Archive::new();
+        for &(path, data) in files {
+            archive.insert_file(
+                path,
+                File::from_decompressed(data.to_vec().into_boxed_slice()),
+            );
+        }
+        archive
+    }
+
+    #[test]
+    fn detects_all_changes() -> anyhow::Result<()> {
+        let old = make(&[
+            ("a\\same.txt", b"same"),
+            ("a\\changed.txt", b"old"),
+            ("b\\removed.txt", b"gone"),
+            ("c\\renamed.txt", b"name"),
+        ]);
+        let mut new = make(&[
+            ("a\\same.txt", b"same"),
+            ("a\\changed.txt", b"new"),
+            ("b\\added.txt", b"here"),
+            ("c\\renamed.txt", b"name"),
+        ]);
+
+        // names are normalized when keys are constructed, so emulate a name read from disk
+        let directory = new.get_mut(&tes4::hash_directory(b"c".into()).0).unwrap();
+        let file = directory
+            .remove(&tes4::hash_file(b"renamed.txt".into()).0)
+            .unwrap();
+        let mut key = DirectoryKey::from(b"renamed.txt");
+        key.name = Bytes::from_owned(b"Renamed.txt".to_vec().into());
+        directory.insert(key, file);
+
+        let old_options = ArchiveOptions::builder()
+            .flags(ArchiveFlags::DIRECTORY_STRINGS | ArchiveFlags::FILE_STRINGS)
+            .build();
+        let new_options = ArchiveOptions::builder()
+            .flags(old_options.flags() | ArchiveFlags::EMBEDDED_FILE_NAMES)
+            .build();
+
+        let diff = old.diff(&old_options, &new, &new_options)?;
+        assert!(!diff.is_empty());
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].path, "b\\added.txt");
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].path, "b\\removed.txt");
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].path, "a\\changed.txt");
+        assert_eq!(diff.renamed.len(), 1);
+        assert_eq!(diff.renamed[0].old, "c\\renamed.txt");
+        assert_eq!(diff.renamed[0].new, "c\\Renamed.txt");
+        assert!(diff.compression.is_empty());
+        assert!(diff.version.is_none());
+        assert!(diff.types.is_none());
+        let flags = diff.flags.unwrap();
+        assert_eq!(flags.old, old_options.flags());
+        assert_eq!(flags.new, new_options.flags());
+
+        let text = diff.to_string();
+        assert!(text.contains("+ b\\added.txt\n"));
+        assert!(text.contains("- b\\removed.txt\n"));
+        assert!(text.contains("~ a\\changed.txt\n"));
+        assert!(text.contains("> c\\renamed.txt -> c\\Renamed.txt\n"));
+
+        assert!(old.diff(&old_options, &old, &old_options)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn recompression_is_not_a_content_change() -> anyhow::Result<()> {
+        let old = make(&[("a\\file.txt", &[b'x'; 256])]);
+        let options = ArchiveOptions::default();
+        let compression: FileCompressionOptions = (&options).into();
+        let mut new = old.clone();
+        let file = new.get_file_mut("a\\file.txt").unwrap();
+        *file = file.compress(&compression)?;
+
+        let diff = old.diff(&options, &new, &options)?;
+        assert!(diff.changed.is_empty());
+        assert_eq!(diff.compression.len(), 1);
+        assert!(!diff.compression[0].compressed.old);
+        assert!(diff.compression[0].compressed.new);
+        assert!(diff
+            .to_string()
+            .contains("* a\\file.txt: decompressed -> compressed\n"));
+        Ok(())
+    }
+}
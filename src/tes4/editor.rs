@@ -0,0 +1,183 @@
+use crate::{
+    layout::Storage,
+    tes4::{Archive, ArchiveLayout, ArchiveOptions, DirectoryHash, File, FileHash, Result},
+};
+use std::fs;
+
+/// Edits a TES4 archive file in place, without rewriting the whole file.
+///
+/// Changes are made to the archive in memory, and are written out by [`commit`](Self::commit), which appends the data of every new or replaced file to the end of the file, and then rewrites only the header, the directory and file entries, and the file names. File data which the index would grow over is relocated to the end of the file before the index is rewritten, so nothing is lost if it grows.
+///
+/// The space held by files which were replaced or removed is not reclaimed until the archive is [`compact`](Self::compact)ed, which can be done whenever [`dead_space`](Self::dead_space) grows too large.
+///
+/// The archive is read into owned buffers, in the same manner as reading through [`Buffered`](crate::Buffered), and is read again after every commit. Nothing is memory-mapped, so files obtained from the editor remain valid regardless of how the file changes, but the data of every file is held in memory while editing.
+///
+/// ```rust
+/// use ba2::{
+///     prelude::*,
+///     tes4::{Editor, File},
+/// };
+/// use std::fs;
+///
+/// fn example() -> Option<()> {
+///     let stream = fs::File::options()
+///         .read(true)
+///         .write(true)
+///         .open("path/to/skyrim/Data/Skyrim - Misc.bsa")
+///         .ok()?;
+///     let mut editor = Editor::new(stream).ok()?;
+///     editor.insert_file("misc/hello.txt", File::from_decompressed(b"Hello world!\n"));
+///     editor.commit().ok()?;
+///     Some(())
+/// }
+/// ```
+pub struct Editor<'bytes> {
+    stream: fs::File,
+    archive: Archive<'bytes>,
+    options: ArchiveOptions,
+    layout: ArchiveLayout,
+    storage: Storage<(DirectoryHash, FileHash)>,
+}
+
+impl<'bytes> Editor<'bytes> {
+    /// Opens the archive within the given file, which must be both readable and writable.
+    pub fn new(stream: fs::File) -> Result<Self> {
+        let (archive, options, layout, storage) = Archive::read_in_place(&stream)?;
+        Ok(Self {
+            stream,
+            archive,
+            options,
+            layout,
+            storage,
+        })
+    }
+
+    #[must_use]
+    pub fn archive(&self) -> &Archive<'bytes> {
+        &self.archive
+    }
+
+    /// The number of bytes within the file which no longer hold any data, as of the last commit.
+    #[must_use]
+    pub fn dead_space(&self) -> usize {
+        self.storage.dead_space()
+    }
+
+    /// See also [`Archive::insert_file`].
+    pub fn insert_file<P>(&mut self, path: &P, file: File<'bytes>) -> Option<File<'bytes>>
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        self.invalidate(path.as_ref());
+        self.archive.insert_file(path, file)
+    }
+
+    #[must_use]
+    pub fn into_inner(self) -> fs::File {
+        self.stream
+    }
+
+    #[must_use]
+    pub fn options(&self) -> &ArchiveOptions {
+        &self.options
+    }
+
+    /// See also [`Archive::remove_file`].
+    pub fn remove_file<P>(&mut self, path: &P) -> Option<File<'bytes>>
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        self.invalidate(path.as_ref());
+        self.archive.remove_file(path)
+    }
+
+    /// Writes every change made since the last commit to the file.
+    pub fn commit(&mut self) -> Result<()> {
+        self.archive
+            .write_in_place(&mut self.stream, self.options, &self.layout, &self.storage)?;
+        self.reload()
+    }
+
+    /// Commits any changes, and then reclaims all dead space within the file, by moving file data towards the start of the file and truncating it.
+    pub fn compact(&mut self) -> Result<()> {
+        self.commit()?;
+        let len = self.archive.compact_in_place(
+            &mut self.stream,
+            self.options,
+            &self.layout,
+            &self.storage,
+        )?;
+        self.stream.set_len(len.try_into()?)?;
+        self.reload()
+    }
+
+    fn invalidate(&mut self, path: &[u8]) {
+        let id = Archive::hash_path(path);
+        self.storage.retain(|x| *x != id);
+    }
+
+    fn reload(&mut self) -> Result<()> {
+        let (archive, options, layout, storage) = Archive::read_in_place(&self.stream)?;
+        self.archive = archive;
+        self.options = options;
+        self.layout = layout;
+        self.storage = storage;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        prelude::*,
+        tes4::{Archive, Editor, File},
+        Copied,
+    };
+    use std::{fs, path::Path};
+
+    #[test]
+    fn append_and_compact() -> anyhow::Result<()> {
+        let source = Path::new("data/tes4_compression_test/test_105.bsa");
+        let (original, _) = Archive::read(source)?;
+        let path = std::env::temp_dir().join("ba2_tes4_editor_append_and_compact.bsa");
+        fs::copy(source, &path)?;
+        let stream = fs::File::options().read(true).write(true).open(&path)?;
+        let mut editor = Editor::new(stream)?;
+        assert_eq!(editor.dead_space(), 0);
+
+        let (removed, _) = original.files().next().unwrap();
+        assert!(editor.remove_file(removed.as_ref()).is_some());
+        for i in 0..64 {
+            let file = File::from_decompressed(b"Hello world!\n");
+            editor.insert_file(format!("new/file{i}.txt").as_str(), file);
+        }
+        editor.commit()?;
+        assert!(editor.dead_space() > 0);
+
+        // the index grew over the data which followed it, so it must have been relocated
+        let bytes = fs::read(&path)?;
+        let (copy, options) = Archive::read(Copied(&bytes))?;
+        assert!(copy.get_file(removed.as_ref()).is_none());
+        for (name, file) in original.files() {
+            if name == removed {
+                continue;
+            }
+            let other = copy.get_file(name.as_ref()).unwrap();
+            assert_eq!(other.as_bytes(), file.as_bytes());
+        }
+        let file = copy.get_file("new/file7.txt").unwrap();
+        assert_eq!(file.as_bytes(), b"Hello world!\n");
+
+        editor.compact()?;
+        assert_eq!(editor.dead_space(), 0);
+        let bytes = fs::read(&path)?;
+        let (compacted, _) = Archive::read(Copied(&bytes))?;
+        assert!(compacted.diff(&options, &copy, &options)?.is_empty());
+
+        let mut rewritten = Vec::new();
+        compacted.write(&mut rewritten, &options)?;
+        assert_eq!(bytes.len(), rewritten.len());
+
+        Ok(())
+    }
+}
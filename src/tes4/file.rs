@@ -1,16 +1,12 @@
 use crate::{
+    codec::{lz4, zlib},
     containers::CompressableBytes,
     derive,
     io::Source,
     tes4::{ArchiveOptions, CompressionCodec, Error, Result, Version},
     CompressionResult,
 };
-use flate2::{
-    write::{ZlibDecoder, ZlibEncoder},
-    Compression,
-};
-use lzzzz::lz4f::{self, AutoFlush, PreferencesBuilder};
-use std::io::Write;
+use flate2::Compression;
 
 /// See also [`FileCompressionOptions`](CompressionOptions).
 #[derive(Debug, Default)]
@@ -29,6 +25,15 @@ impl CompressionOptionsBuilder {
         self
     }
 
+    /// Overrides the compression level used by the codec, where `None` uses the level the game was shipped with.
+    ///
+    /// Levels range from `0..=9` for zlib, and `1..=12` for lz4. Higher levels trade speed for a better compression ratio, and are clamped to the range supported by the codec, so level 0 is the same as level 1 for lz4.
+    #[must_use]
+    pub fn compression_level_override(mut self, compression_level_override: Option<u32>) -> Self {
+        self.0.compression_level_override = compression_level_override;
+        self
+    }
+
     #[must_use]
     pub fn new() -> Self {
         Self::default()
@@ -72,11 +77,18 @@ impl From<&ArchiveOptions> for CompressionOptionsBuilder {
 /// let _ = FileCompressionOptions::builder()
 ///     .version(Version::SSE)
 ///     .build();
+///
+/// // Configure for SSE, favoring speed over size
+/// let _ = FileCompressionOptions::builder()
+///     .version(Version::SSE)
+///     .compression_level_override(Some(1))
+///     .build();
 /// ```
 #[derive(Clone, Copy, Debug, Default)]
 pub struct CompressionOptions {
     version: Version,
     compression_codec: CompressionCodec,
+    compression_level_override: Option<u32>,
 }
 
 impl CompressionOptions {
@@ -90,6 +102,11 @@ impl CompressionOptions {
         self.compression_codec
     }
 
+    #[must_use]
+    pub fn compression_level_override(&self) -> Option<u32> {
+        self.compression_level_override
+    }
+
     #[must_use]
     pub fn version(&self) -> Version {
         self.version
@@ -242,11 +259,13 @@ impl<'bytes> File<'bytes> {
             Err(Error::AlreadyCompressed)
         } else {
             match options.version {
-                Version::v103 => self.compress_into_zlib(out),
+                Version::v103 => self.compress_into_zlib(out, options.compression_level_override),
                 Version::v104 => match options.compression_codec {
-                    CompressionCodec::Normal => self.compress_into_zlib(out),
+                    CompressionCodec::Normal => {
+                        self.compress_into_zlib(out, options.compression_level_override)
+                    }
                 },
-                Version::v105 => self.compress_into_lz4(out),
+                Version::v105 => self.compress_into_lz4(out, options.compression_level_override),
             }
         }
     }
@@ -258,9 +277,9 @@ impl<'bytes> File<'bytes> {
 
         out.reserve_exact(decompressed_len);
         let out_len = match options.version {
-            Version::v103 => self.decompress_into_zlib(out),
+            Version::v103 => self.decompress_into_zlib(out, decompressed_len),
             Version::v104 => match options.compression_codec {
-                CompressionCodec::Normal => self.decompress_into_zlib(out),
+                CompressionCodec::Normal => self.decompress_into_zlib(out, decompressed_len),
             },
             Version::v105 => self.decompress_into_lz4(out),
         }?;
@@ -280,31 +299,25 @@ impl<'bytes> File<'bytes> {
         File { bytes }
     }
 
-    fn compress_into_lz4(&self, out: &mut Vec<u8>) -> Result<()> {
-        let prefs = PreferencesBuilder::new()
-            .compression_level(9)
-            .auto_flush(AutoFlush::Enabled)
-            .build();
-        lz4f::compress_to_vec(self.as_bytes(), out, &prefs)?;
+    fn compress_into_lz4(&self, out: &mut Vec<u8>, level: Option<u32>) -> Result<()> {
+        lz4::compress_frame(self.as_bytes(), out, level.unwrap_or(9))?;
         Ok(())
     }
 
-    fn compress_into_zlib(&self, out: &mut Vec<u8>) -> Result<()> {
-        let mut e = ZlibEncoder::new(out, Compression::default());
-        e.write_all(self.as_bytes())?;
-        e.finish()?;
+    fn compress_into_zlib(&self, out: &mut Vec<u8>, level: Option<u32>) -> Result<()> {
+        let level = level.map_or_else(Compression::default, |level| Compression::new(level.min(9)));
+        zlib::compress(self.as_bytes(), out, level, zlib::MAX_WINDOW_BITS)?;
         Ok(())
     }
 
     fn decompress_into_lz4(&self, out: &mut Vec<u8>) -> Result<usize> {
-        let len = lz4f::decompress_to_vec(self.as_bytes(), out)?;
+        let len = lz4::decompress_frame(self.as_bytes(), out)?;
         Ok(len)
     }
 
-    fn decompress_into_zlib(&self, out: &mut Vec<u8>) -> Result<usize> {
-        let mut d = ZlibDecoder::new(out);
-        d.write_all(self.as_bytes())?;
-        Ok(d.total_out().try_into()?)
+    fn decompress_into_zlib(&self, out: &mut Vec<u8>, decompressed_len: usize) -> Result<usize> {
+        let len = zlib::decompress(self.as_bytes(), out, decompressed_len)?;
+        Ok(len)
     }
 
     #[allow(clippy::trivially_copy_pass_by_ref)]
@@ -324,7 +337,11 @@ impl<'bytes> File<'bytes> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{prelude::*, tes4::File};
+    use crate::{
+        containers::CompressableBytes,
+        prelude::*,
+        tes4::{Error, File, FileCompressionOptions, Version},
+    };
 
     #[test]
     fn default_state() {
@@ -344,4 +361,56 @@ mod tests {
         assert_eq!(f.as_bytes().len(), payload.len());
         assert_eq!(f.as_bytes().as_ptr(), payload.as_ptr());
     }
+
+    #[test]
+    fn compression_levels() -> anyhow::Result<()> {
+        let payload: Vec<u8> = (0..4096u32)
+            .flat_map(|i| (i * i % 997).to_le_bytes())
+            .collect();
+        let payload = &payload[..];
+        let decompressed = File::from_decompressed(payload);
+        for version in [Version::TES4, Version::SSE] {
+            let compress = |level| {
+                let options = FileCompressionOptions::builder()
+                    .version(version)
+                    .compression_level_override(level)
+                    .build();
+                decompressed.compress(&options).map(|file| (file, options))
+            };
+
+            let (fast, _) = compress(Some(0))?;
+            let (best, options) = compress(Some(u32::MAX))?;
+            // the pure rust lz4 backend does not support compression levels
+            if version != Version::SSE || cfg!(feature = "lzzzz") {
+                assert!(best.len() < fast.len());
+            }
+            assert_eq!(best.decompress(&options)?.as_bytes(), payload);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decompression_size_mismatch() -> anyhow::Result<()> {
+        let payload = [b'x'; 64];
+        for version in [Version::TES4, Version::SSE] {
+            let options = FileCompressionOptions::builder().version(version).build();
+            let compressed = File::from_decompressed(&payload[..]).compress(&options)?;
+            for decompressed_len in [payload.len() / 2, payload.len() * 2] {
+                let file = File {
+                    bytes: CompressableBytes::from_owned(
+                        compressed.as_bytes().into(),
+                        Some(decompressed_len),
+                    ),
+                };
+                match file.decompress(&options) {
+                    Err(Error::DecompressionSizeMismatch { expected, actual }) => {
+                        assert_eq!(expected, decompressed_len);
+                        assert_eq!(actual, payload.len());
+                    }
+                    _ => anyhow::bail!("decompression should have failed with a size mismatch"),
+                }
+            }
+        }
+        Ok(())
+    }
 }
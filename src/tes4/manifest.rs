@@ -0,0 +1,102 @@
+use crate::tes4::{Archive, ArchiveKey, ArchiveOptions, DirectoryKey};
+use serde::{Deserialize, Serialize};
+
+/// A file within a [`ManifestDirectory`](Directory).
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct File {
+    pub key: DirectoryKey<'static>,
+    /// Whether or not the file is stored compressed.
+    pub compressed: bool,
+}
+
+/// A directory within a [`Manifest`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Directory {
+    pub key: ArchiveKey<'static>,
+    pub files: Vec<File>,
+}
+
+/// Describes everything about a TES4 archive, except for the contents of its files.
+///
+/// A manifest can be stored alongside an archive's extracted files, so that the archive can be rebuilt identically later. Keys are stored with both their hash and their name (as a byte string), so that neither is lost, even if the name does not hash to the same value.
+///
+/// ```rust
+/// use ba2::{
+///     prelude::*,
+///     tes4::{Archive, ArchiveOptions, File, Manifest},
+/// };
+///
+/// fn example() -> Option<()> {
+///     let mut archive = Archive::new();
+///     archive.insert_file("misc/hello.txt", File::from_decompressed(b"Hello world!\n"));
+///     let manifest = Manifest::new(&archive, &ArchiveOptions::default());
+///     let json = serde_json::to_string(&manifest).ok()?;
+///     let manifest: Manifest = serde_json::from_str(&json).ok()?;
+///     assert_eq!(manifest.directories[0].files[0].key.name(), "hello.txt");
+///     Some(())
+/// }
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Manifest {
+    pub options: ArchiveOptions,
+    pub directories: Vec<Directory>,
+}
+
+impl Manifest {
+    /// Describes the given archive, which was read using the given options.
+    #[must_use]
+    pub fn new(archive: &Archive<'_>, options: &ArchiveOptions) -> Self {
+        let directories = archive
+            .iter()
+            .map(|(key, directory)| Directory {
+                key: key.clone().into_owned(),
+                files: directory
+                    .iter()
+                    .map(|(key, file)| File {
+                        key: key.clone().into_owned(),
+                        compressed: file.is_compressed(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        Self {
+            options: *options,
+            directories,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        prelude::*,
+        tes4::{Archive, Manifest},
+    };
+    use std::path::Path;
+
+    #[test]
+    fn round_trip() -> anyhow::Result<()> {
+        let path = Path::new("data/tes4_compression_test/test_105.bsa");
+        let (archive, options) = Archive::read(path)?;
+        let manifest = Manifest::new(&archive, &options);
+        let json = serde_json::to_string(&manifest)?;
+        let copy: Manifest = serde_json::from_str(&json)?;
+
+        assert_eq!(copy.options.version(), options.version());
+        assert_eq!(copy.options.flags(), options.flags());
+        assert_eq!(copy.options.types(), options.types());
+        assert_eq!(copy.directories.len(), archive.len());
+        for (directory, (key, original)) in copy.directories.iter().zip(&archive) {
+            assert_eq!(directory.key.hash(), key.hash());
+            assert_eq!(directory.key.name(), key.name());
+            assert_eq!(directory.files.len(), original.len());
+            for (file, (key, original)) in directory.files.iter().zip(original) {
+                assert_eq!(file.key.hash(), key.hash());
+                assert_eq!(file.key.name(), key.name());
+                assert_eq!(file.compressed, original.is_compressed());
+            }
+        }
+
+        Ok(())
+    }
+}
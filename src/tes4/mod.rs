@@ -67,16 +67,28 @@
 //! ```
 
 mod archive;
+#[cfg(feature = "async")]
+mod async_archive;
+mod diff;
 mod directory;
+mod editor;
 mod file;
 mod hashing;
+#[cfg(feature = "serde")]
+mod manifest;
+mod patch;
 
 pub use self::{
     archive::{
-        Archive, Flags as ArchiveFlags, Key as ArchiveKey, Options as ArchiveOptions,
-        OptionsBuilder as ArchiveOptionsBuilder, Types as ArchiveTypes,
+        Archive, Flags as ArchiveFlags, Key as ArchiveKey, Layout as ArchiveLayout,
+        Options as ArchiveOptions, OptionsBuilder as ArchiveOptionsBuilder, Types as ArchiveTypes,
+    },
+    diff::{
+        Change as DiffChange, CompressionChange as DiffCompressionChange, Diff, Entry as DiffEntry,
+        Rename as DiffRename,
     },
     directory::{Directory, Key as DirectoryKey},
+    editor::Editor,
     file::{
         CompressionOptions as FileCompressionOptions,
         CompressionOptionsBuilder as FileCompressionOptionsBuilder, File,
@@ -86,9 +98,23 @@ pub use self::{
         hash_directory, hash_directory_in_place, hash_file, hash_file_in_place, DirectoryHash,
         FileHash, Hash,
     },
+    patch::{
+        Addition as PatchAddition, Base as PatchBase, Patch, Replacement as PatchReplacement,
+        Target as PatchTarget,
+    },
 };
 
+#[cfg(feature = "async")]
+pub use self::async_archive::AsyncArchive;
+
+#[cfg(feature = "serde")]
+pub use self::manifest::{Directory as ManifestDirectory, File as ManifestFile, Manifest};
+
+#[cfg(feature = "lz4_flex")]
+use crate::Lz4Error;
+use bstr::BString;
 use core::num::TryFromIntError;
+#[cfg(feature = "lzzzz")]
 use lzzzz::lz4f;
 use std::io;
 
@@ -101,9 +127,18 @@ pub enum Error {
     #[error("can not decompress the given file because it is already decompressed")]
     AlreadyDecompressed,
 
+    #[error("the operation was cancelled")]
+    Cancelled,
+
     #[error("buffer failed to decompress to the expected size... expected {expected} bytes, but got {actual} bytes")]
     DecompressionSizeMismatch { expected: usize, actual: usize },
 
+    #[error("a file already exists at the given path: {0}")]
+    FileExists(BString),
+
+    #[error("no file exists at the given path: {0}")]
+    FileNotFound(BString),
+
     #[error("an operation on two integers would have overflowed and corrupted data")]
     IntegralOverflow,
 
@@ -122,8 +157,22 @@ pub enum Error {
     #[error(transparent)]
     Io(#[from] io::Error),
 
+    #[cfg(feature = "lzzzz")]
     #[error(transparent)]
     LZ4(#[from] lz4f::Error),
+
+    #[cfg(feature = "lz4_flex")]
+    #[error(transparent)]
+    Lz4Flex(#[from] Lz4Error),
+
+    #[error("file is present in more than one archive: {0}")]
+    MergeConflict(BString),
+
+    #[error("patch was made against a different archive than the given one")]
+    PatchBaseMismatch,
+
+    #[error("patch does not apply to the given archive: {0}")]
+    PatchMismatch(BString),
 }
 
 impl From<TryFromIntError> for Error {
@@ -149,6 +198,7 @@ pub enum CompressionCodec {
 /// Each version has an impact on the abi of the TES4 archive file format.
 #[allow(non_camel_case_types)]
 #[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Version {
     #[default]
     v103 = 103,
@@ -0,0 +1,721 @@
+use crate::{
+    containers::Bytes,
+    derive,
+    io::{Endian, Sink, Source},
+    protocols::WString,
+    tes4::{
+        Archive, ArchiveFlags, ArchiveKey, ArchiveOptions, ArchiveTypes, Diff, DiffEntry,
+        Directory, DirectoryHash, DirectoryKey, Error, File, FileHash, Hash, Result, Version,
+    },
+};
+use bstr::{BStr, BString};
+use flate2::Crc;
+use std::{collections::BTreeMap, io::Write};
+
+mod constants {
+    use crate::cc;
+
+    pub(crate) const MAGIC: u32 = cc::make_four(b"BSAP");
+    pub(crate) const VERSION: u32 = 1;
+}
+
+/// A fingerprint of the archive which a [`Patch`] was made against.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Base {
+    /// The options the base archive was read with.
+    pub options: ArchiveOptions,
+    /// The crc32 of the hash and checksum of every file within the base archive, in hash order.
+    pub checksum: u32,
+}
+
+impl Base {
+    #[must_use]
+    pub fn new(archive: &Archive<'_>, options: &ArchiveOptions) -> Self {
+        let mut crc = Crc::new();
+        for (directory_key, directory) in archive {
+            for (key, file) in directory {
+                crc.update(&directory_key.hash().numeric().to_le_bytes());
+                crc.update(&key.hash().numeric().to_le_bytes());
+                crc.update(&checksum(file).to_le_bytes());
+            }
+        }
+        Self {
+            options: *options,
+            checksum: crc.sum(),
+        }
+    }
+
+    /// Returns `true` if the given archive, read using the given options, is the one this fingerprint was taken of.
+    #[must_use]
+    pub fn matches(&self, archive: &Archive<'_>, options: &ArchiveOptions) -> bool {
+        let other = Self::new(archive, options);
+        self.checksum == other.checksum
+            && self.options.version() == other.options.version()
+            && self.options.flags() == other.options.flags()
+            && self.options.types() == other.options.types()
+    }
+}
+
+/// A file which must exist within the base archive for a [`Patch`] to apply.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Target {
+    pub entry: DiffEntry,
+    /// The crc32 of the file's data, exactly as it is stored within the base archive.
+    pub checksum: u32,
+}
+
+impl Target {
+    fn new(directory: &ArchiveKey<'_>, key: &DirectoryKey<'_>, file: &File<'_>) -> Self {
+        Self {
+            entry: DiffEntry::new(directory, key),
+            checksum: checksum(file),
+        }
+    }
+}
+
+/// A file which replaces a [`Target`] within the base archive.
+#[derive(Clone, Debug)]
+pub struct Replacement<'bytes> {
+    pub target: Target,
+    pub directory: ArchiveKey<'bytes>,
+    pub key: DirectoryKey<'bytes>,
+    /// The new data for the file, or `None` if only its path has changed.
+    pub file: Option<File<'bytes>>,
+}
+
+/// A file which does not exist within the base archive.
+#[derive(Clone, Debug)]
+pub struct Addition<'bytes> {
+    pub directory: ArchiveKey<'bytes>,
+    pub key: DirectoryKey<'bytes>,
+    pub file: File<'bytes>,
+}
+
+/// A set of changes which transforms one revision of a TES4 archive into another.
+///
+/// Only the data for files which were added or replaced is stored, exactly as it was stored in the new archive (i.e. compressed files remain compressed). Every file which is removed or replaced is identified by a checksum of its data in the base archive, and the base archive as a whole is identified by a [`Base`] fingerprint of its options and of every file within it. [`apply`](Self::apply) verifies all of them before making any changes, so that a patch can not be applied to the wrong base.
+///
+/// ```rust
+/// use ba2::{
+///     prelude::*,
+///     tes4::{Archive, ArchiveOptions, File, Patch},
+///     Borrowed,
+/// };
+///
+/// fn example() -> Option<()> {
+///     let options = ArchiveOptions::default();
+///     let mut old = Archive::new();
+///     old.insert_file("misc/hello.txt", File::from_decompressed(b"Hello world!\n"));
+///     let mut new = old.clone();
+///     new.insert_file("misc/hello.txt", File::from_decompressed(b"Goodbye world!\n"));
+///
+///     let patch = Patch::new(&old, &options, &new, &options).ok()?;
+///     let mut stream = Vec::new();
+///     patch.write(&mut stream).ok()?;
+///
+///     let patch = Patch::read(Borrowed(&stream)).ok()?;
+///     let mut options = options;
+///     patch.apply(&mut old, &mut options).ok()?;
+///     assert!(old.diff(&options, &new, &options).ok()?.is_empty());
+///     Some(())
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Patch<'bytes> {
+    pub base: Base,
+    /// The options of the new archive.
+    pub options: ArchiveOptions,
+    pub removed: Vec<Target>,
+    pub replaced: Vec<Replacement<'bytes>>,
+    pub added: Vec<Addition<'bytes>>,
+}
+
+impl crate::Sealed for Patch<'_> {}
+
+type ReadResult<T> = T;
+derive::reader!(Patch => ReadResult);
+
+impl<'bytes> Patch<'bytes> {
+    /// Makes a patch which transforms `old` into `new`, where each archive is paired with the options it was read with.
+    ///
+    /// Files are compared using [`Archive::diff`]. If the archives differ in version, then any file which is compressed in either archive is also replaced, since its compressed data would not be valid in the other.
+    pub fn new(
+        old: &Archive<'_>,
+        old_options: &ArchiveOptions,
+        new: &Archive<'bytes>,
+        new_options: &ArchiveOptions,
+    ) -> Result<Self> {
+        let diff = Diff::new((old, old_options), (new, new_options))?;
+
+        // maps each replaced file to whether or not its data must be replaced as well
+        let mut replaced = BTreeMap::new();
+        for entry in diff
+            .changed
+            .iter()
+            .chain(diff.compression.iter().map(|x| &x.entry))
+        {
+            replaced.insert((entry.directory, entry.file), true);
+        }
+        for rename in &diff.renamed {
+            replaced
+                .entry((rename.directory, rename.file))
+                .or_insert(false);
+        }
+        if diff.version.is_some() {
+            for (directory_key, directory) in new {
+                for (key, file) in directory {
+                    if let Some((_, _, old_file)) = find(old, *directory_key.hash(), *key.hash()) {
+                        if file.is_compressed() || old_file.is_compressed() {
+                            replaced.insert((*directory_key.hash(), *key.hash()), true);
+                        }
+                    }
+                }
+            }
+        }
+
+        let removed = diff
+            .removed
+            .iter()
+            .filter_map(|entry| find(old, entry.directory, entry.file))
+            .map(|(directory, key, file)| Target::new(directory, key, file))
+            .collect();
+        let replaced = replaced
+            .into_iter()
+            .filter_map(|((directory, file), data)| {
+                let (old_directory, old_key, old_file) = find(old, directory, file)?;
+                let (directory, key, file) = find(new, directory, file)?;
+                Some(Replacement {
+                    target: Target::new(old_directory, old_key, old_file),
+                    directory: directory.clone(),
+                    key: key.clone(),
+                    file: data.then(|| file.clone()),
+                })
+            })
+            .collect();
+        let added = diff
+            .added
+            .iter()
+            .filter_map(|entry| find(new, entry.directory, entry.file))
+            .map(|(directory, key, file)| Addition {
+                directory: directory.clone(),
+                key: key.clone(),
+                file: file.clone(),
+            })
+            .collect();
+
+        Ok(Self {
+            base: Base::new(old, old_options),
+            options: *new_options,
+            removed,
+            replaced,
+            added,
+        })
+    }
+
+    /// Applies the patch to the given archive, which was read using the given options.
+    ///
+    /// Every removed or replaced file must exist within the archive with a matching checksum, and every added file must not exist within the archive, otherwise [`Error::PatchMismatch`] is returned. Then the archive and its options must match the [`Base`] of the patch, otherwise [`Error::PatchBaseMismatch`] is returned. In either case, the archive is left untouched. Directories which are emptied by the patch are removed.
+    pub fn apply(self, archive: &mut Archive<'bytes>, options: &mut ArchiveOptions) -> Result<()> {
+        let targets = self
+            .removed
+            .iter()
+            .chain(self.replaced.iter().map(|x| &x.target));
+        for target in targets {
+            let entry = &target.entry;
+            match find(archive, entry.directory, entry.file) {
+                Some((_, _, file)) if checksum(file) == target.checksum => (),
+                _ => return Err(Error::PatchMismatch(entry.to_string().into())),
+            }
+        }
+        for addition in &self.added {
+            if find(archive, *addition.directory.hash(), *addition.key.hash()).is_some() {
+                let entry = DiffEntry::new(&addition.directory, &addition.key);
+                return Err(Error::PatchMismatch(entry.to_string().into()));
+            }
+        }
+        if !self.base.matches(archive, options) {
+            return Err(Error::PatchBaseMismatch);
+        }
+
+        for target in &self.removed {
+            remove(archive, target.entry.directory, target.entry.file);
+        }
+        for replacement in self.replaced {
+            let entry = &replacement.target.entry;
+            if let Some(file) = remove(archive, entry.directory, entry.file) {
+                let file = replacement.file.unwrap_or(file);
+                insert(archive, replacement.directory, replacement.key, file);
+            }
+        }
+        for addition in self.added {
+            insert(archive, addition.directory, addition.key, addition.file);
+        }
+
+        *options = self.options;
+        Ok(())
+    }
+
+    /// Returns `true` if the patch makes no changes to the files of an archive.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.replaced.is_empty() && self.added.is_empty()
+    }
+
+    pub fn write<Out>(&self, stream: &mut Out) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        let mut sink = Sink::new(stream);
+        sink.write(&(constants::MAGIC, constants::VERSION), Endian::Little)?;
+        Self::write_options(&mut sink, self.base.options)?;
+        sink.write(&self.base.checksum, Endian::Little)?;
+        Self::write_options(&mut sink, self.options)?;
+
+        let len: u32 = self.removed.len().try_into()?;
+        sink.write(&len, Endian::Little)?;
+        for target in &self.removed {
+            Self::write_target(&mut sink, target)?;
+        }
+
+        let len: u32 = self.replaced.len().try_into()?;
+        sink.write(&len, Endian::Little)?;
+        for replacement in &self.replaced {
+            Self::write_target(&mut sink, &replacement.target)?;
+            Self::write_key(
+                &mut sink,
+                replacement.directory.hash(),
+                replacement.directory.name(),
+            )?;
+            Self::write_key(&mut sink, replacement.key.hash(), replacement.key.name())?;
+            match &replacement.file {
+                Some(file) => {
+                    sink.write(&1u8, Endian::Little)?;
+                    Self::write_file(&mut sink, file)?;
+                }
+                None => sink.write(&0u8, Endian::Little)?,
+            }
+        }
+
+        let len: u32 = self.added.len().try_into()?;
+        sink.write(&len, Endian::Little)?;
+        for addition in &self.added {
+            Self::write_key(
+                &mut sink,
+                addition.directory.hash(),
+                addition.directory.name(),
+            )?;
+            Self::write_key(&mut sink, addition.key.hash(), addition.key.name())?;
+            Self::write_file(&mut sink, &addition.file)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_file<Out>(sink: &mut Sink<Out>, file: &File<'bytes>) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        match file.decompressed_len() {
+            Some(len) => {
+                let len: u32 = len.try_into()?;
+                sink.write(&(1u8, len), Endian::Little)?;
+            }
+            None => sink.write(&(0u8, 0u32), Endian::Little)?,
+        }
+        let len: u32 = file.len().try_into()?;
+        sink.write(&len, Endian::Little)?;
+        sink.write_bytes(file.as_bytes())?;
+        Ok(())
+    }
+
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn write_hash<Out>(sink: &mut Sink<Out>, hash: &Hash) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        sink.write(
+            &(hash.last, hash.last2, hash.length, hash.first, hash.crc),
+            Endian::Little,
+        )?;
+        Ok(())
+    }
+
+    fn write_options<Out>(sink: &mut Sink<Out>, options: ArchiveOptions) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        sink.write(
+            &(
+                options.version() as u32,
+                options.flags().bits(),
+                options.types().bits(),
+            ),
+            Endian::Little,
+        )?;
+        Ok(())
+    }
+
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn write_key<Out>(sink: &mut Sink<Out>, hash: &Hash, name: &BStr) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        Self::write_hash(sink, hash)?;
+        sink.write_protocol::<WString>(name, Endian::Little)?;
+        Ok(())
+    }
+
+    fn write_target<Out>(sink: &mut Sink<Out>, target: &Target) -> Result<()>
+    where
+        Out: ?Sized + Write,
+    {
+        Self::write_hash(sink, &target.entry.directory)?;
+        Self::write_hash(sink, &target.entry.file)?;
+        sink.write_protocol::<WString>(target.entry.path.as_ref(), Endian::Little)?;
+        sink.write(&target.checksum, Endian::Little)?;
+        Ok(())
+    }
+
+    fn do_read<In>(source: &mut In) -> Result<ReadResult<Self>>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        let (magic, version): (u32, u32) = source.read(Endian::Little)?;
+        if magic != constants::MAGIC {
+            return Err(Error::InvalidMagic(magic));
+        }
+        if version != constants::VERSION {
+            return Err(Error::InvalidVersion(version));
+        }
+
+        let base = Base {
+            options: Self::read_options(source)?,
+            checksum: source.read(Endian::Little)?,
+        };
+        let options = Self::read_options(source)?;
+
+        let len: u32 = source.read(Endian::Little)?;
+        let mut removed = Vec::new();
+        for _ in 0..len {
+            removed.push(Self::read_target(source)?);
+        }
+
+        let len: u32 = source.read(Endian::Little)?;
+        let mut replaced = Vec::new();
+        for _ in 0..len {
+            let target = Self::read_target(source)?;
+            let (hash, name) = Self::read_key(source)?;
+            let directory = ArchiveKey {
+                hash: hash.into(),
+                name,
+            };
+            let (hash, name) = Self::read_key(source)?;
+            let key = DirectoryKey {
+                hash: hash.into(),
+                name,
+            };
+            let has_file: u8 = source.read(Endian::Little)?;
+            let file = if has_file == 0 {
+                None
+            } else {
+                Some(Self::read_file(source)?)
+            };
+            replaced.push(Replacement {
+                target,
+                directory,
+                key,
+                file,
+            });
+        }
+
+        let len: u32 = source.read(Endian::Little)?;
+        let mut added = Vec::new();
+        for _ in 0..len {
+            let (hash, name) = Self::read_key(source)?;
+            let directory = ArchiveKey {
+                hash: hash.into(),
+                name,
+            };
+            let (hash, name) = Self::read_key(source)?;
+            let key = DirectoryKey {
+                hash: hash.into(),
+                name,
+            };
+            let file = Self::read_file(source)?;
+            added.push(Addition {
+                directory,
+                key,
+                file,
+            });
+        }
+
+        Ok(Self {
+            base,
+            options,
+            removed,
+            replaced,
+            added,
+        })
+    }
+
+    fn read_file<In>(source: &mut In) -> Result<File<'bytes>>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        let (compressed, decompressed_len, len): (u8, u32, u32) = source.read(Endian::Little)?;
+        let bytes = source.read_bytes(len as usize)?;
+        let decompressed_len = (compressed != 0).then_some(decompressed_len as usize);
+        Ok(File {
+            bytes: bytes.into_compressable(decompressed_len),
+        })
+    }
+
+    fn read_hash<In>(source: &mut In) -> Result<Hash>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        let (last, last2, length, first, crc) = source.read(Endian::Little)?;
+        Ok(Hash {
+            last,
+            last2,
+            length,
+            first,
+            crc,
+        })
+    }
+
+    fn read_key<In>(source: &mut In) -> Result<(Hash, Bytes<'bytes>)>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        let hash = Self::read_hash(source)?;
+        let name = source.read_protocol::<WString>(Endian::Little)?;
+        Ok((hash, name))
+    }
+
+    fn read_options<In>(source: &mut In) -> Result<ArchiveOptions>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        let (version, flags, types): (u32, u32, u16) = source.read(Endian::Little)?;
+        let version = match version {
+            103 => Version::v103,
+            104 => Version::v104,
+            105 => Version::v105,
+            _ => return Err(Error::InvalidVersion(version)),
+        };
+        Ok(ArchiveOptions::builder()
+            .version(version)
+            .flags(ArchiveFlags::from_bits_retain(flags))
+            .types(ArchiveTypes::from_bits_retain(types))
+            .build())
+    }
+
+    fn read_target<In>(source: &mut In) -> Result<Target>
+    where
+        In: ?Sized + Source<'bytes>,
+    {
+        let directory: DirectoryHash = Self::read_hash(source)?.into();
+        let file: FileHash = Self::read_hash(source)?.into();
+        let path = source.read_protocol::<WString>(Endian::Little)?;
+        let checksum = source.read(Endian::Little)?;
+        Ok(Target {
+            entry: DiffEntry {
+                directory,
+                file,
+                path: BString::from(path.as_bytes()),
+            },
+            checksum,
+        })
+    }
+}
+
+fn checksum(file: &File<'_>) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(file.as_bytes());
+    crc.sum()
+}
+
+fn find<'archive, 'bytes>(
+    archive: &'archive Archive<'bytes>,
+    directory: DirectoryHash,
+    file: FileHash,
+) -> Option<(
+    &'archive ArchiveKey<'bytes>,
+    &'archive DirectoryKey<'bytes>,
+    &'archive File<'bytes>,
+)> {
+    let (directory_key, directory) = archive.get_key_value(&directory)?;
+    let (key, file) = directory.get_key_value(&file)?;
+    Some((directory_key, key, file))
+}
+
+fn insert<'bytes>(
+    archive: &mut Archive<'bytes>,
+    directory: ArchiveKey<'bytes>,
+    key: DirectoryKey<'bytes>,
+    file: File<'bytes>,
+) {
+    if let Some((existing_key, mut existing)) = archive.remove_entry(directory.hash()) {
+        let directory = if directory.name().is_empty() {
+            existing_key
+        } else {
+            directory
+        };
+        existing.insert(key, file);
+        archive.insert(directory, existing);
+    } else {
+        let files: Directory = [(key, file)].into_iter().collect();
+        archive.insert(directory, files);
+    }
+}
+
+fn remove<'bytes>(
+    archive: &mut Archive<'bytes>,
+    directory: DirectoryHash,
+    file: FileHash,
+) -> Option<File<'bytes>> {
+    let files = archive.get_mut(&directory)?;
+    let result = files.remove(&file);
+    if files.is_empty() {
+        archive.remove(&directory);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        containers::Bytes,
+        prelude::*,
+        tes4::{
+            Archive, ArchiveKey, ArchiveOptions, DirectoryKey, Error, File, FileCompressionOptions,
+            Patch, Version,
+        },
+        Borrowed,
+    };
+    use std::path::Path;
+
+    fn make() -> Archive<'static> {
+        let mut archive = Archive::new();
+        archive.insert_file("a/same.txt", File::from_decompressed(b"same"));
+        archive.insert_file("a/changed.txt", File::from_decompressed(b"old"));
+        archive.insert_file("b/removed.txt", File::from_decompressed(b"gone"));
+        archive.insert_file("b/renamed.txt", File::from_decompressed(b"name"));
+        archive
+    }
+
+    #[test]
+    fn round_trip() -> anyhow::Result<()> {
+        let options = ArchiveOptions::default();
+        let compression = FileCompressionOptions::from(&options);
+        let mut old = make();
+        let mut new = make();
+        new.insert_file(
+            "a/changed.txt",
+            File::from_decompressed(&[b'x'; 64][..]).compress(&compression)?,
+        );
+        new.remove_file("b/removed.txt");
+        new.insert_file("c/added.txt", File::from_decompressed(b"here"));
+
+        // names are normalized when keys are constructed, so emulate a name read from disk
+        let (key, directory) = new.remove_entry(&ArchiveKey::from(b"b")).unwrap();
+        let (mut file_key, file) = directory.into_iter().next().unwrap();
+        file_key.name = Bytes::from_owned(b"Renamed.txt".to_vec().into());
+        new.insert(key, [(file_key, file)].into_iter().collect());
+
+        let patch = Patch::new(&old, &options, &new, &options)?;
+        assert!(!patch.is_empty());
+        assert_eq!(patch.removed.len(), 1);
+        assert_eq!(patch.removed[0].entry.path, "b\\removed.txt");
+        assert_eq!(patch.added.len(), 1);
+        assert_eq!(patch.replaced.len(), 2);
+        assert!(patch
+            .replaced
+            .iter()
+            .any(|x| x.key.name() == "Renamed.txt" && x.file.is_none()));
+
+        let mut stream = Vec::new();
+        patch.write(&mut stream)?;
+        let patch = Patch::read(Borrowed(&stream))?;
+
+        let mut patched_options = ArchiveOptions::builder().version(Version::v105).build();
+        assert!(matches!(
+            patch.clone().apply(&mut old.clone(), &mut patched_options),
+            Err(Error::PatchBaseMismatch)
+        ));
+        let mut patched_options = options;
+        patch.apply(&mut old, &mut patched_options)?;
+        assert!(old.diff(&options, &new, &options)?.is_empty());
+        let file = old.get_file("a/changed.txt").unwrap();
+        assert!(file.is_compressed());
+        let directory = old.get(&ArchiveKey::from(b"b")).unwrap();
+        let (key, _) = directory
+            .get_key_value(&DirectoryKey::from(b"renamed.txt"))
+            .unwrap();
+        assert_eq!(key.name(), "Renamed.txt");
+
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_base() -> anyhow::Result<()> {
+        let options = ArchiveOptions::default();
+        let old = make();
+        let mut new = make();
+        new.insert_file("a/changed.txt", File::from_decompressed(b"new"));
+        let patch = Patch::new(&old, &options, &new, &options)?;
+
+        let mut base = make();
+        base.insert_file("a/changed.txt", File::from_decompressed(b"???"));
+        let mut base_options = options;
+        match patch.apply(&mut base, &mut base_options) {
+            Err(Error::PatchMismatch(name)) => assert_eq!(name, "a\\changed.txt"),
+            Err(err) => return Err(err.into()),
+            Ok(()) => anyhow::bail!("patch should not have applied"),
+        }
+        assert_eq!(base.get_file("a/changed.txt").unwrap().as_bytes(), b"???");
+
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_base_for_additions() -> anyhow::Result<()> {
+        let options = ArchiveOptions::default();
+        let old = make();
+        let mut new = make();
+        new.insert_file("c/added.txt", File::from_decompressed(b"here"));
+        let patch = Patch::new(&old, &options, &new, &options)?;
+        assert!(patch.removed.is_empty() && patch.replaced.is_empty());
+
+        let mut base = make();
+        base.remove_file("a/same.txt");
+        let mut base_options = options;
+        match patch.apply(&mut base, &mut base_options) {
+            Err(Error::PatchBaseMismatch) => (),
+            Err(err) => return Err(err.into()),
+            Ok(()) => anyhow::bail!("patch should not have applied"),
+        }
+        assert!(base.get_file("c/added.txt").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn across_versions() -> anyhow::Result<()> {
+        let root = Path::new("data/tes4_compression_test");
+        let (mut old, mut old_options) = Archive::read(root.join("test_104.bsa").as_path())?;
+        let (new, new_options) = Archive::read(root.join("test_105.bsa").as_path())?;
+
+        let patch = Patch::new(&old, &old_options, &new, &new_options)?;
+        assert!(patch.removed.is_empty() && patch.added.is_empty());
+        assert!(!patch.replaced.is_empty());
+        assert!(patch.replaced.iter().all(|x| x.file.is_some()));
+
+        patch.apply(&mut old, &mut old_options)?;
+        assert_eq!(old_options.version(), Version::v105);
+        assert!(old.diff(&old_options, &new, &new_options)?.is_empty());
+
+        Ok(())
+    }
+}
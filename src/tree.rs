@@ -0,0 +1,514 @@
+//! A hierarchical view over archives which store their paths as a flat list.
+//!
+//! [`tes3`] and [`fo4`] archives identify each file by its full path, rather than grouping files by directory. A [`Tree`] rebuilds the directory hierarchy from those paths, which is useful when presenting the contents of an archive to a user, i.e. in an asset browser.
+//!
+//! ```rust,no_run
+//! use ba2::{fo4, prelude::*, tree::Tree};
+//! use std::path::Path;
+//!
+//! fn example() -> Option<()> {
+//!     let (archive, _) = fo4::Archive::read(Path::new("Fallout4 - Meshes.ba2")).ok()?;
+//!     let tree = Tree::from(&archive);
+//!
+//!     let meshes = tree.get_directory("meshes/actors")?;
+//!     for (name, _) in meshes.directories() {
+//!         println!("{name}");
+//!     }
+//!     println!("{} bytes", meshes.size().decompressed_len());
+//!
+//!     for (path, node) in tree.walk() {
+//!         if let Some(file) = node.as_file() {
+//!             println!("{path}: {} bytes", file.size().len());
+//!         }
+//!     }
+//!     Some(())
+//! }
+//! ```
+//!
+//! Paths are normalized before they are split, so `Meshes/Foo.nif` and `meshes\foo.nif` are placed in the same directory, and every name in the tree is lowercase. Files which were read without their names (e.g. [`fo4`] archives without a string table) can not be placed by path, so they are instead collected under a synthetic directory named [`UNNAMED`], beneath the root, and are named by their hash.
+
+use crate::{fo4, hashing, tes3, QueryEntry};
+use bstr::{BStr, BString, ByteSlice as _};
+use std::collections::BTreeMap;
+
+/// The name of the directory which holds every file that was read without a name.
+///
+/// Angle brackets can not appear in a path on Windows, so this name never collides with a real directory.
+pub const UNNAMED: &str = "<unnamed>";
+
+/// The aggregate size of a file, or of every file within a directory.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Size {
+    files: usize,
+    len: usize,
+    decompressed_len: usize,
+}
+
+impl Size {
+    #[must_use]
+    fn new(entry: &QueryEntry) -> Self {
+        Self {
+            files: 1,
+            len: entry.len(),
+            decompressed_len: entry.decompressed_len().unwrap_or(entry.len()),
+        }
+    }
+
+    fn add(&mut self, other: Self) {
+        self.files += other.files;
+        self.len += other.len;
+        self.decompressed_len += other.decompressed_len;
+    }
+
+    /// The length of the data once it has been decompressed. Decompressed data contributes its length as is.
+    #[must_use]
+    pub fn decompressed_len(&self) -> usize {
+        self.decompressed_len
+    }
+
+    /// The number of files which contribute to the size.
+    #[must_use]
+    pub fn files(&self) -> usize {
+        self.files
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.files == 0
+    }
+
+    /// The length of the data, as it is stored in the archive.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// A file within the tree, which refers back to its key in the archive.
+#[derive(Debug)]
+pub struct File<'archive, K> {
+    key: &'archive K,
+    size: Size,
+}
+
+impl<'archive, K> File<'archive, K> {
+    /// The key of the file, which can be used to get the file from the archive.
+    #[must_use]
+    pub fn key(&self) -> &'archive K {
+        self.key
+    }
+
+    #[must_use]
+    pub fn size(&self) -> Size {
+        self.size
+    }
+}
+
+/// A directory within the tree.
+#[derive(Debug)]
+pub struct Directory<'archive, K> {
+    directories: BTreeMap<BString, Directory<'archive, K>>,
+    files: BTreeMap<BString, File<'archive, K>>,
+    size: Size,
+}
+
+impl<K> Default for Directory<'_, K> {
+    fn default() -> Self {
+        Self {
+            directories: BTreeMap::new(),
+            files: BTreeMap::new(),
+            size: Size::default(),
+        }
+    }
+}
+
+impl<'archive, K> Directory<'archive, K> {
+    /// Iterates over the immediate children of the directory, with subdirectories listed before files.
+    pub fn children(&self) -> impl Iterator<Item = (&BStr, Node<'_, 'archive, K>)> {
+        let directories = self
+            .directories
+            .iter()
+            .map(|(name, directory)| (name.as_bstr(), Node::Directory(directory)));
+        let files = self
+            .files
+            .iter()
+            .map(|(name, file)| (name.as_bstr(), Node::File(file)));
+        directories.chain(files)
+    }
+
+    /// Iterates over the immediate subdirectories of the directory.
+    pub fn directories(&self) -> impl Iterator<Item = (&BStr, &Directory<'archive, K>)> {
+        self.directories
+            .iter()
+            .map(|(name, directory)| (name.as_bstr(), directory))
+    }
+
+    /// Iterates over the files which are immediately within the directory.
+    pub fn files(&self) -> impl Iterator<Item = (&BStr, &File<'archive, K>)> {
+        self.files.iter().map(|(name, file)| (name.as_bstr(), file))
+    }
+
+    /// Gets an immediate child of the directory by name. Subdirectories take precedence over files.
+    #[must_use]
+    pub fn get<N>(&self, name: &N) -> Option<Node<'_, 'archive, K>>
+    where
+        N: ?Sized + AsRef<[u8]>,
+    {
+        let name = name.as_ref().as_bstr();
+        self.directories
+            .get(name)
+            .map(Node::Directory)
+            .or_else(|| self.files.get(name).map(Node::File))
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.directories.is_empty() && self.files.is_empty()
+    }
+
+    /// The number of immediate children of the directory.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.directories.len() + self.files.len()
+    }
+
+    /// The aggregate size of every file within the directory, including those within subdirectories.
+    #[must_use]
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    fn insert(&mut self, path: &[u8], file: File<'archive, K>) {
+        self.size.add(file.size);
+        match path.find_byte(b'\\') {
+            Some(pos) => self
+                .directories
+                .entry(path[..pos].into())
+                .or_default()
+                .insert(&path[pos + 1..], file),
+            None => {
+                self.files.insert(path.into(), file);
+            }
+        }
+    }
+}
+
+/// Either a directory, or a file, within the tree.
+#[derive(Debug)]
+pub enum Node<'tree, 'archive, K> {
+    Directory(&'tree Directory<'archive, K>),
+    File(&'tree File<'archive, K>),
+}
+
+impl<K> Clone for Node<'_, '_, K> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K> Copy for Node<'_, '_, K> {}
+
+impl<'tree, 'archive, K> Node<'tree, 'archive, K> {
+    #[must_use]
+    pub fn as_directory(self) -> Option<&'tree Directory<'archive, K>> {
+        match self {
+            Self::Directory(directory) => Some(directory),
+            Self::File(_) => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_file(self) -> Option<&'tree File<'archive, K>> {
+        match self {
+            Self::Directory(_) => None,
+            Self::File(file) => Some(file),
+        }
+    }
+
+    #[must_use]
+    pub fn size(self) -> Size {
+        match self {
+            Self::Directory(directory) => directory.size(),
+            Self::File(file) => file.size(),
+        }
+    }
+}
+
+/// The directory hierarchy of an archive, rebuilt from the paths of its files.
+///
+/// A tree borrows the keys of the archive it was built from, and does not observe any changes made to the archive afterwards.
+#[derive(Debug)]
+pub struct Tree<'archive, K> {
+    root: Directory<'archive, K>,
+}
+
+impl<'archive, K> Tree<'archive, K> {
+    /// Gets a directory, or a file, using its path. An empty path, or `.`, refers to the root.
+    #[must_use]
+    pub fn get<P>(&self, path: &P) -> Option<Node<'_, 'archive, K>>
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        let mut path = BString::from(path.as_ref());
+        hashing::normalize_case_and_separators(&mut path);
+        if path.is_empty() || path == "." {
+            return Some(Node::Directory(&self.root));
+        }
+
+        let (parent, name) = match path.rfind_byte(b'\\') {
+            Some(pos) => (&path[..pos], &path[pos + 1..]),
+            None => (&b""[..], &path[..]),
+        };
+        let mut directory = &self.root;
+        for component in parent.split_str(b"\\").filter(|x| !x.is_empty()) {
+            directory = directory.directories.get(component.as_bstr())?;
+        }
+        directory.get(name)
+    }
+
+    /// See also [`get`](Self::get).
+    #[must_use]
+    pub fn get_directory<P>(&self, path: &P) -> Option<&Directory<'archive, K>>
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        self.get(path)?.as_directory()
+    }
+
+    /// See also [`get`](Self::get).
+    #[must_use]
+    pub fn get_file<P>(&self, path: &P) -> Option<&File<'archive, K>>
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        self.get(path)?.as_file()
+    }
+
+    #[must_use]
+    pub fn root(&self) -> &Directory<'archive, K> {
+        &self.root
+    }
+
+    /// Walks every directory and file in the tree, depth first, alongside its full path.
+    ///
+    /// Each directory is visited before its children, and the root itself is not visited.
+    pub fn walk(&self) -> impl Iterator<Item = (BString, Node<'_, 'archive, K>)> {
+        let mut stack = Vec::new();
+        Self::push_children(&mut stack, b"", &self.root);
+        core::iter::from_fn(move || {
+            let (path, node) = stack.pop()?;
+            if let Node::Directory(directory) = node {
+                Self::push_children(&mut stack, &path, directory);
+            }
+            Some((path, node))
+        })
+    }
+
+    fn build<I, F>(entries: I, unnamed: F) -> Self
+    where
+        I: IntoIterator<Item = (&'archive K, QueryEntry)>,
+        F: Fn(&K) -> Option<BString>,
+    {
+        let mut root = Directory::default();
+        for (key, entry) in entries {
+            let file = File {
+                key,
+                size: Size::new(&entry),
+            };
+            match unnamed(key) {
+                Some(name) => {
+                    let mut path = BString::from(UNNAMED);
+                    path.push(b'\\');
+                    path.extend_from_slice(&name);
+                    root.insert(&path, file);
+                }
+                None => root.insert(entry.path(), file),
+            }
+        }
+        Self { root }
+    }
+
+    fn push_children<'tree>(
+        stack: &mut Vec<(BString, Node<'tree, 'archive, K>)>,
+        prefix: &[u8],
+        directory: &'tree Directory<'archive, K>,
+    ) {
+        let children: Vec<_> = directory.children().collect();
+        stack.extend(children.into_iter().rev().map(|(name, node)| {
+            let mut path = BString::from(prefix);
+            if !path.is_empty() {
+                path.push(b'\\');
+            }
+            path.extend_from_slice(name);
+            (path, node)
+        }));
+    }
+}
+
+impl<'archive, 'bytes> From<&'archive tes3::Archive<'bytes>>
+    for Tree<'archive, tes3::ArchiveKey<'bytes>>
+{
+    fn from(value: &'archive tes3::Archive<'bytes>) -> Self {
+        Self::build(
+            value
+                .iter()
+                .map(|(key, file)| (key, tes3::Archive::query_entry(key, file))),
+            |key| {
+                key.name()
+                    .is_empty()
+                    .then(|| format!("{:016x}", key.hash().numeric()).into())
+            },
+        )
+    }
+}
+
+impl<'archive, 'bytes> From<&'archive fo4::Archive<'bytes>>
+    for Tree<'archive, fo4::ArchiveKey<'bytes>>
+{
+    fn from(value: &'archive fo4::Archive<'bytes>) -> Self {
+        Self::build(
+            value
+                .iter()
+                .map(|(key, file)| (key, fo4::Archive::query_entry(key, file))),
+            |key| {
+                key.name().is_empty().then(|| {
+                    let hash = key.hash();
+                    format!(
+                        "{:08x}{:08x}{:08x}",
+                        hash.directory, hash.file, hash.extension
+                    )
+                    .into()
+                })
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        containers::Bytes,
+        fo4::{self, Chunk, ChunkCompressionOptions},
+        prelude::*,
+        tes3,
+        tree::{Tree, UNNAMED},
+    };
+    use bstr::BString;
+
+    #[test]
+    fn tes3_hierarchy() {
+        let archive: tes3::Archive = [
+            ("meshes/clutter/bucket.nif", 3),
+            ("Meshes\\Clutter\\Broom.nif", 5),
+            ("meshes/rock.nif", 7),
+            ("readme.txt", 11),
+        ]
+        .into_iter()
+        .map(|(path, len)| {
+            let key = tes3::ArchiveKey::from(path);
+            (key, tes3::File::from(&[0u8; 16][..len]))
+        })
+        .collect();
+        let tree = Tree::from(&archive);
+
+        let root = tree.root();
+        assert_eq!(root.len(), 2);
+        assert_eq!(root.size().files(), 4);
+        assert_eq!(root.size().len(), 26);
+        assert_eq!(root.size().decompressed_len(), 26);
+
+        let meshes = tree.get_directory("MESHES").unwrap();
+        assert_eq!(meshes.size().len(), 15);
+        let names: Vec<_> = meshes.children().map(|(name, _)| name).collect();
+        assert_eq!(names, ["clutter", "rock.nif"]);
+
+        let clutter = tree.get_directory("meshes/clutter/").unwrap();
+        let names: Vec<_> = clutter.files().map(|(name, _)| name).collect();
+        assert_eq!(names, ["broom.nif", "bucket.nif"]);
+        let broom = tree.get_file("meshes\\clutter\\broom.nif").unwrap();
+        assert_eq!(broom.size().len(), 5);
+        assert_eq!(broom.key().name(), "meshes\\clutter\\broom.nif");
+
+        assert!(tree.get("meshes/missing").is_none());
+        assert!(tree.get_file("meshes").is_none());
+        assert!(tree.get_directory(".").is_some());
+
+        let paths: Vec<_> = tree.walk().map(|(path, _)| path).collect();
+        assert_eq!(
+            paths,
+            [
+                "meshes",
+                "meshes\\clutter",
+                "meshes\\clutter\\broom.nif",
+                "meshes\\clutter\\bucket.nif",
+                "meshes\\rock.nif",
+                "readme.txt",
+            ]
+        );
+    }
+
+    #[test]
+    fn long_paths() {
+        let directory = format!("Meshes/{}", "Clutter/".repeat(40));
+        let archive: tes3::Archive = ["Bucket.nif", "Broom.nif"]
+            .into_iter()
+            .map(|name| {
+                // names are normalized when keys are constructed, so emulate a name read from disk
+                let mut key = tes3::ArchiveKey::from(name);
+                key.name = Bytes::from_owned(format!("{directory}{name}").into_bytes().into());
+                (key, tes3::File::from(&b"data"[..]))
+            })
+            .collect();
+        let tree = Tree::from(&archive);
+
+        assert_eq!(tree.root().size().files(), 2);
+        assert!(tree.root().get(".").is_none());
+        assert!(tree
+            .get_directory(".")
+            .is_some_and(|x| x.size().files() == 2));
+        for name in ["bucket.nif", "BROOM.NIF"] {
+            let path = format!("{directory}{name}");
+            assert!(path.len() >= 260);
+            assert!(tree.get_file(&path).is_some(), "{name}");
+        }
+        let clutter = tree.get_directory(&directory.replace('/', "\\")).unwrap();
+        assert_eq!(clutter.size().files(), 2);
+    }
+
+    #[test]
+    fn fo4_sizes_and_unnamed() -> anyhow::Result<()> {
+        let options = ChunkCompressionOptions::default();
+        let payload = [b'a'; 0x100];
+        let compressed = Chunk::from_decompressed(&payload[..]).compress(&options)?;
+        let decompressed = Chunk::from_decompressed(&payload[..]);
+
+        let mut archive = fo4::Archive::new();
+        let file: fo4::File = [compressed.clone(), decompressed].into_iter().collect();
+        archive.insert(fo4::ArchiveKey::from(b"textures/sky.dds"), file);
+        let file: fo4::File = [compressed.clone()].into_iter().collect();
+        archive.insert(fo4::ArchiveKey::from(b"textures/sky_n.dds"), file);
+        let (hash, _) = fo4::hash_file(b"textures/hidden.dds".into());
+        let file: fo4::File = [compressed.clone()].into_iter().collect();
+        archive.insert(fo4::ArchiveKey::from(hash), file);
+
+        let tree = Tree::from(&archive);
+        let textures = tree.get_directory("textures").unwrap();
+        assert_eq!(textures.size().files(), 2);
+        assert_eq!(textures.size().len(), compressed.len() * 2 + payload.len());
+        assert_eq!(textures.size().decompressed_len(), payload.len() * 3);
+
+        let unnamed = tree.get_directory(UNNAMED).unwrap();
+        assert_eq!(unnamed.len(), 1);
+        let (name, file) = unnamed.files().next().unwrap();
+        assert_eq!(
+            BString::from(name),
+            format!(
+                "{:08x}{:08x}{:08x}",
+                hash.directory, hash.file, hash.extension
+            )
+        );
+        assert_eq!(file.key().hash(), &hash);
+        assert_eq!(tree.root().size().files(), 3);
+        Ok(())
+    }
+}
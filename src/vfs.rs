@@ -0,0 +1,573 @@
+//! A virtual filesystem which overlays any number of archives and loose directories.
+//!
+//! The game resolves a path by consulting every data source it knows about, and then picking the one with the highest precedence. [`Vfs`] models this by assigning every [`Layer`] an explicit priority: the layer with the greatest priority wins, and ties are broken in favor of the layer which was added last (i.e. load order).
+//!
+//! ```rust,no_run
+//! use ba2::{
+//!     prelude::*,
+//!     tes4,
+//!     vfs::{Loose, Vfs},
+//! };
+//! use std::path::Path;
+//!
+//! fn example() -> Option<()> {
+//!     let (archive, _) = tes4::Archive::read(Path::new("Skyrim - Misc.bsa")).ok()?;
+//!     let loose = Loose::read(Path::new("Data")).ok()?;
+//!
+//!     let mut vfs = Vfs::new();
+//!     vfs.push("Skyrim - Misc.bsa", 0, archive);
+//!     vfs.push("Data", 1, loose);
+//!
+//!     let winner = vfs.resolve("interface/exported/widgets/skyui/followerpanel.swf")?;
+//!     println!("{}", winner.layer.name());
+//!     for conflict in vfs.conflicts() {
+//!         println!("{} is provided by {} sources", conflict.path, conflict.losers.len() + 1);
+//!     }
+//!     Some(())
+//! }
+//! ```
+//!
+//! All paths are compared after normalization, so `Textures/Foo.dds` and `textures\foo.dds` refer to the same file. Archive entries which were read without their names (e.g. [`fo4`] archives without a string table) can still be resolved by path, but are excluded from reports and listings, since there is no name to report.
+
+#[cfg(feature = "encoding")]
+use crate::encoding::Encoding;
+use crate::{fo4, hashing, tes3, tes4};
+use bstr::{BStr, BString, ByteSlice as _};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+fn normalize<P>(path: &P) -> BString
+where
+    P: ?Sized + AsRef<[u8]>,
+{
+    let mut path = BString::from(path.as_ref());
+    hashing::normalize_path(&mut path);
+    path
+}
+
+/// A directory of loose files on disk.
+///
+/// The directory is indexed once, when it is read. Changes made to the directory afterwards will not be observed.
+#[derive(Clone, Debug, Default)]
+pub struct Loose {
+    root: PathBuf,
+    files: BTreeMap<BString, PathBuf>,
+}
+
+impl Loose {
+    /// Recursively indexes every file under `root`.
+    pub fn read(root: &Path) -> io::Result<Self> {
+        Self::read_with(root, |path| Ok(path.to_string_lossy().as_bytes().into()))
+    }
+
+    /// Recursively indexes every file under `root`, encoding their paths using the given encoding.
+    ///
+    /// Paths on disk are unicode, while the names within an archive are not, so this is needed for loose files to be matched against archives which were written using a legacy code page.
+    #[cfg(feature = "encoding")]
+    pub fn read_with_encoding(root: &Path, encoding: Encoding) -> io::Result<Self> {
+        Self::read_with(root, |path| {
+            encoding
+                .encode_path(path)
+                .map_err(|x| io::Error::new(io::ErrorKind::InvalidData, x))
+        })
+    }
+
+    fn read_with<F>(root: &Path, mut encode: F) -> io::Result<Self>
+    where
+        F: FnMut(&Path) -> io::Result<BString>,
+    {
+        let mut files = BTreeMap::new();
+        let mut pending = vec![root.to_path_buf()];
+        while let Some(directory) = pending.pop() {
+            for entry in fs::read_dir(&directory)? {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_type()?.is_dir() {
+                    pending.push(path);
+                } else if let Ok(relative) = path.strip_prefix(root) {
+                    let name = normalize(&encode(relative)?);
+                    files.insert(name, path);
+                }
+            }
+        }
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            files,
+        })
+    }
+
+    /// Gets the path on disk of the file at the given virtual path.
+    #[must_use]
+    pub fn get<P>(&self, path: &P) -> Option<&Path>
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        self.files.get(&normalize(path)).map(PathBuf::as_path)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// The directory this index was built from.
+    #[must_use]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+/// The data backing a [`Layer`].
+#[derive(Clone, Debug)]
+pub enum Source<'bytes> {
+    TES3(tes3::Archive<'bytes>),
+    TES4(tes4::Archive<'bytes>),
+    FO4(fo4::Archive<'bytes>),
+    Loose(Loose),
+}
+
+impl<'bytes> Source<'bytes> {
+    fn get<'this>(&'this self, path: &BStr) -> Option<Entry<'this, 'bytes>> {
+        match self {
+            Self::TES3(archive) => archive.get(&tes3::hash_file(path).0).map(Entry::TES3),
+            Self::TES4(archive) => archive.get_file(path).map(Entry::TES4),
+            Self::FO4(archive) => archive.get(&fo4::hash_file(path).0).map(Entry::FO4),
+            Self::Loose(loose) => loose.files.get(path).map(|x| Entry::Loose(x)),
+        }
+    }
+
+    fn paths(&self) -> Vec<BString> {
+        match self {
+            Self::TES3(archive) => archive
+                .keys()
+                .filter(|x| !x.name().is_empty())
+                .map(|x| normalize(x.name()))
+                .collect(),
+            Self::TES4(archive) => archive
+                .iter()
+                .filter(|(directory, _)| !directory.name().is_empty())
+                .flat_map(|(directory_key, directory)| {
+                    directory
+                        .keys()
+                        .filter(|x| !x.name().is_empty())
+                        .map(move |file_key| {
+                            let mut path = normalize(directory_key.name());
+                            if path == "." {
+                                path.clear();
+                            } else {
+                                path.push(b'\\');
+                            }
+                            path.extend_from_slice(file_key.name());
+                            normalize(&path)
+                        })
+                })
+                .collect(),
+            Self::FO4(archive) => archive
+                .keys()
+                .filter(|x| !x.name().is_empty())
+                .map(|x| normalize(x.name()))
+                .collect(),
+            Self::Loose(loose) => loose.files.keys().cloned().collect(),
+        }
+    }
+}
+
+impl<'bytes> From<tes3::Archive<'bytes>> for Source<'bytes> {
+    fn from(value: tes3::Archive<'bytes>) -> Self {
+        Self::TES3(value)
+    }
+}
+
+impl<'bytes> From<tes4::Archive<'bytes>> for Source<'bytes> {
+    fn from(value: tes4::Archive<'bytes>) -> Self {
+        Self::TES4(value)
+    }
+}
+
+impl<'bytes> From<fo4::Archive<'bytes>> for Source<'bytes> {
+    fn from(value: fo4::Archive<'bytes>) -> Self {
+        Self::FO4(value)
+    }
+}
+
+impl From<Loose> for Source<'_> {
+    fn from(value: Loose) -> Self {
+        Self::Loose(value)
+    }
+}
+
+/// A named data source, with an explicit priority.
+#[derive(Clone, Debug)]
+pub struct Layer<'bytes> {
+    name: String,
+    priority: i32,
+    source: Source<'bytes>,
+}
+
+impl<'bytes> Layer<'bytes> {
+    /// The name given to this layer, for reporting purposes.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[must_use]
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    #[must_use]
+    pub fn source(&self) -> &Source<'bytes> {
+        &self.source
+    }
+}
+
+/// A file, as it exists within a specific [`Layer`].
+#[derive(Clone, Copy, Debug)]
+pub enum Entry<'vfs, 'bytes> {
+    TES3(&'vfs tes3::File<'bytes>),
+    TES4(&'vfs tes4::File<'bytes>),
+    FO4(&'vfs fo4::File<'bytes>),
+    /// The path to the file on disk.
+    Loose(&'vfs Path),
+}
+
+/// A layer which provides a given path.
+#[derive(Clone, Copy, Debug)]
+pub struct Provider<'vfs, 'bytes> {
+    /// The index of the layer, in the order it was added.
+    pub index: usize,
+    pub layer: &'vfs Layer<'bytes>,
+    pub entry: Entry<'vfs, 'bytes>,
+}
+
+/// A path which is provided by more than one layer.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Conflict {
+    /// The normalized path.
+    pub path: BString,
+    /// The index of the layer which wins.
+    pub winner: usize,
+    /// The indices of the layers which are overridden, from highest to lowest precedence.
+    pub losers: Vec<usize>,
+}
+
+/// An entry within a virtual directory.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum DirectoryEntry {
+    Directory(BString),
+    File(BString),
+}
+
+/// Layers archives and loose directories on top of one another, in the same manner as the game.
+#[derive(Clone, Debug, Default)]
+pub struct Vfs<'bytes> {
+    layers: Vec<Layer<'bytes>>,
+}
+
+impl<'bytes> Vfs<'bytes> {
+    /// Lists every path provided by more than one layer.
+    #[must_use]
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        self.index()
+            .into_iter()
+            .filter_map(|(path, providers)| match providers.split_first() {
+                Some((&winner, losers)) if !losers.is_empty() => Some(Conflict {
+                    path,
+                    winner,
+                    losers: losers.to_vec(),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// Every layer, in the order they were added.
+    #[must_use]
+    pub fn layers(&self) -> &[Layer<'bytes>] {
+        &self.layers
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Lists the immediate children of the given virtual directory.
+    ///
+    /// Use an empty path, or `.`, to list the root.
+    #[must_use]
+    pub fn list_directory<P>(&self, path: &P) -> Vec<DirectoryEntry>
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        let mut prefix = normalize(path);
+        if prefix == "." {
+            prefix.clear();
+        } else {
+            prefix.push(b'\\');
+        }
+
+        let mut entries = BTreeSet::new();
+        for layer in &self.layers {
+            for file in layer.source.paths() {
+                if let Some(rest) = file.strip_prefix(prefix.as_slice()) {
+                    let entry = match rest.find_byte(b'\\') {
+                        Some(pos) => DirectoryEntry::Directory(rest[..pos].into()),
+                        None => DirectoryEntry::File(rest.into()),
+                    };
+                    entries.insert(entry);
+                }
+            }
+        }
+
+        entries.into_iter().collect()
+    }
+
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lists the paths provided by the given layer, which are overridden by another layer, paired with the index of the winner.
+    #[must_use]
+    pub fn overridden(&self, index: usize) -> Vec<(BString, usize)> {
+        self.index()
+            .into_iter()
+            .filter_map(|(path, providers)| match providers.first() {
+                Some(&winner) if winner != index && providers.contains(&index) => {
+                    Some((path, winner))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Lists the paths provided by the given layer, which override at least one other layer.
+    #[must_use]
+    pub fn overrides(&self, index: usize) -> Vec<BString> {
+        self.index()
+            .into_iter()
+            .filter_map(|(path, providers)| {
+                (providers.len() > 1 && providers.first() == Some(&index)).then_some(path)
+            })
+            .collect()
+    }
+
+    /// Lists every layer which provides the given path, from highest to lowest precedence.
+    #[must_use]
+    pub fn providers<P>(&self, path: &P) -> Vec<Provider<'_, 'bytes>>
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        let path = normalize(path);
+        self.precedence()
+            .into_iter()
+            .filter_map(|index| {
+                let layer = &self.layers[index];
+                layer.source.get(path.as_bstr()).map(|entry| Provider {
+                    index,
+                    layer,
+                    entry,
+                })
+            })
+            .collect()
+    }
+
+    /// Adds a new layer, returning its index.
+    ///
+    /// Layers with a greater `priority` take precedence. Layers with equal priority are resolved in load order, i.e. the layer added last wins.
+    pub fn push<S>(&mut self, name: impl Into<String>, priority: i32, source: S) -> usize
+    where
+        S: Into<Source<'bytes>>,
+    {
+        self.layers.push(Layer {
+            name: name.into(),
+            priority,
+            source: source.into(),
+        });
+        self.layers.len() - 1
+    }
+
+    /// Finds the layer which wins for the given path.
+    #[must_use]
+    pub fn resolve<P>(&self, path: &P) -> Option<Provider<'_, 'bytes>>
+    where
+        P: ?Sized + AsRef<[u8]>,
+    {
+        let path = normalize(path);
+        self.precedence().into_iter().find_map(|index| {
+            let layer = &self.layers[index];
+            layer.source.get(path.as_bstr()).map(|entry| Provider {
+                index,
+                layer,
+                entry,
+            })
+        })
+    }
+
+    /// Maps every named path to the layers which provide it, from highest to lowest precedence.
+    fn index(&self) -> BTreeMap<BString, Vec<usize>> {
+        let mut index: BTreeMap<BString, Vec<usize>> = BTreeMap::new();
+        for layer in self.precedence() {
+            for path in self.layers[layer].source.paths() {
+                let providers = index.entry(path).or_default();
+                if !providers.contains(&layer) {
+                    providers.push(layer);
+                }
+            }
+        }
+        index
+    }
+
+    /// Layer indices, from highest to lowest precedence.
+    fn precedence(&self) -> Vec<usize> {
+        let mut order: Vec<_> = (0..self.layers.len()).collect();
+        order.sort_by_key(|&x| core::cmp::Reverse((self.layers[x].priority, x)));
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        fo4,
+        prelude::*,
+        tes3, tes4,
+        vfs::{DirectoryEntry, Entry, Loose, Vfs},
+    };
+    use anyhow::Context as _;
+    use std::path::Path;
+
+    fn load() -> anyhow::Result<Vfs<'static>> {
+        let root = Path::new("data/common_guess_test");
+        let tes3 = tes3::Archive::read(root.join("tes3.bsa").as_path())
+            .context("failed to read tes3 archive")?;
+        let (tes4, _) = tes4::Archive::read(root.join("tes4.bsa").as_path())
+            .context("failed to read tes4 archive")?;
+        let (fo4, _) = fo4::Archive::read(root.join("fo4.ba2").as_path())
+            .context("failed to read fo4 archive")?;
+        let loose = Loose::read(&root.join("data")).context("failed to read loose files")?;
+
+        let mut vfs = Vfs::new();
+        vfs.push("tes3.bsa", 0, tes3);
+        vfs.push("tes4.bsa", 0, tes4);
+        vfs.push("data", 1, loose);
+        vfs.push("fo4.ba2", 0, fo4);
+        Ok(vfs)
+    }
+
+    #[test]
+    fn resolution_follows_priority() -> anyhow::Result<()> {
+        let vfs = load()?;
+        assert_eq!(vfs.len(), 4);
+
+        let winner = vfs
+            .resolve("MISC/Example.txt")
+            .context("failed to resolve path")?;
+        assert_eq!(winner.index, 2);
+        assert_eq!(winner.layer.name(), "data");
+        assert!(matches!(winner.entry, Entry::Loose(_)));
+
+        let providers: Vec<_> = vfs
+            .providers("misc/example.txt")
+            .into_iter()
+            .map(|x| x.index)
+            .collect();
+        assert_eq!(providers, [2, 3, 1, 0]);
+
+        assert!(vfs.resolve("misc/missing.txt").is_none());
+        assert!(vfs.providers("misc/missing.txt").is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn ties_are_broken_by_load_order() -> anyhow::Result<()> {
+        let mut vfs = load()?;
+        vfs.layers.remove(2);
+        let winner = vfs
+            .resolve("misc\\example.txt")
+            .context("failed to resolve path")?;
+        assert_eq!(winner.layer.name(), "fo4.ba2");
+        assert!(matches!(winner.entry, Entry::FO4(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn conflict_reports() -> anyhow::Result<()> {
+        let vfs = load()?;
+
+        let conflicts = vfs.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "misc\\example.txt");
+        assert_eq!(conflicts[0].winner, 2);
+        assert_eq!(conflicts[0].losers, [3, 1, 0]);
+
+        assert_eq!(vfs.overrides(2), ["misc\\example.txt"]);
+        assert!(vfs.overrides(0).is_empty());
+        assert!(vfs.overridden(2).is_empty());
+        assert_eq!(vfs.overridden(0), [("misc\\example.txt".into(), 2)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn directory_listing() -> anyhow::Result<()> {
+        let vfs = load()?;
+        assert_eq!(
+            vfs.list_directory(""),
+            [DirectoryEntry::Directory("misc".into())]
+        );
+        assert_eq!(
+            vfs.list_directory("Misc/"),
+            [DirectoryEntry::File("example.txt".into())]
+        );
+        assert!(vfs.list_directory("textures").is_empty());
+        Ok(())
+    }
+
+    #[cfg(feature = "encoding")]
+    #[test]
+    fn loose_files_with_encoding() -> anyhow::Result<()> {
+        use crate::encoding::{CodePage, Encoding, Mode};
+        use std::fs;
+
+        let root = std::env::temp_dir().join("ba2_vfs_loose_files_with_encoding");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("textures"))?;
+        fs::write(root.join("textures").join("ściana.dds"), b"loose")?;
+
+        let encoding = Encoding::new(CodePage::Windows1250, Mode::Strict);
+        let path = encoding.encode("textures\\ściana.dds")?;
+        let mut archive = tes4::Archive::new();
+        archive.insert_file(&*path, tes4::File::from_decompressed(b"packed"));
+
+        let mut vfs = Vfs::new();
+        vfs.push("archive.bsa", 0, archive);
+        vfs.push("loose", 1, Loose::read_with_encoding(&root, encoding)?);
+        assert_eq!(vfs.providers(&*path).len(), 2);
+        assert_eq!(vfs.conflicts()[0].path, path.as_ref());
+
+        // unicode paths do not match names written using a legacy code page
+        vfs.push("unicode", 2, Loose::read(&root)?);
+        assert_eq!(vfs.providers(&*path).len(), 2);
+
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+}